@@ -61,7 +61,8 @@ in
       --daemon-rpc-addr 127.0.0.1:18443 \
       --cookie $(cat $COOKIE_PATH) \
       --db-dir ./electrs-db \
-      --http-addr 0.0.0.0:3000 -vvv
+      --http-addr 0.0.0.0:3000 \
+      --electrum-rpc-addr 127.0.0.1:60401 -vvv
   '';
 
   processes.esplora-ui.exec = ''
@@ -0,0 +1,188 @@
+//! Carry-over balances for miners whose cut of a block is too small to be
+//! worth a tree leaf. Their amount goes into a pool-held output instead and
+//! accumulates here until a later block pays it out with their share.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use bitcoin::{ScriptBuf, Txid};
+use serde::{Deserialize, Serialize};
+
+use crate::ctv::is_anchor;
+use crate::error::Error;
+use crate::shares::{ManifestEntry, PayoutManifest};
+use crate::tree::TreeNode;
+
+/// Where the share scripts keep outstanding balances and block history.
+pub const BALANCE_STORE: &str = "./data/balances.json";
+
+/// What one mined block did to the balances.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockRecord {
+    pub coinbase_txid: String,
+    /// Value split between miners and pool, after tree fees.
+    pub reward: u64,
+    /// Sum of every miner payout in the tree.
+    pub paid: u64,
+    /// Hex script of the pool output and its value.
+    pub pool_script: String,
+    pub pool_value: u64,
+    /// Part of `pool_value` the payout scheme did not owe miners.
+    pub retained: u64,
+    /// New amounts carried over to later blocks.
+    pub carried: u64,
+    /// Earlier balances included in `paid`.
+    pub released: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BalanceStore {
+    pub balances: BTreeMap<String, u64>,
+    pub blocks: Vec<BlockRecord>,
+}
+
+/// How a block's manifest is paid once balances are taken into account.
+#[derive(Clone, Debug)]
+pub struct CarryPlan {
+    pub reward: u64,
+    /// Miners paid in this block, balances included.
+    pub payouts: PayoutManifest,
+    /// Everything else: retained reward plus new carries minus releases.
+    pub pool_value: u64,
+    pub retained: u64,
+    pub carried: u64,
+    pub released: u64,
+    pub balances_after: BTreeMap<String, u64>,
+}
+
+impl BalanceStore {
+    /// Loads the store, or an empty one if none was saved yet.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        if !path.exists() {
+            return Ok(BalanceStore::default());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn outstanding(&self) -> u64 {
+        self.balances.values().sum()
+    }
+
+    /// Pays every miner whose share plus balance reaches `threshold` and
+    /// carries the rest. A coinbase can only pay out what it mined, so a
+    /// balance is only released while the block's carries and retained
+    /// reward cover it; otherwise the miner gets this block's share alone
+    /// (if that clears the threshold) and keeps the balance.
+    pub fn plan(&self, manifest: &PayoutManifest, threshold: u64) -> CarryPlan {
+        let reward = manifest.total() + manifest.retained;
+        let mut balances = self.balances.clone();
+
+        // Carries fund releases, so settle them first
+        let below: Vec<bool> = manifest
+            .entries
+            .iter()
+            .map(|entry| entry.value + self.balances.get(&entry.address).copied().unwrap_or(0) < threshold)
+            .collect();
+        let mut budget = manifest.retained;
+        let mut carried = 0;
+        for (entry, _) in manifest.entries.iter().zip(&below).filter(|(_, below)| **below) {
+            *balances.entry(entry.address.clone()).or_default() += entry.value;
+            budget += entry.value;
+            carried += entry.value;
+        }
+
+        let mut payouts = vec![];
+        let mut released = 0;
+        for (entry, _) in manifest.entries.iter().zip(&below).filter(|(_, below)| !**below) {
+            let balance = balances.get(&entry.address).copied().unwrap_or(0);
+            if balance <= budget {
+                budget -= balance;
+                released += balance;
+                balances.remove(&entry.address);
+                payouts.push(ManifestEntry { value: entry.value + balance, ..entry.clone() });
+            } else if entry.value >= threshold {
+                payouts.push(entry.clone());
+            } else {
+                *balances.entry(entry.address.clone()).or_default() += entry.value;
+                budget += entry.value;
+                carried += entry.value;
+            }
+        }
+
+        let paid: u64 = payouts.iter().map(|e| e.value).sum();
+        CarryPlan {
+            reward,
+            payouts: PayoutManifest { entries: payouts, retained: 0 },
+            pool_value: reward - paid,
+            retained: manifest.retained,
+            carried,
+            released,
+            balances_after: balances,
+        }
+    }
+
+    /// Records a mined block. Call only once the coinbase paying `plan` is
+    /// in the chain, so the balances never run ahead of what was mined.
+    pub fn commit(&mut self, plan: CarryPlan, coinbase_txid: Txid, pool_script: &ScriptBuf) {
+        self.blocks.push(BlockRecord {
+            coinbase_txid: coinbase_txid.to_string(),
+            reward: plan.reward,
+            paid: plan.payouts.total(),
+            pool_script: pool_script.to_hex_string(),
+            pool_value: plan.pool_value,
+            retained: plan.retained,
+            carried: plan.carried,
+            released: plan.released,
+        });
+        self.balances = plan.balances_after;
+    }
+
+    /// Checks the books: every block splits its reward exactly, and the
+    /// balances still owed equal everything carried minus everything released.
+    pub fn reconcile(&self) -> Result<(), Error> {
+        for block in &self.blocks {
+            if block.paid + block.pool_value != block.reward {
+                return Err(Error::Payout(format!("block {} pays {} of {} sat", block.coinbase_txid, block.paid + block.pool_value, block.reward)));
+            }
+            if block.pool_value + block.released != block.retained + block.carried {
+                return Err(Error::Payout(format!("pool output of block {} does not match its carries", block.coinbase_txid)));
+            }
+        }
+        let carried: u64 = self.blocks.iter().map(|b| b.carried).sum();
+        let released: u64 = self.blocks.iter().map(|b| b.released).sum();
+        if carried.checked_sub(released) != Some(self.outstanding()) {
+            return Err(Error::Payout(format!(
+                "{} sat outstanding but blocks carried {carried} and released {released}",
+                self.outstanding()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Payout and pool totals a mined tree actually pays, to compare with its record.
+pub fn tree_totals(root: &TreeNode, pool_script: &ScriptBuf) -> (u64, u64) {
+    let mut paid = 0;
+    let mut pool = 0;
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        for (_, output) in node.leaf_outputs() {
+            if output.script_pubkey == *pool_script {
+                pool += output.value.to_sat();
+            } else if !is_anchor(&output.script_pubkey) {
+                paid += output.value.to_sat();
+            }
+        }
+        stack.extend(node.children.iter().map(|(_, child)| child));
+    }
+    (paid, pool)
+}
@@ -0,0 +1,299 @@
+//! Bare CTV payout trees of any size and radix, either balanced or shaped by
+//! payout value, optionally keeping payouts that would cost more to unroll
+//! than they are worth out of the deep levels.
+
+use bitcoin::{Amount, ScriptBuf, TxOut};
+
+use crate::ctv::{build_ctv_script, template_tx};
+use crate::error::Error;
+use crate::exit_cost::leaf_exits;
+use crate::tree::TreeNode;
+
+/// Builds a tree with at most `radix` outputs per node whose inner outputs are
+/// bare `<hash> OP_CTV` scripts. Every node pays `node_fee`, so the root's
+/// `input_value` is what the coinbase output must be worth at minimum. Fails
+/// if any output, payout or inner, would be dust.
+pub fn build_ctv_tree(
+    payouts: &[TxOut],
+    radix: usize,
+    node_fee: u64,
+) -> Result<TreeNode, Error> {
+    if payouts.is_empty() || radix < 2 {
+        return Err(Error::InvalidTemplate("a tree needs at least one payout and a radix of two or more".into()));
+    }
+    if payouts.len() <= radix {
+        return node_paying(payouts.to_vec(), vec![], node_fee);
+    }
+
+    let mut outputs = vec![];
+    let mut children = vec![];
+    for (vout, chunk) in split_evenly(payouts, radix).into_iter().enumerate() {
+        let child = build_ctv_tree(chunk, radix, node_fee)?;
+        outputs.push(TxOut {
+            value: Amount::from_sat(child.input_value),
+            script_pubkey: build_ctv_script(&child.tx.output),
+        });
+        children.push((vout as u32, child));
+    }
+    node_paying(outputs, children, node_fee)
+}
+
+/// How payouts are arranged below the root.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TreeShape {
+    /// Every leaf at the same depth, in payout order.
+    Balanced,
+    /// Radix-ary Huffman tree over payout values. With `large_shallow` the
+    /// biggest payouts get the shortest unroll paths, which minimises the
+    /// value-weighted depth; without it the smallest ones do. Node vsizes
+    /// grow with their output count, so this only approximates the least
+    /// value-weighted unroll vbytes.
+    ValueWeighted { large_shallow: bool },
+}
+
+/// Builds a tree over `payouts` in the given shape.
+pub fn build_shaped_tree(
+    payouts: &[TxOut],
+    radix: usize,
+    node_fee: u64,
+    shape: TreeShape,
+) -> Result<TreeNode, Error> {
+    match shape {
+        TreeShape::Balanced => build_ctv_tree(payouts, radix, node_fee),
+        TreeShape::ValueWeighted { large_shallow } => build_weighted_tree(payouts, radix, node_fee, large_shallow),
+    }
+}
+
+/// Sum over every leaf of its value times the vbytes it takes to unroll.
+pub fn value_weighted_vbytes(root: &TreeNode) -> u128 {
+    leaf_exits(root)
+        .iter()
+        .map(|exit| exit.value as u128 * exit.vbytes as u128)
+        .sum()
+}
+
+enum Pending {
+    Payout(TxOut),
+    Node(TreeNode),
+}
+
+fn build_weighted_tree(
+    payouts: &[TxOut],
+    radix: usize,
+    node_fee: u64,
+    large_shallow: bool,
+) -> Result<TreeNode, Error> {
+    if payouts.is_empty() || radix < 2 {
+        return Err(Error::InvalidTemplate("a tree needs at least one payout and a radix of two or more".into()));
+    }
+
+    // (weight, insertion order, item); the order keeps ties deterministic
+    let mut pending: Vec<(u64, usize, Pending)> = payouts
+        .iter()
+        .enumerate()
+        .map(|(i, o)| (o.value.to_sat(), i, Pending::Payout(o.clone())))
+        .collect();
+    let mut next_id = pending.len();
+
+    // The first merge takes just enough items that every later merge is full
+    let mut group = if pending.len() <= radix { pending.len() } else { (pending.len() - 2) % (radix - 1) + 2 };
+    while pending.len() > 1 || matches!(pending.first(), Some((_, _, Pending::Payout(_)))) {
+        // Items that should sit deepest go to the end and are merged first
+        pending.sort_by_key(|(weight, id, _)| (*weight, *id));
+        if large_shallow {
+            pending.reverse();
+        }
+        let merged: Vec<(u64, usize, Pending)> = pending.split_off(pending.len() - group);
+
+        let weight = merged.iter().fold(0u64, |sum, (w, _, _)| sum.saturating_add(*w));
+        let mut outputs = vec![];
+        let mut children = vec![];
+        for (vout, (_, _, item)) in merged.into_iter().enumerate() {
+            match item {
+                Pending::Payout(output) => outputs.push(output),
+                Pending::Node(child) => {
+                    outputs.push(TxOut {
+                        value: Amount::from_sat(child.input_value),
+                        script_pubkey: build_ctv_script(&child.tx.output),
+                    });
+                    children.push((vout as u32, child));
+                }
+            }
+        }
+        pending.push((weight, next_id, Pending::Node(node_paying(outputs, children, node_fee)?)));
+        next_id += 1;
+        group = radix.min(pending.len());
+    }
+
+    match pending.pop() {
+        Some((_, _, Pending::Node(root))) => Ok(root),
+        _ => Err(Error::InvalidTemplate("weighted tree build left no root".into())),
+    }
+}
+
+/// What to do with payouts that are not worth unrolling.
+#[derive(Clone, Debug)]
+pub enum DustHandling {
+    /// Pay them all into one pool-held output on the root, to be carried
+    /// over to a later payout. The output is left out if their sum is below
+    /// the pool script's dust limit.
+    CarryOver { pool_script: ScriptBuf },
+    /// Pay them directly from the root so they only need one transaction.
+    /// Payouts below the dust limit cannot be paid even there and are carried.
+    Promote,
+}
+
+/// A leaf is economical when its value exceeds the cost of unrolling it at
+/// `fee_rate` sat/vB by at least `margin` sat.
+#[derive(Clone, Debug)]
+pub struct DustPolicy {
+    pub fee_rate: u64,
+    pub margin: u64,
+    pub handling: DustHandling,
+}
+
+#[derive(Clone, Debug)]
+pub struct DustAwareTree {
+    pub root: TreeNode,
+    /// Payouts to be booked as balances: folded into the carry-over output
+    /// when there is one, otherwise paid nowhere in the tree.
+    pub carried: Vec<TxOut>,
+    /// Payouts moved up to the root.
+    pub promoted: Vec<TxOut>,
+}
+
+/// Builds a tree of the given shape and moves every payout that fails `policy` out of it,
+/// rebuilding until all remaining tree leaves pass. Payouts below the dust limit
+/// are always carried, whatever the handling, so no output in the tree is dust.
+/// Fails if nothing is left to pay from the root.
+pub fn build_dust_aware_tree(
+    payouts: &[TxOut],
+    radix: usize,
+    node_fee: u64,
+    shape: TreeShape,
+    policy: &DustPolicy,
+) -> Result<DustAwareTree, Error> {
+    let (mut kept, below): (Vec<TxOut>, Vec<TxOut>) = payouts.iter().cloned().partition(|o| !is_dust(o));
+    // Payouts that pass the dust limit but not the policy
+    let mut dust = vec![];
+
+    loop {
+        let root_outputs = match &policy.handling {
+            DustHandling::CarryOver { pool_script } => {
+                let carried = sum_values(&below)?
+                    .checked_add(sum_values(&dust)?)
+                    .ok_or_else(|| Error::Policy("carried value overflows u64".into()))?;
+                let output = TxOut { value: Amount::from_sat(carried), script_pubkey: pool_script.clone() };
+                if carried == 0 || is_dust(&output) {
+                    vec![]
+                } else {
+                    vec![output]
+                }
+            }
+            DustHandling::Promote => dust.clone(),
+        };
+        let root = build_with_root_outputs(&kept, root_outputs, radix, node_fee, shape)?;
+
+        // Shaped trees reorder payouts, so match each leaf back to one of them
+        let mut matched = vec![false; kept.len()];
+        let mut failing = vec![];
+        for exit in leaf_exits(&root) {
+            let output = leaf_output(&root, &exit.path).ok_or_else(|| Error::InvalidTemplate("leaf path missing from tree".into()))?;
+            let Some(i) = (0..kept.len()).find(|i| !matched[*i] && kept[*i] == *output) else {
+                continue;
+            };
+            matched[i] = true;
+            if exit.value < exit.cost(policy.fee_rate).saturating_add(policy.margin) {
+                failing.push(i);
+            }
+        }
+        failing.sort_unstable();
+
+        if failing.is_empty() {
+            let (carried, promoted) = match policy.handling {
+                DustHandling::CarryOver { .. } => ([below, dust].concat(), vec![]),
+                DustHandling::Promote => (below, dust),
+            };
+            return Ok(DustAwareTree { root, carried, promoted });
+        }
+        for i in failing.into_iter().rev() {
+            dust.push(kept.remove(i));
+        }
+    }
+}
+
+/// Tree over `kept` whose root additionally pays `extra` directly.
+fn build_with_root_outputs(
+    kept: &[TxOut],
+    extra: Vec<TxOut>,
+    radix: usize,
+    node_fee: u64,
+    shape: TreeShape,
+) -> Result<TreeNode, Error> {
+    if kept.is_empty() {
+        if extra.is_empty() {
+            return Err(Error::InvalidTemplate("no payouts left to build a tree from".into()));
+        }
+        return node_paying(extra, vec![], node_fee);
+    }
+    let subtree = build_shaped_tree(kept, radix, node_fee, shape)?;
+    let mut outputs = subtree.tx.output;
+    outputs.extend(extra);
+    node_paying(outputs, subtree.children, node_fee)
+}
+
+fn leaf_output<'a>(root: &'a TreeNode, path: &[u32]) -> Option<&'a TxOut> {
+    let (vout, parents) = path.split_last()?;
+    let node = parents.iter().try_fold(root, |node, vout| node.child(*vout))?;
+    node.tx.output.get(*vout as usize)
+}
+
+fn node_paying(
+    outputs: Vec<TxOut>,
+    children: Vec<(u32, TreeNode)>,
+    node_fee: u64,
+) -> Result<TreeNode, Error> {
+    check_dust(&outputs)?;
+    let input_value = sum_values(&outputs)?
+        .checked_add(node_fee)
+        .ok_or_else(|| Error::Policy("tree value overflows u64".into()))?;
+    Ok(TreeNode { tx: template_tx(outputs), input_value, children })
+}
+
+/// Whether `output` is worth less than it costs to spend at the dust relay feerate.
+pub(crate) fn is_dust(output: &TxOut) -> bool {
+    output.value < output.script_pubkey.dust_value()
+}
+
+/// Fails on the first output that no node would relay.
+pub(crate) fn check_dust(outputs: &[TxOut]) -> Result<(), Error> {
+    match outputs.iter().find(|o| is_dust(o)) {
+        Some(output) => Err(Error::Policy(format!(
+            "output of {} sat is below its dust limit of {} sat",
+            output.value.to_sat(),
+            output.script_pubkey.dust_value().to_sat()
+        ))),
+        None => Ok(()),
+    }
+}
+
+pub(crate) fn sum_values(outputs: &[TxOut]) -> Result<u64, Error> {
+    outputs
+        .iter()
+        .try_fold(0u64, |sum, o| sum.checked_add(o.value.to_sat()))
+        .ok_or_else(|| Error::Policy("output values overflow u64".into()))
+}
+
+/// Splits `items` into `parts` contiguous chunks whose sizes differ by at most one.
+pub(crate) fn split_evenly<T>(items: &[T], parts: usize) -> Vec<&[T]> {
+    let base = items.len() / parts;
+    let extra = items.len() % parts;
+    let mut chunks = vec![];
+    let mut start = 0;
+    for i in 0..parts {
+        let len = base + usize::from(i < extra);
+        chunks.push(&items[start..start + len]);
+        start += len;
+    }
+    chunks
+}
@@ -0,0 +1,140 @@
+//! Where the explorers read the chain from. bitcoind answers through RPC (and
+//! scans blocks for spends, having no spend index); `esplora` asks an Esplora
+//! REST server, which indexes spends itself, and `electrum` an Electrum
+//! server, which indexes scripts.
+
+use std::fmt;
+
+use bitcoincore_rpc::{jsonrpc, RpcApi};
+use bitcoin::{OutPoint, Transaction, Txid};
+use serde_json::json;
+
+use crate::electrum::take_electrum_arg;
+use crate::error::Error;
+use crate::esplora::take_esplora_arg;
+use crate::rpc::NodeClient;
+
+const RPC_INVALID_ADDRESS_OR_KEY: i32 = -5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxStatus {
+    Unbroadcast,
+    Mempool,
+    Confirmed { height: u64 },
+}
+
+impl fmt::Display for TxStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxStatus::Unbroadcast => write!(f, "unbroadcast"),
+            TxStatus::Mempool => write!(f, "in mempool"),
+            TxStatus::Confirmed { height } => write!(f, "confirmed at height {height}"),
+        }
+    }
+}
+
+/// Lookups and broadcast for following transactions through a CTV tree.
+pub trait ChainSource {
+    /// The transaction, or `None` if neither the mempool nor the chain has it.
+    fn fetch_transaction(&self, txid: &Txid) -> Result<Option<Transaction>, Error>;
+
+    fn transaction_status(&self, txid: &Txid) -> Result<TxStatus, Error>;
+
+    /// For every output of `txid`, the transaction spending it, if any.
+    fn outspends(&self, txid: &Txid) -> Result<Vec<Option<Txid>>, Error>;
+
+    fn broadcast(&self, tx: &Transaction) -> Result<Txid, Error>;
+
+    /// Height of the best block.
+    fn tip_height(&self) -> Result<u64, Error>;
+}
+
+impl ChainSource for NodeClient {
+    fn fetch_transaction(&self, txid: &Txid) -> Result<Option<Transaction>, Error> {
+        match self.get_raw_transaction(txid, None) {
+            Ok(tx) => Ok(Some(tx)),
+            Err(e) if is_not_found(&e) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn transaction_status(&self, txid: &Txid) -> Result<TxStatus, Error> {
+        match self.get_raw_transaction_info(txid, None) {
+            Ok(info) => match info.blockhash {
+                Some(hash) => Ok(TxStatus::Confirmed {
+                    height: self.get_block_header_info(&hash)?.height as u64,
+                }),
+                None => Ok(TxStatus::Mempool),
+            },
+            Err(e) if is_not_found(&e) => Ok(TxStatus::Unbroadcast),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn outspends(&self, txid: &Txid) -> Result<Vec<Option<Txid>>, Error> {
+        let Some(tx) = self.fetch_transaction(txid)? else {
+            return Ok(vec![]);
+        };
+        let confirmed_at = match self.transaction_status(txid)? {
+            TxStatus::Confirmed { height } => Some(height),
+            _ => None,
+        };
+        (0..tx.output.len() as u32)
+            .map(|vout| find_spender(self, OutPoint { txid: *txid, vout }, confirmed_at))
+            .collect()
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<Txid, Error> {
+        Ok(self.send_raw_transaction(tx)?)
+    }
+
+    fn tip_height(&self) -> Result<u64, Error> {
+        Ok(self.get_block_count()?)
+    }
+}
+
+/// Removes `--esplora <url>` or `--electrum <host:port>` from the arguments
+/// and connects to that source. `None` means neither was given.
+pub fn take_source_arg(args: &mut Vec<String>) -> Result<Option<Box<dyn ChainSource>>, Error> {
+    if let Some(esplora) = take_esplora_arg(args)? {
+        return Ok(Some(Box::new(esplora)));
+    }
+    Ok(take_electrum_arg(args)?.map(|electrum| Box::new(electrum) as Box<dyn ChainSource>))
+}
+
+fn is_not_found(e: &bitcoincore_rpc::Error) -> bool {
+    matches!(e, bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(e)) if e.code == RPC_INVALID_ADDRESS_OR_KEY)
+}
+
+/// Finds the transaction spending `outpoint`, checking the mempool first and
+/// then scanning blocks from `confirmed_at` (bitcoind keeps no spend index).
+pub fn find_spender(
+    rpc: &NodeClient,
+    outpoint: OutPoint,
+    confirmed_at: Option<u64>,
+) -> Result<Option<Txid>, Error> {
+    let spending: serde_json::Value = rpc.call(
+        "gettxspendingprevout",
+        &[json!([{ "txid": outpoint.txid.to_string(), "vout": outpoint.vout }])],
+    )?;
+    if let Some(txid) = spending[0].get("spendingtxid").and_then(|v| v.as_str()) {
+        return Ok(Some(txid.parse()?));
+    }
+
+    if rpc.get_tx_out(&outpoint.txid, outpoint.vout, Some(true))?.is_some() {
+        return Ok(None);
+    }
+
+    let Some(from_height) = confirmed_at else {
+        return Ok(None);
+    };
+    for height in from_height..=rpc.get_block_count()? {
+        let block = rpc.get_block(&rpc.get_block_hash(height)?)?;
+        for tx in block.txdata {
+            if tx.input.iter().any(|input| input.previous_output == outpoint) {
+                return Ok(Some(tx.txid()));
+            }
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,173 @@
+//! Cooperative collapse of a MuSig2-keyed CTV subtree. Leaf owners trade
+//! shares off-chain, then every owner under a node signs one key-path spend
+//! paying the consolidated outputs, skipping every CTV level below it.
+
+use std::collections::HashMap;
+
+use bitcoin::{
+    Amount, OutPoint, Transaction, TxOut,
+    key::{Keypair, Secp256k1},
+    secp256k1::{All, PublicKey},
+};
+
+use crate::ctv::{get_virtual_bytes, template_tx};
+use crate::error::Error;
+use crate::musig::sign_with_all;
+use crate::musig_tree::{LeafPayout, MusigTree, NodeLock};
+use crate::taproot::key_spend_sighash;
+use crate::tree::TreeNode;
+
+/// Off-chain sale of a leaf share: the seller is paid out of band and signs
+/// the collapse, the buyer receives the share on chain.
+#[derive(Clone, Copy, Debug)]
+pub struct ShareTrade {
+    pub seller: PublicKey,
+    pub buyer: PublicKey,
+}
+
+/// On-chain footprint of getting a subtree's payouts confirmed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UnrollCost {
+    pub transactions: usize,
+    pub vbytes: usize,
+    pub fees: u64,
+}
+
+/// Cost of unrolling every CTV node in `node`'s subtree one by one.
+pub fn full_unroll_cost(node: &TreeNode) -> UnrollCost {
+    let mut cost = UnrollCost { transactions: 1, vbytes: node.vsize(), fees: node.fee() };
+    for (_, child) in &node.children {
+        let child_cost = full_unroll_cost(child);
+        cost.transactions += child_cost.transactions;
+        cost.vbytes += child_cost.vbytes;
+        cost.fees += child_cost.fees;
+    }
+    cost
+}
+
+/// Unsigned key-path spend of the output the node at `path` spends, paying
+/// one output per remaining owner. Fees saved by skipping the lower levels
+/// are split evenly across those outputs.
+pub fn build_collapse_tx(
+    tree: &MusigTree,
+    path: &[u32],
+    prevout: OutPoint,
+    payouts: &[LeafPayout],
+    trades: &[ShareTrade],
+    fee_rate: u64,
+) -> Result<Transaction, Error> {
+    let node = tree.node(path).ok_or_else(|| Error::InvalidTemplate(format!("no tree node at path {path:?}")))?;
+    let lock = tree.lock(path).ok_or_else(|| Error::InvalidTemplate(format!("no lock at path {path:?}")))?;
+    let shares = consolidated_shares(node, lock, payouts, trades)?;
+
+    // Size the fee with a dummy key-path signature
+    let mut tx = template_tx(shares.iter().map(|(output, _)| output.clone()).collect());
+    tx.input[0].previous_output = prevout;
+    tx.input[0].witness.push([0; 64]);
+    let fee = get_virtual_bytes(&tx) as u64 * fee_rate;
+    tx.input[0].witness.clear();
+
+    let paid: u64 = shares.iter().map(|(_, value)| value).sum();
+    let surplus = node
+        .input_value
+        .checked_sub(paid.saturating_add(fee))
+        .ok_or_else(|| Error::InsufficientValue { needed: paid.saturating_add(fee), available: node.input_value })?;
+    let bonus = surplus / shares.len() as u64;
+    let remainder = surplus % shares.len() as u64;
+
+    for (i, (output, value)) in shares.iter().enumerate() {
+        let extra = if i + 1 == shares.len() { remainder } else { 0 };
+        tx.output[i] = TxOut {
+            value: Amount::from_sat(value + bonus + extra),
+            script_pubkey: output.script_pubkey.clone(),
+        };
+    }
+    Ok(tx)
+}
+
+/// Every owner under the node signs, sellers included, then the aggregate
+/// signature goes in as the key-path witness.
+pub fn sign_collapse_tx(
+    secp: &Secp256k1<All>,
+    lock: &NodeLock,
+    tx: &mut Transaction,
+    spent_output: TxOut,
+    owners: &[Keypair],
+) -> Result<(), Error> {
+    let signers: Vec<&Keypair> = owners
+        .iter()
+        .filter(|owner| lock.owners.contains(&owner.public_key()))
+        .collect();
+    if signers.len() != lock.owners.len() {
+        return Err(Error::Taproot("every owner under the node must sign the collapse".into()));
+    }
+
+    let sighash = key_spend_sighash(tx, 0, &[spent_output])?;
+    let sig = sign_with_all(secp, &lock.signing_context(secp)?, &signers, &sighash)?;
+    tx.input[0].witness.clear();
+    tx.input[0].witness.push(sig.as_ref());
+    Ok(())
+}
+
+/// Applies `trades` to the payouts of the leaves under `node`, merging
+/// everything one final owner holds into their first payout output.
+fn consolidated_shares(
+    node: &TreeNode,
+    lock: &NodeLock,
+    payouts: &[LeafPayout],
+    trades: &[ShareTrade],
+) -> Result<Vec<(TxOut, u64)>, Error> {
+    let mut sold_to: HashMap<PublicKey, PublicKey> = HashMap::new();
+    for trade in trades {
+        if !lock.owners.contains(&trade.seller) || !lock.owners.contains(&trade.buyer) {
+            continue;
+        }
+        if sold_to.insert(trade.seller, trade.buyer).is_some() {
+            return Err(Error::Payout(format!("share of {} sold twice", trade.seller)));
+        }
+    }
+
+    let mut shares: Vec<(PublicKey, TxOut, u64)> = vec![];
+    for leaf in subtree_leaves(node) {
+        let payout = payouts
+            .iter()
+            .find(|p| p.output == *leaf && lock.owners.contains(&p.owner))
+            .ok_or_else(|| Error::Payout("no payout for tree leaf".into()))?;
+
+        // Follow resales until we reach someone who kept the share
+        let mut holder = payout.owner;
+        for _ in 0..lock.owners.len() {
+            match sold_to.get(&holder) {
+                Some(buyer) => holder = *buyer,
+                None => break,
+            }
+        }
+        if sold_to.contains_key(&holder) {
+            return Err(Error::Payout("share trades form a cycle".into()));
+        }
+
+        let value = leaf.value.to_sat();
+        match shares.iter_mut().find(|(h, _, _)| *h == holder) {
+            Some(share) => share.2 += value,
+            None => {
+                let holder_output = payouts
+                    .iter()
+                    .find(|p| p.owner == holder)
+                    .ok_or_else(|| Error::Payout("no payout for share buyer".into()))?
+                    .output
+                    .clone();
+                shares.push((holder, holder_output, value));
+            }
+        }
+    }
+
+    Ok(shares.into_iter().map(|(_, output, value)| (output, value)).collect())
+}
+
+fn subtree_leaves(node: &TreeNode) -> Vec<&TxOut> {
+    let mut leaves: Vec<&TxOut> = node.leaf_outputs().map(|(_, output)| output).collect();
+    for (_, child) in &node.children {
+        leaves.extend(subtree_leaves(child));
+    }
+    leaves
+}
@@ -0,0 +1,116 @@
+use bitcoin::{
+    Amount, OutPoint, ScriptBuf, TxOut,
+    key::{Keypair, Secp256k1},
+};
+use scripts::{
+    contract::split_evenly,
+    collapse::{build_collapse_tx, full_unroll_cost, sign_collapse_tx, ShareTrade},
+    ctv::get_virtual_bytes,
+    error::Error,
+    flow::{mine_and_mature, mine_coinbase_value},
+    musig_tree::{build_musig_tree, LeafPayout},
+    rpc::{connect, ensure_wallet, NodeRpc},
+    signet::signet_miner,
+    tree::{save_unroll_data, unroll_path},
+};
+
+const OWNER_COUNT: usize = 4;
+const RADIX: usize = 2;
+const NODE_FEE: u64 = 500;
+const FEE_RATE: u64 = 2;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+
+    // Path of the node to collapse as dot separated vouts, e.g. "0"; root by default
+    let path: Vec<u32> = match args.get(1).map(String::as_str) {
+        None | Some("") | Some("root") => vec![],
+        Some(path) => path.split('.').map(str::parse).collect::<Result<_, _>>()?,
+    };
+
+    let rpc = connect(None)?;
+
+    ensure_wallet(&rpc, "devwallet")?;
+    // On our custom signet blocks are signed and mined here instead of by generatetoaddress
+    let signet = signet_miner(&rpc)?;
+    let node: &dyn NodeRpc = match &signet {
+        Some(miner) => miner,
+        None => &rpc,
+    };
+    let network = node.network()?;
+
+    let secp = Secp256k1::new();
+    let owners: Vec<Keypair> = (0..OWNER_COUNT)
+        .map(|_| Keypair::new(&secp, &mut rand::thread_rng()))
+        .collect();
+
+    let dummy_addr = node.new_address()?;
+    let cb_value = mine_coinbase_value(node, &dummy_addr)?;
+
+    let fees = 3 * NODE_FEE;
+    let spendable = cb_value
+        .checked_sub(fees)
+        .ok_or(Error::InsufficientValue { needed: fees, available: cb_value })?;
+    let payouts: Vec<LeafPayout> = owners
+        .iter()
+        .zip(split_evenly(spendable, OWNER_COUNT))
+        .map(|(owner, value)| LeafPayout {
+            owner: owner.public_key(),
+            output: TxOut {
+                value: Amount::from_sat(value),
+                script_pubkey: ScriptBuf::new_p2tr(&secp, owner.x_only_public_key().0, None),
+            },
+        })
+        .collect();
+
+    let mut tree = build_musig_tree(&secp, &payouts, RADIX, NODE_FEE, cb_value)?;
+    let root_addr = tree.lock(&[]).ok_or("missing root lock")?.address(network);
+
+    println!("Mining to MuSig2 root: {}", root_addr);
+    let coinbase_txid = mine_and_mature(node, &root_addr, &dummy_addr)?;
+
+    tree.root.bind(OutPoint { txid: coinbase_txid, vout: 0 });
+    save_unroll_data(&unroll_path(&coinbase_txid), &tree.root)?;
+
+    // Unroll the ancestors of the collapsed node through their CTV leaves
+    let mut prevout = OutPoint { txid: coinbase_txid, vout: 0 };
+    let mut spent_output = node.get_raw_transaction(&coinbase_txid)?.output[0].clone();
+    for depth in 0..path.len() {
+        let tree_node = tree.node(&path[..depth]).ok_or("missing ancestor node")?;
+        let txid = node.send_raw_transaction(&tree_node.tx)?;
+        println!("Broadcast ancestor CTV tx: {txid}");
+        prevout = OutPoint { txid, vout: path[depth] };
+        spent_output = tree_node.tx.output[path[depth] as usize].clone();
+    }
+    if !path.is_empty() {
+        node.generate_to_address(1, &dummy_addr)?;
+    }
+
+    // Odd owners sell their leaf to their left sibling for off-chain funds
+    let trades: Vec<ShareTrade> = owners
+        .chunks(2)
+        .filter(|pair| pair.len() == 2)
+        .map(|pair| ShareTrade { seller: pair[1].public_key(), buyer: pair[0].public_key() })
+        .collect();
+
+    let lock = tree.lock(&path).ok_or("no node at that path")?;
+    let mut collapse_tx = build_collapse_tx(&tree, &path, prevout, &payouts, &trades, FEE_RATE)?;
+    sign_collapse_tx(&secp, lock, &mut collapse_tx, spent_output.clone(), &owners)?;
+
+    let collapse_txid = node.send_raw_transaction(&collapse_tx)?;
+    println!("Broadcast collapse tx: {collapse_txid}");
+    node.generate_to_address(1, &dummy_addr)?;
+
+    let full = full_unroll_cost(tree.node(&path).ok_or("no node at that path")?);
+    let collapse_fee = spent_output.value.to_sat() - collapse_tx.output.iter().map(|o| o.value.to_sat()).sum::<u64>();
+    println!();
+    println!("Full unroll: {} txs, {} vB, {} sat fees", full.transactions, full.vbytes, full.fees);
+    println!("Collapse:    1 tx,  {} vB, {} sat fees", get_virtual_bytes(&collapse_tx), collapse_fee);
+    let leaf_values: Vec<u64> = payouts.iter().map(|payout| payout.output.value.to_sat()).collect();
+    println!("  leaves paid {leaf_values:?} sat");
+    for output in &collapse_tx.output {
+        println!("  paid {} sat", output.value.to_sat());
+    }
+
+    Ok(())
+}
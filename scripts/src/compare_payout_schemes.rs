@@ -0,0 +1,74 @@
+use std::env;
+use std::path::Path;
+
+use bitcoin::{Amount, ScriptBuf, TxOut};
+use scripts::{
+    builder::build_ctv_tree,
+    ctv::get_virtual_bytes,
+    exit_cost::leaf_exits,
+    rpc::Chain,
+    shares::{load_shares, parse_scheme, SHARE_LEDGER},
+};
+
+const NODE_FEE: u64 = 500;
+/// A fresh regtest block subsidy, so the comparison needs no node.
+const REWARD: u64 = 5_000_000_000;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().collect();
+    let radix: usize = args.get(1).ok_or("radix required")?.parse()?;
+    let fee_rate: u64 = args.get(2).ok_or("feerate required")?.parse()?;
+    let specs = &args[3..];
+    if specs.is_empty() {
+        return Err("name at least one payout scheme, e.g. pplns:1000000".into());
+    }
+
+    let shares = load_shares(Path::new(SHARE_LEDGER))?;
+    let network = Chain::from_env()?.network();
+
+    println!(
+        "{:<20} {:>7} {:>14} {:>12} {:>12} {:>5} {:>7} {:>6}",
+        "scheme", "miners", "paid", "retained", "smallest", "txs", "vbytes", "dust"
+    );
+    for spec in specs {
+        let scheme = parse_scheme(spec)?;
+
+        // Same layout and fee accounting for every scheme
+        let draft = scheme.manifest(&shares, REWARD)?;
+        let draft_outputs = with_pool_output(draft.to_outputs(network)?, draft.retained);
+        let node_count = build_ctv_tree(&draft_outputs, radix, NODE_FEE)?.transactions().len();
+        let manifest = scheme.manifest(&shares, REWARD - node_count as u64 * NODE_FEE)?;
+        let outputs = with_pool_output(manifest.to_outputs(network)?, manifest.retained);
+        let root = build_ctv_tree(&outputs, radix, NODE_FEE)?;
+
+        let exits = leaf_exits(&root);
+        let dust = exits.iter().filter(|exit| !exit.is_economical(fee_rate)).count();
+        let smallest = manifest.entries.iter().map(|e| e.value).min().unwrap_or(0);
+        let vbytes: usize = root.transactions().into_iter().map(get_virtual_bytes).sum();
+
+        println!(
+            "{:<20} {:>7} {:>14} {:>12} {:>12} {:>5} {:>7} {:>6}",
+            spec,
+            manifest.entries.len(),
+            manifest.total(),
+            manifest.retained,
+            smallest,
+            root.transactions().len(),
+            vbytes,
+            dust,
+        );
+    }
+    println!();
+    println!("dust: leaves that cost more to unroll than they pay at {fee_rate} sat/vB");
+
+    Ok(())
+}
+
+/// Stand-in for the pool's retained output; only its size matters here.
+fn with_pool_output(mut outputs: Vec<TxOut>, retained: u64) -> Vec<TxOut> {
+    if retained > 0 {
+        let p2tr_sized = ScriptBuf::from_bytes([&[0x51, 0x20][..], &[0; 32]].concat());
+        outputs.push(TxOut { value: Amount::from_sat(retained), script_pubkey: p2tr_sized });
+    }
+    outputs
+}
@@ -0,0 +1,218 @@
+//! Coinbase contracts the mining scripts build: the flat CTV spend with an
+//! optional anchor and timeout leaf, and the leaf outputs of the layered tree.
+
+use bitcoin::{
+    Address, Amount, OutPoint, Transaction, TxOut, Txid,
+    key::Secp256k1,
+    script::ScriptBuf,
+    secp256k1::All,
+    taproot::{LeafVersion, TaprootSpendInfo},
+    XOnlyPublicKey,
+};
+
+use crate::builder::check_dust;
+use crate::ctv::{
+    anchor_output, build_ctv_script, ctv_spend_info, get_virtual_bytes, template_tx, TimeoutFallback, ANCHOR_VALUE,
+};
+use crate::error::Error;
+use crate::rpc::NodeRpc;
+use crate::shares::{PayoutManifest, PayoutScheme, Share};
+use crate::tree::TreeNode;
+
+/// Where the coinbase spend pays to.
+pub enum Payouts {
+    /// This many fresh wallet addresses, split evenly.
+    NewAddresses(usize),
+    /// The share ledger, split by a payout scheme. Anything the scheme
+    /// does not owe miners goes to a pool address.
+    Scheme(Box<dyn PayoutScheme>, Vec<Share>),
+}
+
+/// The flat contract: the coinbase's taproot output and the spend paying out of it.
+#[derive(Clone, Debug)]
+pub struct CtvContract {
+    pub taproot_info: TaprootSpendInfo,
+    /// Where the coinbase pays to.
+    pub address: Address,
+    /// Spend of the coinbase, prevout and witness still to fill in.
+    pub spend_tx: Transaction,
+    pub ctv_script: ScriptBuf,
+    /// What a payout scheme split, `None` for fresh addresses.
+    pub manifest: Option<PayoutManifest>,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn build_ctv_contract<R: NodeRpc + ?Sized>(
+    secp: &Secp256k1<All>,
+    xonly: XOnlyPublicKey,
+    input_value_sat: u64,
+    fee_rate: u64,
+    include_anchor: bool,
+    payouts: &Payouts,
+    fallback: Option<&TimeoutFallback>,
+    rpc: &R,
+) -> Result<CtvContract, Error> {
+    let reserved = if include_anchor { ANCHOR_VALUE } else { 0 };
+    let unreserved = input_value_sat
+        .checked_sub(reserved)
+        .ok_or(Error::InsufficientValue { needed: reserved, available: input_value_sat })?;
+    let network = rpc.network()?;
+    let spendable_after = |fee: u64| {
+        unreserved
+            .checked_sub(fee)
+            .ok_or_else(|| Error::InsufficientValue { needed: fee.saturating_add(reserved), available: input_value_sat })
+    };
+
+    let (mut outputs, manifest) = match payouts {
+        Payouts::NewAddresses(0) => return Err(Error::InvalidTemplate("the contract needs at least one output".into())),
+        Payouts::NewAddresses(count) => {
+            let scripts = (0..*count).map(|_| Ok(rpc.new_address()?.script_pubkey())).collect::<Result<Vec<_>, Error>>()?;
+            let fee = calculate_fee_with_anchor(secp, xonly, fee_rate, &scripts, include_anchor, fallback)?;
+            let outputs = split_evenly(spendable_after(fee)?, *count)
+                .into_iter()
+                .zip(scripts)
+                .map(|(value, script_pubkey)| TxOut { value: Amount::from_sat(value), script_pubkey })
+                .collect();
+            (outputs, None)
+        }
+        Payouts::Scheme(scheme, shares) => {
+            // The fee comes off the top before the split, but the split decides
+            // which outputs there are. Repeat until the two agree; if they flip
+            // between two sizes, pay the larger fee.
+            let mut pool_script = None;
+            let mut fee = 0;
+            let mut tried = vec![];
+            loop {
+                let manifest = scheme.manifest(shares, spendable_after(fee)?)?;
+                let mut outputs = manifest.to_outputs(network)?;
+                if manifest.retained > 0 {
+                    let script_pubkey = match &pool_script {
+                        Some(script) => script,
+                        None => pool_script.insert(rpc.new_address()?.script_pubkey()),
+                    };
+                    outputs.push(TxOut { value: Amount::from_sat(manifest.retained), script_pubkey: script_pubkey.clone() });
+                }
+                let scripts: Vec<ScriptBuf> = outputs.iter().map(|o| o.script_pubkey.clone()).collect();
+                let needed = calculate_fee_with_anchor(secp, xonly, fee_rate, &scripts, include_anchor, fallback)?;
+                if needed == fee || (needed < fee && tried.contains(&needed)) {
+                    break (outputs, Some(manifest));
+                }
+                tried.push(fee);
+                fee = needed;
+            }
+        }
+    };
+
+    check_dust(&outputs)?;
+    if include_anchor {
+        outputs.push(anchor_output());
+    }
+
+    let ctv_script = build_ctv_script(&outputs);
+    let taproot_info = ctv_spend_info(secp, xonly, &ctv_script, fallback)?;
+    let address = Address::p2tr_tweaked(taproot_info.output_key(), network);
+    let spend_tx = template_tx(outputs);
+
+    Ok(CtvContract { taproot_info, address, spend_tx, ctv_script, manifest })
+}
+
+/// Fee at `fee_rate` for the CTV spend paying `output_scripts`, sized with
+/// the script-path witness it will carry.
+pub fn calculate_fee_with_anchor(
+    secp: &Secp256k1<All>,
+    xonly: XOnlyPublicKey,
+    fee_rate: u64,
+    output_scripts: &[ScriptBuf],
+    include_anchor: bool,
+    fallback: Option<&TimeoutFallback>,
+) -> Result<u64, Error> {
+    let mut dummy_outputs: Vec<TxOut> = output_scripts
+        .iter()
+        .map(|script_pubkey| TxOut { value: Amount::from_sat(0), script_pubkey: script_pubkey.clone() })
+        .collect();
+
+    if include_anchor {
+        dummy_outputs.push(anchor_output());
+    }
+
+    let dummy_ctv_script = build_ctv_script(&dummy_outputs);
+    let dummy_taproot_info = ctv_spend_info(secp, xonly, &dummy_ctv_script, fallback)?;
+    let mut dummy_tx = template_tx(dummy_outputs);
+
+    dummy_tx.input[0].witness.push(dummy_ctv_script.to_bytes());
+    dummy_tx.input[0].witness.push(
+        dummy_taproot_info
+            .control_block(&(dummy_ctv_script.clone(), LeafVersion::TapScript))
+            .ok_or_else(|| Error::Taproot("failed to get control block".into()))?
+            .serialize(),
+    );
+
+    let vsize = get_virtual_bytes(&dummy_tx) as u64;
+    vsize.checked_mul(fee_rate).ok_or_else(|| Error::Policy(format!("fee at {fee_rate} sat/vB overflows u64")))
+}
+
+/// Splits `total` into `count` equal values. The last takes the remainder so
+/// no sats end up as extra fee.
+pub fn split_evenly(total: u64, count: usize) -> Vec<u64> {
+    if count == 0 {
+        return vec![];
+    }
+    let mut values = vec![total / count as u64; count];
+    values[count - 1] += total % count as u64;
+    values
+}
+
+/// One output per value, each paying a fresh wallet address.
+pub fn build_leaf_outputs<R: NodeRpc + ?Sized>(rpc: &R, values: &[u64]) -> Result<Vec<TxOut>, Error> {
+    values
+        .iter()
+        .map(|value| {
+            let addr = rpc.new_address()?;
+            Ok(TxOut {
+                value: Amount::from_sat(*value),
+                script_pubkey: addr.script_pubkey(),
+            })
+        })
+        .collect()
+}
+
+/// Points the contract spend at the mined coinbase and adds the CTV leaf's
+/// script-path witness.
+pub fn finalize_ctv_spend(
+    spend_tx: &mut Transaction,
+    coinbase_txid: Txid,
+    taproot_info: &TaprootSpendInfo,
+    ctv_script: &ScriptBuf,
+) -> Result<(), Error> {
+    spend_tx.input[0].previous_output = OutPoint { txid: coinbase_txid, vout: 0 };
+
+    let ctrl_block = taproot_info
+        .control_block(&(ctv_script.clone(), LeafVersion::TapScript))
+        .ok_or_else(|| Error::Taproot("missing control block".into()))?;
+
+    spend_tx.input[0].witness.push(ctv_script.as_bytes());
+    spend_tx.input[0].witness.push(ctrl_block.serialize());
+    Ok(())
+}
+
+/// Broadcasts every transaction of a bound tree, mining a block after each
+/// level since TRUC allows one unconfirmed child per parent.
+pub fn broadcast_by_level<R: NodeRpc + ?Sized>(
+    rpc: &R,
+    root: &TreeNode,
+    miner: &Address,
+) -> Result<Vec<Txid>, Error> {
+    let mut txids = vec![];
+    let mut level = vec![root];
+    while !level.is_empty() {
+        for node in &level {
+            txids.push(rpc.send_raw_transaction(&node.tx)?);
+        }
+        rpc.generate_to_address(1, miner)?;
+        level = level
+            .iter()
+            .flat_map(|node| node.children.iter().map(|(_, child)| child))
+            .collect();
+    }
+    Ok(txids)
+}
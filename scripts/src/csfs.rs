@@ -0,0 +1,83 @@
+//! BIP-348 `OP_CHECKSIGFROMSTACK` scripts: a bare signature check over a
+//! stack item, a checksig gated on an oracle's signed message, and a CTV leaf
+//! whose template an operator picks by signing its hash.
+
+use bitcoin::{
+    Opcode, ScriptBuf, XOnlyPublicKey,
+    hashes::{sha256, Hash},
+    key::{Keypair, Secp256k1},
+    opcodes::all::{OP_CHECKSIG, OP_OVER, OP_RETURN_204, OP_VERIFY},
+    script::Builder,
+    secp256k1::{schnorr::Signature, All, Message},
+};
+
+use crate::ctv::OP_CTV;
+
+/// BIP-348 `OP_CHECKSIGFROMSTACK`, which redefines `OP_SUCCESS204` in tapscript.
+pub const OP_CSFS: Opcode = OP_RETURN_204;
+
+/// Messages are signed as their SHA256 digest so every signature is over the
+/// 32 bytes libsecp256k1's BIP-340 API accepts. The digest is what goes on the
+/// stack or in the script.
+pub fn message_digest(message: &[u8]) -> [u8; 32] {
+    sha256::Hash::hash(message).to_byte_array()
+}
+
+pub fn sign_message(secp: &Secp256k1<All>, keypair: &Keypair, message: &[u8]) -> Signature {
+    sign_digest(secp, keypair, message_digest(message))
+}
+
+/// Signs 32 bytes as-is, for stack items that are already hashes (e.g. CTV template hashes).
+pub fn sign_digest(secp: &Secp256k1<All>, keypair: &Keypair, digest: [u8; 32]) -> Signature {
+    secp.sign_schnorr(&Message::from_digest(digest), keypair)
+}
+
+pub fn verify_message(
+    secp: &Secp256k1<All>,
+    signature: &Signature,
+    message: &[u8],
+    pubkey: &XOnlyPublicKey,
+) -> bool {
+    secp.verify_schnorr(signature, &Message::from_digest(message_digest(message)), pubkey)
+        .is_ok()
+}
+
+/// `<pubkey> OP_CSFS`: satisfied by a witness of `<sig> <digest>` where `sig`
+/// is `pubkey`'s signature over `digest`.
+pub fn csfs_script(pubkey: &XOnlyPublicKey) -> ScriptBuf {
+    Builder::new()
+        .push_x_only_key(pubkey)
+        .push_opcode(OP_CSFS)
+        .into_script()
+}
+
+/// `<digest> <oracle> OP_CSFS OP_VERIFY <owner> OP_CHECKSIG`: the owner can
+/// only spend once the oracle has signed `message` off-chain.
+/// Witness: `<owner tx sig> <oracle message sig>`.
+pub fn csfs_gated_checksig_script(
+    message: &[u8],
+    oracle: &XOnlyPublicKey,
+    owner: &XOnlyPublicKey,
+) -> ScriptBuf {
+    Builder::new()
+        .push_slice(message_digest(message))
+        .push_x_only_key(oracle)
+        .push_opcode(OP_CSFS)
+        .push_opcode(OP_VERIFY)
+        .push_x_only_key(owner)
+        .push_opcode(OP_CHECKSIG)
+        .into_script()
+}
+
+/// `OP_OVER <operator> OP_CSFS OP_VERIFY OP_CTV`: spends to whichever CTV
+/// template the operator signed, so the payout can be chosen after mining.
+/// Witness: `<template hash> <operator sig over template hash>`.
+pub fn signed_template_script(operator: &XOnlyPublicKey) -> ScriptBuf {
+    Builder::new()
+        .push_opcode(OP_OVER)
+        .push_x_only_key(operator)
+        .push_opcode(OP_CSFS)
+        .push_opcode(OP_VERIFY)
+        .push_opcode(OP_CTV)
+        .into_script()
+}
@@ -0,0 +1,85 @@
+use bitcoin::{
+    Address, Amount, OutPoint, TxOut,
+    consensus::encode::serialize_hex,
+    key::{Keypair, Secp256k1},
+    XOnlyPublicKey,
+};
+use scripts::{
+    csfs::{csfs_gated_checksig_script, sign_message, verify_message},
+    ctv::template_tx,
+    error::Error,
+    flow::mine_and_mature,
+    rpc::{connect, ensure_wallet, NodeRpc},
+    signet::signet_miner,
+    taproot::{leaf_spend_info, sign_script_spend, tapscript_witness},
+};
+
+const SPEND_FEE: u64 = 500;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let rpc = connect(None)?;
+
+    ensure_wallet(&rpc, "devwallet")?;
+
+    let rpc = connect(Some("devwallet"))?;
+    // On our custom signet blocks are signed and mined here instead of by generatetoaddress
+    let signet = signet_miner(&rpc)?;
+    let node: &dyn NodeRpc = match &signet {
+        Some(miner) => miner,
+        None => &rpc,
+    };
+    let network = node.network()?;
+
+    let secp = Secp256k1::new();
+    let internal = Keypair::new(&secp, &mut rand::thread_rng());
+    let oracle = Keypair::new(&secp, &mut rand::thread_rng());
+    let owner = Keypair::new(&secp, &mut rand::thread_rng());
+    let (internal_key, _) = XOnlyPublicKey::from_keypair(&internal);
+    let (oracle_key, _) = XOnlyPublicKey::from_keypair(&oracle);
+    let (owner_key, _) = XOnlyPublicKey::from_keypair(&owner);
+
+    let message = format!("release coinbase to {owner_key}");
+    let leaf = csfs_gated_checksig_script(message.as_bytes(), &oracle_key, &owner_key);
+    let spend_info = leaf_spend_info(&secp, internal_key, &leaf)?;
+    let csfs_address = Address::p2tr_tweaked(spend_info.output_key(), network);
+
+    println!("Mining to CSFS contract address: {}", csfs_address);
+    let dummy_addr = node.new_address()?;
+    let coinbase_txid = mine_and_mature(node, &csfs_address, &dummy_addr)?;
+    let coinbase_tx = node.get_raw_transaction(&coinbase_txid)?;
+
+    // The oracle authorizes the release off-chain
+    let oracle_sig = sign_message(&secp, &oracle, message.as_bytes());
+    if !verify_message(&secp, &oracle_sig, message.as_bytes(), &oracle_key) {
+        return Err("oracle signature does not verify".into());
+    }
+    println!("Oracle signed \"{message}\": {oracle_sig}");
+
+    let prevout = coinbase_tx.output[0].clone();
+    let available = prevout.value.to_sat();
+    let value = available
+        .checked_sub(SPEND_FEE)
+        .ok_or(Error::InsufficientValue { needed: SPEND_FEE, available })?;
+    let dest_addr = node.new_address()?;
+    let mut spend_tx = template_tx(vec![TxOut {
+        value: Amount::from_sat(value),
+        script_pubkey: dest_addr.script_pubkey(),
+    }]);
+    spend_tx.input[0].previous_output = OutPoint { txid: coinbase_tx.txid(), vout: 0 };
+
+    let owner_sig = sign_script_spend(&secp, &owner, &spend_tx, 0, &[prevout], &leaf)?;
+    spend_tx.input[0].witness = tapscript_witness(
+        &[&owner_sig.serialize(), &oracle_sig.serialize()],
+        &leaf,
+        &spend_info,
+    )?;
+
+    println!("Spending tx: {}", serialize_hex(&spend_tx));
+    let txid = node.send_raw_transaction(&spend_tx)?;
+    println!("Broadcasted txid: {txid}");
+
+    node.generate_to_address(1, &dummy_addr)?;
+    println!("Mined txid: {txid}");
+
+    Ok(())
+}
@@ -0,0 +1,218 @@
+use bitcoin::{
+    Amount, Transaction, TxOut, TxIn, OutPoint,
+    blockdata::script::Instruction,
+    consensus::{Encodable, encode::serialize},
+    hashes::{sha256, Hash},
+    key::Secp256k1,
+    opcodes::all::{OP_CSV, OP_DROP, OP_NOP4, OP_PUSHNUM_1},
+    script::{Builder, Script, ScriptBuf},
+    secp256k1::All,
+    taproot::{TaprootBuilder, TaprootSpendInfo},
+    Opcode, Sequence, Witness, XOnlyPublicKey,
+};
+
+use crate::error::Error;
+
+pub const OP_CTV: Opcode = OP_NOP4;
+pub const ANCHOR_VALUE: u64 = 330;
+pub const ANCHOR_PUSHBYTES: [u8; 2] = [0x4e, 0x73];
+
+/// Pay-to-anchor output anyone can spend to CPFP the transaction carrying it.
+pub fn anchor_output() -> TxOut {
+    TxOut {
+        value: Amount::from_sat(ANCHOR_VALUE),
+        script_pubkey: Builder::new()
+            .push_opcode(OP_PUSHNUM_1)
+            .push_slice(ANCHOR_PUSHBYTES)
+            .into_script(),
+    }
+}
+
+pub fn is_anchor(spk: &Script) -> bool {
+    spk.as_bytes() == anchor_output().script_pubkey.as_bytes()
+}
+
+/// BIP-119 default template hash for a single-input v3 transaction paying
+/// `outputs`, whose input carries `ENABLE_RBF_NO_LOCKTIME`.
+pub fn calc_ctv_hash(outputs: &[TxOut]) -> [u8; 32] {
+    calc_ctv_hash_with_sequence(outputs, Sequence::ENABLE_RBF_NO_LOCKTIME)
+}
+
+/// The same template with the input's nSequence set to `sequence`, so a
+/// relative timelock is part of what the hash commits to.
+pub fn calc_ctv_hash_with_sequence(outputs: &[TxOut], sequence: Sequence) -> [u8; 32] {
+    let mut buffer = Vec::new();
+    buffer.extend(3_i32.to_le_bytes()); // version
+    buffer.extend(0_i32.to_le_bytes()); // locktime
+    buffer.extend(1_u32.to_le_bytes()); // input count
+
+    let seq = sha256::Hash::hash(&sequence.0.to_le_bytes());
+    buffer.extend(seq.to_byte_array());
+
+    buffer.extend((outputs.len() as u32).to_le_bytes());
+
+    let mut output_bytes = Vec::new();
+    for o in outputs {
+        o.consensus_encode(&mut output_bytes).unwrap();
+    }
+    buffer.extend(sha256::Hash::hash(&output_bytes).to_byte_array());
+
+    buffer.extend(0_u32.to_le_bytes()); // input index
+
+    sha256::Hash::hash(&buffer).to_byte_array()
+}
+
+/// BIP-119 default template hash of `tx` spent at `input_index`, for checking
+/// a transaction against the hash its parent output commits to.
+pub fn template_hash(tx: &Transaction, input_index: u32) -> [u8; 32] {
+    let mut buffer = Vec::new();
+    buffer.extend(tx.version.0.to_le_bytes());
+    buffer.extend(tx.lock_time.to_consensus_u32().to_le_bytes());
+
+    if tx.input.iter().any(|i| !i.script_sig.is_empty()) {
+        let mut script_sigs = Vec::new();
+        for input in &tx.input {
+            input.script_sig.consensus_encode(&mut script_sigs).unwrap();
+        }
+        buffer.extend(sha256::Hash::hash(&script_sigs).to_byte_array());
+    }
+
+    buffer.extend((tx.input.len() as u32).to_le_bytes());
+    let sequences: Vec<u8> = tx.input.iter().flat_map(|i| i.sequence.0.to_le_bytes()).collect();
+    buffer.extend(sha256::Hash::hash(&sequences).to_byte_array());
+
+    buffer.extend((tx.output.len() as u32).to_le_bytes());
+    let mut output_bytes = Vec::new();
+    for o in &tx.output {
+        o.consensus_encode(&mut output_bytes).unwrap();
+    }
+    buffer.extend(sha256::Hash::hash(&output_bytes).to_byte_array());
+
+    buffer.extend(input_index.to_le_bytes());
+
+    sha256::Hash::hash(&buffer).to_byte_array()
+}
+
+/// `<hash> OP_CTV` committing to a transaction paying `outputs`.
+pub fn build_ctv_script(outputs: &[TxOut]) -> ScriptBuf {
+    let hash = calc_ctv_hash(outputs);
+    Builder::new().push_slice(hash).push_opcode(OP_CTV).into_script()
+}
+
+/// Returns the committed template hash if `script` is exactly `<32 bytes> OP_CTV`.
+pub fn ctv_hash_from_script(script: &Script) -> Option<[u8; 32]> {
+    let mut instructions = script.instructions();
+    let hash = match instructions.next() {
+        Some(Ok(Instruction::PushBytes(bytes))) => <[u8; 32]>::try_from(bytes.as_bytes()).ok()?,
+        _ => return None,
+    };
+    match (instructions.next(), instructions.next()) {
+        (Some(Ok(Instruction::Op(op))), None) if op == OP_CTV => Some(hash),
+        _ => None,
+    }
+}
+
+/// Pool recovery leaf for a CTV contract nobody unrolls:
+/// `<blocks> OP_CSV OP_DROP <hash> OP_CTV`. The template pays `recovery` from
+/// an input carrying the CSV nSequence, so the timelock is committed by the
+/// hash and anyone can broadcast the recovery once it has passed.
+#[derive(Clone, Debug)]
+pub struct TimeoutFallback {
+    pub blocks: u16,
+    pub recovery: Vec<TxOut>,
+}
+
+impl TimeoutFallback {
+    pub fn script(&self) -> ScriptBuf {
+        Builder::new()
+            .push_sequence(self.sequence())
+            .push_opcode(OP_CSV)
+            .push_opcode(OP_DROP)
+            .push_slice(calc_ctv_hash_with_sequence(&self.recovery, self.sequence()))
+            .push_opcode(OP_CTV)
+            .into_script()
+    }
+
+    /// nSequence the recovery transaction must carry for `OP_CSV` to pass.
+    pub fn sequence(&self) -> Sequence {
+        Sequence::from_height(self.blocks)
+    }
+
+    /// Unsigned recovery transaction matching the leaf's template, with the
+    /// prevout left null.
+    pub fn recovery_tx(&self) -> Transaction {
+        template_tx_with_sequence(self.recovery.clone(), self.sequence())
+    }
+}
+
+/// The template hash a recovery leaf commits to and the nSequence its
+/// `OP_CSV` checks, if `script` is `<n> OP_CSV OP_DROP <32 bytes> OP_CTV`.
+pub fn timeout_from_script(script: &Script) -> Option<(Sequence, [u8; 32])> {
+    let instructions: Vec<Instruction> = script.instructions().collect::<Result<_, _>>().ok()?;
+    match instructions.as_slice() {
+        [delay, Instruction::Op(csv), Instruction::Op(drop), Instruction::PushBytes(hash), Instruction::Op(ctv)]
+            if *csv == OP_CSV && *drop == OP_DROP && *ctv == OP_CTV =>
+        {
+            let blocks = delay.script_num()?;
+            Some((
+                Sequence(u32::try_from(blocks).ok()?),
+                <[u8; 32]>::try_from(hash.as_bytes()).ok()?,
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Taproot tree holding the CTV leaf, with the timeout leaf beside it if given.
+pub fn ctv_spend_info(
+    secp: &Secp256k1<All>,
+    internal_key: XOnlyPublicKey,
+    ctv_script: &ScriptBuf,
+    fallback: Option<&TimeoutFallback>,
+) -> Result<TaprootSpendInfo, Error> {
+    let builder = match fallback {
+        Some(fallback) => TaprootBuilder::new()
+            .add_leaf(1, ctv_script.clone())?
+            .add_leaf(1, fallback.script())?,
+        None => TaprootBuilder::new().add_leaf(0, ctv_script.clone())?,
+    };
+    builder
+        .finalize(secp, internal_key)
+        .map_err(|e| Error::Taproot(format!("taproot finalize failed: {e:?}")))
+}
+
+/// Unsigned transaction matching the template `calc_ctv_hash` commits to.
+/// The prevout is left null until the parent txid is known.
+pub fn template_tx(outputs: Vec<TxOut>) -> Transaction {
+    template_tx_with_sequence(outputs, Sequence::ENABLE_RBF_NO_LOCKTIME)
+}
+
+/// The template `calc_ctv_hash_with_sequence` commits to.
+pub fn template_tx_with_sequence(outputs: Vec<TxOut>, sequence: Sequence) -> Transaction {
+    Transaction {
+        version: bitcoin::transaction::Version(3),
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: ScriptBuf::new(),
+            sequence,
+            witness: Witness::default(),
+        }],
+        output: outputs,
+    }
+}
+
+pub fn get_virtual_bytes(tx: &Transaction) -> usize {
+    let total_size = serialize(tx).len();
+
+    // Clone and strip all witness data to get base size
+    let mut base_tx = tx.clone();
+    for input in &mut base_tx.input {
+        input.witness.clear();
+    }
+    let base_size = serialize(&base_tx).len();
+
+    // weight = base * 3 + total
+    let weight = base_size * 3 + total_size;
+    weight.div_ceil(4)
+}
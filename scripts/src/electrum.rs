@@ -0,0 +1,275 @@
+//! `ChainSource` over the Electrum protocol, which the devenv's electrs serves
+//! on port 60401 and which most miners' wallets already talk to. Electrum
+//! indexes by script rather than by outpoint: a transaction's status and its
+//! outputs' spends come from the history of each output's scripthash, and
+//! subscribing to those scripthashes gets a notification whenever one of them
+//! is paid or spent, which is how `watch_tree` follows an unroll.
+//!
+//! Requests are newline-delimited JSON-RPC over one plain TCP connection (no
+//! TLS). Notifications that arrive while waiting for a response are queued
+//! for `wait_for_change`.
+
+use std::collections::{hash_map::Entry, HashMap, VecDeque};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::TcpStream;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use bitcoin::{
+    consensus::encode::{deserialize, serialize_hex},
+    hashes::{sha256, Hash},
+    OutPoint, Script, Transaction, Txid,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::chain::{ChainSource, TxStatus};
+use crate::ctv::is_anchor;
+use crate::error::Error;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+const SUBSCRIBE: &str = "blockchain.scripthash.subscribe";
+
+/// A connection to an Electrum server at `host:port`.
+pub struct ElectrumClient {
+    connection: Mutex<Connection>,
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    next_id: u64,
+    /// Bytes of a line cut off by a read timeout.
+    partial: Vec<u8>,
+    /// Scripthashes whose status changed, in the order we heard.
+    notifications: VecDeque<String>,
+}
+
+#[derive(Deserialize)]
+struct HistoryEntry {
+    tx_hash: Txid,
+    /// Confirmation height, or 0 (or -1 with unconfirmed parents) in the mempool.
+    height: i64,
+}
+
+/// The Electrum scripthash of an output script: its SHA256, byte-reversed, in hex.
+pub fn script_hash(script: &Script) -> String {
+    let mut hash = sha256::Hash::hash(script.as_bytes()).to_byte_array();
+    hash.reverse();
+    hash.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+impl ElectrumClient {
+    pub fn new(addr: &str) -> Result<Self, Error> {
+        let addr = addr.strip_prefix("tcp://").unwrap_or(addr);
+        if addr.starts_with("ssl://") || !addr.contains(':') {
+            return Err(Error::Parse(format!("Electrum server {addr:?} must be host:port over plain TCP")));
+        }
+        let writer = TcpStream::connect(addr)?;
+        writer.set_write_timeout(Some(TIMEOUT))?;
+        let reader = BufReader::new(writer.try_clone()?);
+        let client = ElectrumClient {
+            connection: Mutex::new(Connection {
+                reader,
+                writer,
+                next_id: 0,
+                partial: vec![],
+                notifications: VecDeque::new(),
+            }),
+        };
+        client.call("server.version", json!(["coinbase-playground", "1.4"]))?;
+        Ok(client)
+    }
+
+    fn call(&self, method: &str, params: Value) -> Result<Value, Error> {
+        self.request(method, params)?
+            .map_err(|reason| Error::Node(format!("Electrum {method} failed: {reason}")))
+    }
+
+    /// The result of `method`, or the server's error message if it answered
+    /// with one.
+    fn request(&self, method: &str, params: Value) -> Result<Result<Value, String>, Error> {
+        let mut conn = self.connection.lock().expect("electrum connection poisoned");
+        conn.next_id += 1;
+        let id = conn.next_id;
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        conn.writer.write_all(format!("{request}\n").as_bytes())?;
+
+        loop {
+            let message = conn.read_message(Some(TIMEOUT))?.ok_or_else(|| {
+                Error::Node(format!("Electrum server did not answer {method} within {}s", TIMEOUT.as_secs()))
+            })?;
+            if message.get("id").and_then(Value::as_u64) != Some(id) {
+                continue;
+            }
+            if let Some(error) = message.get("error").filter(|e| !e.is_null()) {
+                let reason = error.get("message").and_then(Value::as_str).map_or_else(|| error.to_string(), String::from);
+                return Ok(Err(reason));
+            }
+            return Ok(Ok(message.get("result").cloned().unwrap_or(Value::Null)));
+        }
+    }
+
+    /// Subscribes to changes in the history of `script`, returning its current
+    /// status hash (`None` if nothing has ever paid it).
+    pub fn subscribe(&self, script: &Script) -> Result<Option<String>, Error> {
+        let status = self.call(SUBSCRIBE, json!([script_hash(script)]))?;
+        Ok(status.as_str().map(String::from))
+    }
+
+    /// The scripthash of the next subscribed script whose history changed, or
+    /// `None` if nothing changed within `timeout` (`None` waits forever).
+    pub fn wait_for_change(&self, timeout: Option<Duration>) -> Result<Option<String>, Error> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut conn = self.connection.lock().expect("electrum connection poisoned");
+        loop {
+            if let Some(hash) = conn.notifications.pop_front() {
+                return Ok(Some(hash));
+            }
+            // No request is in flight, so anything read is a notification,
+            // possibly for a new header rather than a script
+            let remaining = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) if !remaining.is_zero() => Some(remaining),
+                    _ => return Ok(None),
+                },
+                None => None,
+            };
+            if conn.read_message(remaining)?.is_none() {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn history(&self, script: &Script) -> Result<Vec<HistoryEntry>, Error> {
+        let history = self.call("blockchain.scripthash.get_history", json!([script_hash(script)]))?;
+        Ok(serde_json::from_value(history)?)
+    }
+}
+
+impl Connection {
+    /// Reads the next message, queueing it if it is a subscription
+    /// notification. `None` if `timeout` passes first.
+    fn read_message(&mut self, timeout: Option<Duration>) -> Result<Option<Value>, Error> {
+        self.reader.get_ref().set_read_timeout(timeout)?;
+        match self.reader.read_until(b'\n', &mut self.partial) {
+            Ok(0) => return Err(Error::Node("Electrum server closed the connection".into())),
+            Ok(_) if self.partial.ends_with(b"\n") => {}
+            Ok(_) => return Err(Error::Node("Electrum server closed the connection mid-message".into())),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let line = std::mem::take(&mut self.partial);
+        let message: Value = serde_json::from_slice(&line)?;
+        if message.get("method").and_then(Value::as_str) == Some(SUBSCRIBE) {
+            if let Some(hash) = message["params"][0].as_str() {
+                self.notifications.push_back(hash.to_string());
+            }
+        }
+        Ok(Some(message))
+    }
+}
+
+impl ChainSource for ElectrumClient {
+    /// Electrum servers report a missing transaction as an error response like
+    /// any other, so only the not-found messages read as `None`.
+    fn fetch_transaction(&self, txid: &Txid) -> Result<Option<Transaction>, Error> {
+        let hex = match self.request("blockchain.transaction.get", json!([txid.to_string(), false]))? {
+            Ok(hex) => hex,
+            Err(reason) if is_not_found(&reason) => return Ok(None),
+            Err(reason) => return Err(Error::Node(format!("Electrum could not get {txid}: {reason}"))),
+        };
+        let hex = hex.as_str().ok_or_else(|| Error::Node(format!("Electrum returned no hex for {txid}")))?;
+        Ok(Some(deserialize(&hex::decode(hex)?)?))
+    }
+
+    fn transaction_status(&self, txid: &Txid) -> Result<TxStatus, Error> {
+        let Some(tx) = self.fetch_transaction(txid)? else {
+            return Ok(TxStatus::Unbroadcast);
+        };
+        // Any output's history lists the transaction that paid it
+        let Some(output) = tx.output.iter().find(|o| !o.script_pubkey.is_op_return() && !is_anchor(&o.script_pubkey))
+        else {
+            return Err(Error::Node(format!("{txid} has no output Electrum indexes")));
+        };
+        Ok(match self.history(&output.script_pubkey)?.iter().find(|entry| entry.tx_hash == *txid) {
+            Some(entry) if entry.height > 0 => TxStatus::Confirmed { height: entry.height as u64 },
+            Some(_) => TxStatus::Mempool,
+            None => TxStatus::Unbroadcast,
+        })
+    }
+
+    /// Anchor spends are not looked up: every P2A output shares one script, so
+    /// its history is every anchor spend on the chain.
+    fn outspends(&self, txid: &Txid) -> Result<Vec<Option<Txid>>, Error> {
+        let Some(tx) = self.fetch_transaction(txid)? else {
+            return Ok(vec![]);
+        };
+        let mut fetched: HashMap<Txid, Transaction> = HashMap::new();
+        let mut spends = vec![];
+        for (vout, output) in tx.output.iter().enumerate() {
+            if output.script_pubkey.is_op_return() || is_anchor(&output.script_pubkey) {
+                spends.push(None);
+                continue;
+            }
+            let outpoint = OutPoint { txid: *txid, vout: vout as u32 };
+            let mut spender = None;
+            for entry in self.history(&output.script_pubkey)? {
+                if entry.tx_hash == *txid {
+                    continue;
+                }
+                let candidate = match fetched.entry(entry.tx_hash) {
+                    Entry::Occupied(tx) => tx.into_mut(),
+                    Entry::Vacant(slot) => match self.fetch_transaction(&entry.tx_hash)? {
+                        Some(tx) => slot.insert(tx),
+                        None => continue,
+                    },
+                };
+                if candidate.input.iter().any(|input| input.previous_output == outpoint) {
+                    spender = Some(entry.tx_hash);
+                    break;
+                }
+            }
+            spends.push(spender);
+        }
+        Ok(spends)
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<Txid, Error> {
+        let txid = self.call("blockchain.transaction.broadcast", json!([serialize_hex(tx)]))?;
+        txid.as_str()
+            .ok_or_else(|| Error::Node(format!("Electrum broadcast returned {txid}")))?
+            .parse()
+            .map_err(Error::from)
+    }
+
+    /// Subscribing is the only way to ask for the tip. The header
+    /// notifications that follow are read and dropped.
+    fn tip_height(&self) -> Result<u64, Error> {
+        let tip = self.call("blockchain.headers.subscribe", json!([]))?;
+        tip.get("height").and_then(Value::as_u64).ok_or_else(|| Error::Node(format!("Electrum returned tip {tip}")))
+    }
+}
+
+/// Whether a server error means the transaction is unknown. electrs and
+/// ElectrumX pass on bitcoind's "No such mempool or blockchain transaction",
+/// Fulcrum and Blockstream's electrs say it in their own words.
+fn is_not_found(reason: &str) -> bool {
+    let reason = reason.to_ascii_lowercase();
+    ["no such mempool or blockchain transaction", "not found", "cannot find"]
+        .iter()
+        .any(|message| reason.contains(message))
+}
+
+/// Removes `--electrum <host:port>` from the arguments, leaving the positional ones.
+pub fn take_electrum_arg(args: &mut Vec<String>) -> Result<Option<ElectrumClient>, Error> {
+    let Some(i) = args.iter().position(|arg| arg == "--electrum") else {
+        return Ok(None);
+    };
+    if i + 1 >= args.len() {
+        return Err(Error::Parse("--electrum needs host:port".into()));
+    }
+    let addr = args.remove(i + 1);
+    args.remove(i);
+    Ok(Some(ElectrumClient::new(&addr)?))
+}
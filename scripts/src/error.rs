@@ -0,0 +1,106 @@
+//! Error type returned by every library function. The binaries keep
+//! `Box<dyn std::error::Error>` in `main`, which `?` converts into.
+
+use std::fmt;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// A bitcoind RPC call failed.
+    Rpc(bitcoincore_rpc::Error),
+    /// The node (or the mock chain) rejected a transaction or does not know
+    /// what was asked for.
+    Node(String),
+    /// An output or transaction would not be relayed: dust, fees that
+    /// overflow, values past `u64`.
+    Policy(String),
+    /// The available value cannot pay the outputs and fees.
+    InsufficientValue { needed: u64, available: u64 },
+    /// A template, tree or tree path that cannot be built or followed.
+    InvalidTemplate(String),
+    /// Taproot trees, control blocks, key derivation, tweaks and signatures.
+    Taproot(String),
+    /// Share ledgers, payout schemes and share trades.
+    Payout(String),
+    /// Malformed input: addresses, hex, transactions, numbers, arguments.
+    Parse(String),
+    Io(std::io::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Rpc(e) => write!(f, "RPC error: {e}"),
+            Error::InsufficientValue { needed, available } => {
+                write!(f, "needs {needed} sat but only {available} sat is available")
+            }
+            Error::Node(msg)
+            | Error::Policy(msg)
+            | Error::InvalidTemplate(msg)
+            | Error::Taproot(msg)
+            | Error::Payout(msg)
+            | Error::Parse(msg) => f.write_str(msg),
+            Error::Io(e) => write!(f, "{e}"),
+            Error::Json(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Rpc(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<bitcoincore_rpc::Error> for Error {
+    fn from(e: bitcoincore_rpc::Error) -> Self {
+        Error::Rpc(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+/// Maps library errors that all mean the same thing here onto one variant.
+macro_rules! from_as {
+    ($variant:ident: $($source:ty),+ $(,)?) => {
+        $(impl From<$source> for Error {
+            fn from(e: $source) -> Self {
+                Error::$variant(e.to_string())
+            }
+        })+
+    };
+}
+
+from_as!(Taproot:
+    bitcoin::secp256k1::Error,
+    bitcoin::taproot::TaprootBuilderError,
+    bitcoin::taproot::TaprootError,
+    bitcoin::sighash::Error,
+    bitcoin::sighash::InvalidSighashTypeError,
+    bitcoin::bip32::Error,
+);
+from_as!(Parse:
+    bitcoin::address::Error,
+    bitcoin::address::ParseError,
+    bitcoin::consensus::encode::Error,
+    bitcoin::hashes::hex::HexToArrayError,
+    hex::FromHexError,
+    std::num::ParseIntError,
+);
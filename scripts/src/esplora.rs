@@ -0,0 +1,136 @@
+//! `ChainSource` over an Esplora REST API, such as the electrs the devenv
+//! serves on port 3000. Esplora indexes spends, so walking a tree costs one
+//! request per node instead of bitcoind's block scans.
+//!
+//! Requests go through `ureq`, so both `http://` and `https://` instances
+//! work, such as a local electrs or a public mempool.space or blockstream.info.
+
+use std::io::Read;
+use std::time::Duration;
+
+use bitcoin::{consensus::encode::serialize_hex, Transaction, Txid};
+use serde::Deserialize;
+use ureq::{Agent, AgentBuilder};
+
+use crate::chain::{ChainSource, TxStatus};
+use crate::error::Error;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An Esplora API at a base URL like `http://127.0.0.1:3000` or
+/// `https://mempool.space/api`.
+pub struct EsploraClient {
+    agent: Agent,
+    /// Base URL without a trailing slash.
+    base: String,
+}
+
+#[derive(Deserialize)]
+struct Status {
+    confirmed: bool,
+    block_height: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct Outspend {
+    spent: bool,
+    txid: Option<Txid>,
+}
+
+impl EsploraClient {
+    pub fn new(url: &str) -> Result<Self, Error> {
+        let rest = url
+            .strip_prefix("http://")
+            .or_else(|| url.strip_prefix("https://"))
+            .ok_or_else(|| Error::Parse(format!("Esplora URL {url:?} must start with http:// or https://")))?;
+        if rest.split('/').next().unwrap_or_default().is_empty() {
+            return Err(Error::Parse(format!("Esplora URL {url:?} has no host")));
+        }
+        let agent = AgentBuilder::new().timeout_connect(CONNECT_TIMEOUT).timeout(TIMEOUT).build();
+        Ok(EsploraClient { agent, base: url.trim_end_matches('/').to_string() })
+    }
+
+    /// The body of a successful `GET`, or `None` on 404.
+    fn get(&self, path: &str) -> Result<Option<Vec<u8>>, Error> {
+        match self.agent.get(&format!("{}{path}", self.base)).call() {
+            Ok(response) => Ok(Some(read_body(response)?)),
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(e) => Err(http_error(path, e)),
+        }
+    }
+
+    fn get_json<T: for<'de> Deserialize<'de>>(&self, path: &str) -> Result<Option<T>, Error> {
+        self.get(path)?.map(|body| serde_json::from_slice(&body).map_err(Error::from)).transpose()
+    }
+}
+
+impl ChainSource for EsploraClient {
+    fn fetch_transaction(&self, txid: &Txid) -> Result<Option<Transaction>, Error> {
+        let Some(body) = self.get(&format!("/tx/{txid}/raw"))? else {
+            return Ok(None);
+        };
+        Ok(Some(bitcoin::consensus::deserialize(&body)?))
+    }
+
+    fn transaction_status(&self, txid: &Txid) -> Result<TxStatus, Error> {
+        let Some(status) = self.get_json::<Status>(&format!("/tx/{txid}/status"))? else {
+            return Ok(TxStatus::Unbroadcast);
+        };
+        match (status.confirmed, status.block_height) {
+            (true, Some(height)) => Ok(TxStatus::Confirmed { height }),
+            (true, None) => Err(Error::Node(format!("Esplora reports {txid} confirmed without a height"))),
+            (false, _) => Ok(TxStatus::Mempool),
+        }
+    }
+
+    fn outspends(&self, txid: &Txid) -> Result<Vec<Option<Txid>>, Error> {
+        let outspends: Vec<Outspend> = self.get_json(&format!("/tx/{txid}/outspends"))?.unwrap_or_default();
+        Ok(outspends.into_iter().map(|o| o.txid.filter(|_| o.spent)).collect())
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<Txid, Error> {
+        let response = self
+            .agent
+            .post(&format!("{}/tx", self.base))
+            .set("Content-Type", "text/plain")
+            .send_string(&serialize_hex(tx))
+            .map_err(|e| http_error("/tx", e))?;
+        Ok(String::from_utf8_lossy(&read_body(response)?).trim().parse()?)
+    }
+
+    fn tip_height(&self) -> Result<u64, Error> {
+        let body = self.get("/blocks/tip/height")?.ok_or_else(|| Error::Node("Esplora has no tip".into()))?;
+        Ok(String::from_utf8_lossy(&body).trim().parse()?)
+    }
+}
+
+/// Removes `--esplora <url>` from the arguments, leaving the positional ones.
+pub fn take_esplora_arg(args: &mut Vec<String>) -> Result<Option<EsploraClient>, Error> {
+    let Some(i) = args.iter().position(|arg| arg == "--esplora") else {
+        return Ok(None);
+    };
+    if i + 1 >= args.len() {
+        return Err(Error::Parse("--esplora needs a URL".into()));
+    }
+    let url = args.remove(i + 1);
+    args.remove(i);
+    Ok(Some(EsploraClient::new(&url)?))
+}
+
+fn read_body(response: ureq::Response) -> Result<Vec<u8>, Error> {
+    let mut body = vec![];
+    response.into_reader().read_to_end(&mut body)?;
+    Ok(body)
+}
+
+/// Rejections keep the body, which carries the node's reason.
+fn http_error(path: &str, e: ureq::Error) -> Error {
+    match e {
+        ureq::Error::Status(status, response) => {
+            let body = response.into_string().unwrap_or_default();
+            Error::Node(format!("Esplora {path} returned {status}: {}", body.trim()))
+        }
+        ureq::Error::Transport(e) => Error::Node(format!("Esplora {path}: {e}")),
+    }
+}
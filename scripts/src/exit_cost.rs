@@ -0,0 +1,162 @@
+//! What it costs leaves of a CTV tree to exit: every ancestor transaction has
+//! to confirm before a leaf's payout exists on chain, and leaves claiming at
+//! the same time can share the ancestors they have in common.
+
+use bitcoin::OutPoint;
+
+use crate::ctv::is_anchor;
+use crate::error::Error;
+use crate::tree::TreeNode;
+
+/// One payout output and the chain of tree transactions that creates it.
+#[derive(Clone, Debug)]
+pub struct LeafExit {
+    pub outpoint: OutPoint,
+    /// Vouts from the root down to this output.
+    pub path: Vec<u32>,
+    pub value: u64,
+    /// Tree transactions to broadcast, including the one paying the leaf.
+    pub ancestors: usize,
+    pub vbytes: usize,
+}
+
+impl LeafExit {
+    /// Fee for confirming every ancestor at `fee_rate` sat/vB.
+    pub fn cost(&self, fee_rate: u64) -> u64 {
+        (self.vbytes as u64).saturating_mul(fee_rate)
+    }
+
+    /// Whether the payout is worth more than unrolling it at `fee_rate`.
+    pub fn is_economical(&self, fee_rate: u64) -> bool {
+        self.value > self.cost(fee_rate)
+    }
+}
+
+/// Every non-anchor leaf output of the tree, in tree order.
+pub fn leaf_exits(root: &TreeNode) -> Vec<LeafExit> {
+    let mut exits = vec![];
+    collect_exits(root, &mut vec![], 0, 0, &mut exits);
+    exits
+}
+
+fn collect_exits(
+    node: &TreeNode,
+    path: &mut Vec<u32>,
+    ancestors: usize,
+    vbytes: usize,
+    exits: &mut Vec<LeafExit>,
+) {
+    let ancestors = ancestors + 1;
+    let vbytes = vbytes + node.vsize();
+    let txid = node.txid();
+
+    for (vout, output) in node.tx.output.iter().enumerate() {
+        let vout = vout as u32;
+        path.push(vout);
+        match node.child(vout) {
+            Some(child) => collect_exits(child, path, ancestors, vbytes, exits),
+            None if !is_anchor(&output.script_pubkey) => exits.push(LeafExit {
+                outpoint: OutPoint { txid, vout },
+                path: path.clone(),
+                value: output.value.to_sat(),
+                ancestors,
+                vbytes,
+            }),
+            None => {}
+        }
+        path.pop();
+    }
+}
+
+/// A claiming leaf's share of the transactions broadcast for a group unroll.
+#[derive(Clone, Debug)]
+pub struct ClaimShare {
+    pub exit: LeafExit,
+    /// Vbytes charged to this leaf: each transaction's size split evenly
+    /// between the claiming leaves beneath it, the last of them taking the
+    /// odd vbytes so that the shares add up to the plan's total.
+    pub vbytes: usize,
+}
+
+impl ClaimShare {
+    pub fn cost(&self, fee_rate: u64) -> u64 {
+        (self.vbytes as u64).saturating_mul(fee_rate)
+    }
+
+    /// What sharing ancestors saves this leaf compared to exiting alone.
+    pub fn savings(&self, fee_rate: u64) -> u64 {
+        self.exit.cost(fee_rate).saturating_sub(self.cost(fee_rate))
+    }
+}
+
+/// Transactions to broadcast so that a set of leaves can claim together.
+#[derive(Clone, Debug)]
+pub struct ClaimPlan {
+    /// Path, vsize and number of claimers below each transaction, parents
+    /// before children.
+    pub transactions: Vec<(Vec<u32>, usize, usize)>,
+    pub shares: Vec<ClaimShare>,
+}
+
+impl ClaimPlan {
+    pub fn vbytes(&self) -> usize {
+        self.transactions.iter().map(|(_, vsize, _)| vsize).sum()
+    }
+}
+
+/// Smallest set of tree transactions that creates every leaf in `claims`
+/// (given as paths from `leaf_exits`), with fees split among the claimers
+/// that share each transaction.
+pub fn plan_claims(root: &TreeNode, claims: &[Vec<u32>]) -> Result<ClaimPlan, Error> {
+    let exits = leaf_exits(root);
+    let mut claimed = vec![];
+    for claim in claims {
+        let exit = exits
+            .iter()
+            .find(|exit| exit.path == *claim)
+            .ok_or_else(|| Error::InvalidTemplate(format!("no payout leaf at path {claim:?}")))?;
+        if !claimed.iter().any(|c: &&LeafExit| c.path == exit.path) {
+            claimed.push(exit);
+        }
+    }
+
+    let mut transactions = vec![];
+    collect_claim_txs(root, &mut vec![], &claimed, &mut transactions);
+
+    let mut shares: Vec<ClaimShare> = claimed
+        .iter()
+        .map(|exit| ClaimShare { exit: (*exit).clone(), vbytes: 0 })
+        .collect();
+    for (path, vsize, claimers) in &transactions {
+        let beneath: Vec<usize> = (0..shares.len())
+            .filter(|i| shares[*i].exit.path.starts_with(path))
+            .collect();
+        for i in &beneath {
+            shares[*i].vbytes += vsize / claimers;
+        }
+        if let Some(last) = beneath.last() {
+            shares[*last].vbytes += vsize % claimers;
+        }
+    }
+
+    Ok(ClaimPlan { transactions, shares })
+}
+
+fn collect_claim_txs(
+    node: &TreeNode,
+    path: &mut Vec<u32>,
+    claimed: &[&LeafExit],
+    transactions: &mut Vec<(Vec<u32>, usize, usize)>,
+) {
+    // A transaction is needed if any claimed output sits below it
+    let claimers = claimed.iter().filter(|exit| exit.path.starts_with(path)).count();
+    if claimers == 0 {
+        return;
+    }
+    transactions.push((path.clone(), node.vsize(), claimers));
+    for (vout, child) in &node.children {
+        path.push(*vout);
+        collect_claim_txs(child, path, claimed, transactions);
+        path.pop();
+    }
+}
@@ -0,0 +1,97 @@
+use std::env;
+use std::path::PathBuf;
+
+use bitcoin::{OutPoint, Txid};
+use scripts::{
+    chain::{take_source_arg, ChainSource},
+    explorer::{explore, ExploredNode, ExploredOutput, OutputKind},
+    rpc::connect,
+    tree::{load_unroll_data, unroll_path},
+};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args: Vec<String> = env::args().collect();
+    let source = take_source_arg(&mut args)?;
+
+    let start_str = args.get(1).cloned().expect("txid or txid:vout required");
+    let start: OutPoint = match start_str.split_once(':') {
+        Some(_) => start_str.parse()?,
+        None => OutPoint { txid: start_str.parse::<Txid>()?, vout: 0 },
+    };
+
+    // Unroll data lets us show branches that were never broadcast
+    let unroll_file = args
+        .get(2)
+        .map(PathBuf::from)
+        .or_else(|| Some(unroll_path(&start.txid)).filter(|p| p.exists()));
+    let unroll = match &unroll_file {
+        Some(path) => {
+            println!("Using unroll data from {}", path.display());
+            Some(load_unroll_data(path)?)
+        }
+        None => None,
+    };
+
+    let source: Box<dyn ChainSource> = match source {
+        Some(source) => source,
+        None => Box::new(connect(None)?),
+    };
+    let root = explore(source.as_ref(), start, unroll.as_ref())?;
+
+    println!();
+    print_output(&root, "", "");
+
+    let unclaimed = root.child.as_ref().map(ExploredNode::unclaimed_leaves).unwrap_or_default();
+    let unclaimed_value: u64 = unclaimed.iter().map(|o| o.value).sum();
+    println!();
+    println!("Remaining leaves to claim: {} ({} sat)", unclaimed.len(), unclaimed_value);
+    if unroll.is_none() && unclaimed.iter().any(|output| output.kind == OutputKind::Unknown) {
+        println!("Outputs marked p2tr? may hide a CTV leaf in their taproot tree; pass the unroll data to tell");
+    }
+
+    Ok(())
+}
+
+fn print_output(output: &ExploredOutput, first_prefix: &str, rest_prefix: &str) {
+    let spend = match (&output.spent_by, &output.child) {
+        (Some(txid), _) => format!("spent by {txid}"),
+        (None, Some(_)) => "unspent, unroll pending".to_string(),
+        (None, None) => "unspent".to_string(),
+    };
+    println!(
+        "{first_prefix}{} {:<6} {:>13} sat  {spend}",
+        output.outpoint,
+        kind_label(output.kind),
+        output.value,
+    );
+
+    if let Some(child) = &output.child {
+        print_node(child, &format!("{rest_prefix}└─ "), &format!("{rest_prefix}   "));
+    }
+}
+
+fn print_node(node: &ExploredNode, first_prefix: &str, rest_prefix: &str) {
+    println!(
+        "{first_prefix}tx {}  {}  fee {} sat",
+        node.tx.txid(),
+        node.status,
+        node.fee,
+    );
+
+    for (i, output) in node.outputs.iter().enumerate() {
+        if i + 1 == node.outputs.len() {
+            print_output(output, &format!("{rest_prefix}└─ "), &format!("{rest_prefix}   "));
+        } else {
+            print_output(output, &format!("{rest_prefix}├─ "), &format!("{rest_prefix}│  "));
+        }
+    }
+}
+
+fn kind_label(kind: OutputKind) -> &'static str {
+    match kind {
+        OutputKind::Anchor => "anchor",
+        OutputKind::Ctv => "ctv",
+        OutputKind::Payout => "payout",
+        OutputKind::Unknown => "p2tr?",
+    }
+}
@@ -0,0 +1,170 @@
+use bitcoin::{OutPoint, Script, Transaction, Txid};
+
+pub use crate::chain::TxStatus;
+use crate::chain::ChainSource;
+use crate::ctv::{ctv_hash_from_script, is_anchor};
+use crate::error::Error;
+use crate::tree::TreeNode;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputKind {
+    Anchor,
+    Ctv,
+    Payout,
+    /// An unspent P2TR output seen without unroll data: it may be a payout or
+    /// hide a CTV leaf in its taproot tree, and only a script path spend or
+    /// the unroll data can tell.
+    Unknown,
+}
+
+/// An output in the explored tree and, if anything spends it (or is meant to
+/// per the unroll data), the node that does.
+#[derive(Clone, Debug)]
+pub struct ExploredOutput {
+    pub outpoint: OutPoint,
+    pub value: u64,
+    pub kind: OutputKind,
+    pub spent_by: Option<Txid>,
+    pub child: Option<ExploredNode>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ExploredNode {
+    pub tx: Transaction,
+    pub fee: u64,
+    pub status: TxStatus,
+    pub outputs: Vec<ExploredOutput>,
+}
+
+impl ExploredNode {
+    /// Payout (or possibly payout) outputs whose creating transaction has not
+    /// confirmed yet.
+    pub fn unclaimed_leaves(&self) -> Vec<&ExploredOutput> {
+        let mut leaves = vec![];
+        for output in &self.outputs {
+            let payout = matches!(output.kind, OutputKind::Payout | OutputKind::Unknown);
+            if payout && !matches!(self.status, TxStatus::Confirmed { .. }) {
+                leaves.push(output);
+            }
+            if let Some(child) = &output.child {
+                leaves.extend(child.unclaimed_leaves());
+            }
+        }
+        leaves
+    }
+}
+
+/// Walks the tree rooted at `start`, following spends on chain. `unroll` is the
+/// node expected to spend `start`; it fills in branches nobody has broadcast yet.
+pub fn explore<S: ChainSource + ?Sized>(
+    source: &S,
+    start: OutPoint,
+    unroll: Option<&TreeNode>,
+) -> Result<ExploredOutput, Error> {
+    let tx = source
+        .fetch_transaction(&start.txid)?
+        .ok_or_else(|| Error::Node(format!("transaction {} not found", start.txid)))?;
+    let output = tx.output.get(start.vout as usize).ok_or_else(|| Error::InvalidTemplate(format!("vout {} out of range", start.vout)))?;
+    let kind = if unroll.is_some() {
+        OutputKind::Ctv
+    } else {
+        unhinted_kind(&output.script_pubkey)
+    };
+    let spent_by = source.outspends(&start.txid)?.get(start.vout as usize).copied().flatten();
+    explore_output(source, start, output.value.to_sat(), kind, spent_by, unroll)
+}
+
+fn explore_output<S: ChainSource + ?Sized>(
+    source: &S,
+    outpoint: OutPoint,
+    value: u64,
+    mut kind: OutputKind,
+    spent_by: Option<Txid>,
+    unroll: Option<&TreeNode>,
+) -> Result<ExploredOutput, Error> {
+    let child = match spent_by {
+        Some(txid) => {
+            let tx = source
+                .fetch_transaction(&txid)?
+                .ok_or_else(|| Error::Node(format!("spending transaction {txid} not found")))?;
+            let status = source.transaction_status(&txid)?;
+            let hint = unroll.filter(|node| node.txid() == txid);
+            if kind == OutputKind::Unknown {
+                kind = revealed_kind(&tx, outpoint);
+            }
+            Some(explore_node(source, tx, value, status, hint)?)
+        }
+        None => match unroll {
+            Some(node) => Some(explore_node(source, node.tx.clone(), value, TxStatus::Unbroadcast, Some(node))?),
+            None => None,
+        },
+    };
+
+    Ok(ExploredOutput { outpoint, value, kind, spent_by, child })
+}
+
+fn explore_node<S: ChainSource + ?Sized>(
+    source: &S,
+    tx: Transaction,
+    input_value: u64,
+    status: TxStatus,
+    hint: Option<&TreeNode>,
+) -> Result<ExploredNode, Error> {
+    let txid = tx.txid();
+    let fee = input_value.saturating_sub(tx.output.iter().map(|o| o.value.to_sat()).sum());
+
+    // Nothing can spend a transaction nobody has broadcast
+    let spends = match status {
+        TxStatus::Unbroadcast => vec![],
+        _ => source.outspends(&txid)?,
+    };
+
+    let mut outputs = vec![];
+    for (vout, output) in tx.output.iter().enumerate() {
+        let child_hint = hint.and_then(|node| node.child(vout as u32));
+        let kind = if is_anchor(&output.script_pubkey) {
+            OutputKind::Anchor
+        } else if child_hint.is_some() || ctv_hash_from_script(&output.script_pubkey).is_some() {
+            OutputKind::Ctv
+        } else if hint.is_some() {
+            OutputKind::Payout
+        } else {
+            unhinted_kind(&output.script_pubkey)
+        };
+        outputs.push(explore_output(
+            source,
+            OutPoint { txid, vout: vout as u32 },
+            output.value.to_sat(),
+            kind,
+            spends.get(vout).copied().flatten(),
+            child_hint,
+        )?);
+    }
+
+    Ok(ExploredNode { tx, fee, status, outputs })
+}
+
+/// Kind of an output with no unroll data to go by. A bare `<hash> OP_CTV` is
+/// recognisable, but a P2TR output only shows its tweaked key.
+fn unhinted_kind(script_pubkey: &Script) -> OutputKind {
+    if ctv_hash_from_script(script_pubkey).is_some() {
+        OutputKind::Ctv
+    } else if script_pubkey.is_p2tr() {
+        OutputKind::Unknown
+    } else {
+        OutputKind::Payout
+    }
+}
+
+/// Kind of a P2TR output from the input of `spender` that spends it: a script
+/// path spend reveals its leaf, a key path spend was a payout being claimed.
+fn revealed_kind(spender: &Transaction, outpoint: OutPoint) -> OutputKind {
+    let Some(input) = spender.input.iter().find(|input| input.previous_output == outpoint) else {
+        return OutputKind::Unknown;
+    };
+    match input.witness.tapscript() {
+        Some(leaf) if ctv_hash_from_script(leaf).is_some() => OutputKind::Ctv,
+        Some(_) => OutputKind::Unknown,
+        None => OutputKind::Payout,
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt::Write;
+
+use crate::ctv::is_anchor;
+use crate::tree::TreeNode;
+
+/// Number of hex characters of txids and CTV hashes shown in node labels.
+const PREFIX_LEN: usize = 16;
+
+/// Graphviz DOT graph with one box per tree transaction and one ellipse per
+/// payout or anchor output.
+pub fn to_dot(root: &TreeNode) -> String {
+    let mut out = String::from("digraph ctv_tree {\n    rankdir=TB;\n    node [fontname=\"monospace\"];\n");
+    write_dot_node(&mut out, root, "n");
+    out.push_str("}\n");
+    out
+}
+
+fn write_dot_node(out: &mut String, node: &TreeNode, id: &str) {
+    writeln!(out, "    {id} [shape=box, label=\"{}\"];", node_label(node, "\\n")).unwrap();
+
+    for (vout, output) in node.tx.output.iter().enumerate() {
+        let child_id = format!("{id}_{vout}");
+        match node.child(vout as u32) {
+            Some(child) => write_dot_node(out, child, &child_id),
+            None => {
+                let kind = if is_anchor(&output.script_pubkey) { "anchor" } else { "payout" };
+                writeln!(
+                    out,
+                    "    {child_id} [shape=ellipse, label=\"{kind}\\n{} sat\"];",
+                    output.value.to_sat()
+                )
+                .unwrap();
+            }
+        }
+        writeln!(out, "    {id} -> {child_id} [label=\"{vout}\"];").unwrap();
+    }
+}
+
+/// Mermaid flowchart of the same shape as `to_dot`, for pasting into markdown.
+pub fn to_mermaid(root: &TreeNode) -> String {
+    let mut out = String::from("flowchart TD\n");
+    write_mermaid_node(&mut out, root, "n");
+    out
+}
+
+fn write_mermaid_node(out: &mut String, node: &TreeNode, id: &str) {
+    writeln!(out, "    {id}[\"{}\"]", node_label(node, "<br/>")).unwrap();
+
+    for (vout, output) in node.tx.output.iter().enumerate() {
+        let child_id = format!("{id}_{vout}");
+        match node.child(vout as u32) {
+            Some(child) => write_mermaid_node(out, child, &child_id),
+            None => {
+                let kind = if is_anchor(&output.script_pubkey) { "anchor" } else { "payout" };
+                writeln!(out, "    {child_id}([\"{kind}<br/>{} sat\"])", output.value.to_sat()).unwrap();
+            }
+        }
+        writeln!(out, "    {id} -->|{vout}| {child_id}").unwrap();
+    }
+}
+
+fn node_label(node: &TreeNode, newline: &str) -> String {
+    let txid = node.txid().to_string();
+    let ctv_hash = hex::encode(node.ctv_hash());
+    [
+        format!("txid {}", &txid[..PREFIX_LEN]),
+        format!("value {} sat", node.input_value),
+        format!("fee {} sat", node.fee()),
+        format!("vsize {} vB", node.vsize()),
+        format!("ctv {}", &ctv_hash[..PREFIX_LEN]),
+    ]
+    .join(newline)
+}
@@ -0,0 +1,27 @@
+use std::env;
+use std::path::PathBuf;
+
+use bitcoin::Txid;
+use scripts::{
+    export::{to_dot, to_mermaid},
+    tree::{load_unroll_data, unroll_path},
+};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let source = env::args().nth(1).expect("coinbase txid or unroll file required");
+    let format = env::args().nth(2).unwrap_or("dot".to_string());
+
+    let path = match source.parse::<Txid>() {
+        Ok(txid) => unroll_path(&txid),
+        Err(_) => PathBuf::from(source),
+    };
+    let root = load_unroll_data(&path)?;
+
+    match format.as_str() {
+        "dot" => print!("{}", to_dot(&root)),
+        "mermaid" => print!("{}", to_mermaid(&root)),
+        other => return Err(format!("unknown format {other}, expected dot or mermaid").into()),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,85 @@
+//! The mine-and-spend steps the mining scripts share. Everything goes through
+//! `NodeRpc`, so the binaries run these against bitcoind and the tests run the
+//! same code against the mock chain.
+
+use bitcoin::{
+    Address, OutPoint, Transaction, Txid,
+    key::Secp256k1,
+    script::ScriptBuf,
+    secp256k1::All,
+    taproot::TaprootSpendInfo,
+    XOnlyPublicKey,
+};
+
+use crate::contract::{finalize_ctv_spend, split_evenly};
+use crate::ctv::{build_ctv_script, ctv_spend_info};
+use crate::error::Error;
+use crate::rpc::NodeRpc;
+use crate::tree::TreeNode;
+
+/// Blocks on top of a coinbase before it can be spent.
+pub const COINBASE_MATURITY: u64 = 100;
+
+/// Mines a block to `address` and returns its coinbase value, which sizes
+/// the contract the next block commits to.
+pub fn mine_coinbase_value<R: NodeRpc + ?Sized>(rpc: &R, address: &Address) -> Result<u64, Error> {
+    let block = rpc.generate_to_address(1, address)?[0];
+    Ok(rpc.get_block(&block)?.txdata[0].output[0].value.to_sat())
+}
+
+/// Mines a block paying its coinbase to `address` and matures it with blocks
+/// paying `miner`. Returns the coinbase txid.
+pub fn mine_and_mature<R: NodeRpc + ?Sized>(rpc: &R, address: &Address, miner: &Address) -> Result<Txid, Error> {
+    let block = rpc.generate_to_address(1, address)?[0];
+    let coinbase_txid = rpc.get_block(&block)?.txdata[0].txid();
+    rpc.generate_to_address(COINBASE_MATURITY, miner)?;
+    Ok(coinbase_txid)
+}
+
+/// Mines the coinbase into a flat contract from `build_ctv_contract`, matures
+/// it and fills in the spend's prevout and witness. Returns the coinbase
+/// txid; the spend is ready to broadcast.
+pub fn mine_ctv_contract<R: NodeRpc + ?Sized>(
+    rpc: &R,
+    taproot_info: &TaprootSpendInfo,
+    ctv_address: &Address,
+    spend_tx: &mut Transaction,
+    ctv_script: &ScriptBuf,
+    miner: &Address,
+) -> Result<Txid, Error> {
+    let coinbase_txid = mine_and_mature(rpc, ctv_address, miner)?;
+    finalize_ctv_spend(spend_tx, coinbase_txid, taproot_info, ctv_script)?;
+    Ok(coinbase_txid)
+}
+
+/// Commits the coinbase to the root of a CTV tree, mines and matures it, then
+/// adds the root's script-path witness and binds every node to its parent.
+/// Returns the coinbase txid; the tree is ready for `broadcast_by_level`.
+pub fn mine_ctv_tree<R: NodeRpc + ?Sized>(
+    rpc: &R,
+    secp: &Secp256k1<All>,
+    xonly: XOnlyPublicKey,
+    root: &mut TreeNode,
+    miner: &Address,
+) -> Result<Txid, Error> {
+    let root_script = build_ctv_script(&root.tx.output);
+    let taproot = ctv_spend_info(secp, xonly, &root_script, None)?;
+    let tap_address = Address::p2tr_tweaked(taproot.output_key(), rpc.network()?);
+    println!("Mining to: {tap_address}");
+
+    let coinbase_txid = mine_and_mature(rpc, &tap_address, miner)?;
+    finalize_ctv_spend(&mut root.tx, coinbase_txid, &taproot, &root_script)?;
+    root.bind(OutPoint { txid: coinbase_txid, vout: 0 });
+    Ok(coinbase_txid)
+}
+
+/// Default payouts of the layered tree: four equal leaves of a binary tree
+/// whose three nodes each pay `node_fee` out of `value`, the last leaf taking
+/// the remainder so the root spends exactly `value`.
+pub fn layered_leaf_values(value: u64, node_fee: u64) -> Result<Vec<u64>, Error> {
+    let fees = node_fee.saturating_mul(3);
+    let spendable = value
+        .checked_sub(fees)
+        .ok_or(Error::InsufficientValue { needed: fees, available: value })?;
+    Ok(split_evenly(spendable, 4))
+}
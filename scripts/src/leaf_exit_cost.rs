@@ -0,0 +1,51 @@
+use std::env;
+use std::path::PathBuf;
+
+use bitcoin::Txid;
+use scripts::{
+    exit_cost::leaf_exits,
+    tree::{load_unroll_data, unroll_path},
+};
+
+const DEFAULT_FEE_RATES: &str = "1,10,50";
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let source = env::args().nth(1).expect("coinbase txid or unroll file required");
+    let fee_rates: Vec<u64> = env::args()
+        .nth(2)
+        .unwrap_or(DEFAULT_FEE_RATES.to_string())
+        .split(',')
+        .map(str::parse)
+        .collect::<Result<_, _>>()?;
+
+    let path = match source.parse::<Txid>() {
+        Ok(txid) => unroll_path(&txid),
+        Err(_) => PathBuf::from(source),
+    };
+    let root = load_unroll_data(&path)?;
+
+    print!("{:<12} {:>14} {:>5} {:>7}", "leaf", "value", "txs", "vbytes");
+    for rate in &fee_rates {
+        print!(" {:>14}", format!("@{rate} sat/vB"));
+    }
+    println!();
+
+    let mut uneconomical = 0;
+    for exit in leaf_exits(&root) {
+        let path: Vec<String> = exit.path.iter().map(u32::to_string).collect();
+        print!("{:<12} {:>14} {:>5} {:>7}", path.join("."), exit.value, exit.ancestors, exit.vbytes);
+        for rate in &fee_rates {
+            let flag = if exit.is_economical(*rate) { " " } else { "!" };
+            print!(" {:>13}{}", exit.cost(*rate), flag);
+        }
+        println!();
+        if fee_rates.iter().any(|rate| !exit.is_economical(*rate)) {
+            uneconomical += 1;
+        }
+    }
+
+    println!();
+    println!("{uneconomical} leaves cost more to unroll than they pay at one of the feerates (marked !)");
+
+    Ok(())
+}
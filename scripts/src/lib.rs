@@ -0,0 +1,24 @@
+pub mod balances;
+pub mod builder;
+pub mod chain;
+pub mod collapse;
+pub mod contract;
+pub mod csfs;
+pub mod electrum;
+pub mod error;
+pub mod esplora;
+pub mod ctv;
+pub mod explorer;
+pub mod exit_cost;
+pub mod export;
+pub mod flow;
+pub mod mock;
+pub mod musig;
+pub mod musig_tree;
+pub mod rpc;
+pub mod seed;
+pub mod shares;
+pub mod signet;
+pub mod taproot;
+pub mod tree;
+pub mod watchtower;
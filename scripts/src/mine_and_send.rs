@@ -1,13 +1,12 @@
-use std::path::Path;
-use bitcoincore_rpc::{Auth, Client, RpcApi};
-use bitcoincore_rpc::bitcoin::{Address, Amount, Network};
+use bitcoincore_rpc::RpcApi;
+use bitcoincore_rpc::bitcoin::{Address, Amount};
+use scripts::{
+    rpc::{connect, NodeRpc},
+    signet::signet_miner,
+};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let rpc = Client::new(
-        "http://127.0.0.1:18443",
-        Auth::CookieFile(Path::new("./data/regtest/.cookie").to_path_buf()),
-    )?;
-    
+    let rpc = connect(None)?;
     let wallet_name = "devwallet";
 
     // create and load wallet
@@ -24,15 +23,21 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
+    // On our custom signet blocks are signed and mined here instead of by generatetoaddress
+    let signet = signet_miner(&rpc)?;
+    let node: &dyn NodeRpc = match &signet {
+        Some(miner) => miner,
+        None => &rpc,
+    };
+
     // Check spendable balance
     let balance = rpc.get_balance(None, None)?;
     if balance < Amount::from_btc(1.0)? {
         // Get mining address
-        let mining_addr_unchecked = rpc.get_new_address(None, None)?;
-        let mining_addr: Address = mining_addr_unchecked.require_network(Network::Regtest)?;
+        let mining_addr: Address = rpc.new_address()?;
 
         // Mine 101 blocks to mature coinbase
-        let blocks = rpc.generate_to_address(101, &mining_addr)?;
+        let blocks = node.generate_to_address(101, &mining_addr)?;
         println!("Generated {} blocks to reach spendable balance", blocks.len());
     }
 
@@ -41,8 +46,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("Confirmed balance: {} BTC", balance.to_btc());
 
     // Get a recipient address
-    let dest_addr_unchecked = rpc.get_new_address(None, None)?;
-    let dest_addr: Address = dest_addr_unchecked.require_network(Network::Regtest)?;
+    let dest_addr: Address = rpc.new_address()?;
     println!("Sending to address: {}", dest_addr);
 
     // Send funds
@@ -55,9 +59,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("Sent 1.0 BTC, txid: {}", txid);
 
     // Mine a block to confirm
-    let mining_addr_unchecked = rpc.get_new_address(None, None)?;
-    let mining_addr: Address = mining_addr_unchecked.require_network(Network::Regtest)?;
-    rpc.generate_to_address(1, &mining_addr)?;
+    let mining_addr: Address = rpc.new_address()?;
+    node.generate_to_address(1, &mining_addr)?;
     println!("Confirmed transaction in new block.");
 
     Ok(())
@@ -1,180 +1,125 @@
-use std::path::Path;
-
-use bitcoincore_rpc::{Auth, Client, RpcApi};
 use bitcoin::{
-    Address, Amount, Network, Transaction, TxOut, TxIn, OutPoint,
-    consensus::{Encodable, encode::serialize_hex},
-    hashes::{sha256, Hash},
-    key::{Keypair, Secp256k1},
-    script::{Builder, ScriptBuf},
-    taproot::{TaprootBuilder, LeafVersion},
-    Opcode, XOnlyPublicKey, Sequence, Witness,
+    Amount, TxOut,
+    consensus::encode::serialize_hex,
+    key::Secp256k1,
+    XOnlyPublicKey,
+};
+use scripts::{
+    builder::{build_dust_aware_tree, value_weighted_vbytes, DustHandling, DustPolicy, TreeShape},
+    contract::{broadcast_by_level, build_leaf_outputs},
+    error::Error,
+    flow::{layered_leaf_values, mine_coinbase_value, mine_ctv_tree},
+    rpc::{connect, ensure_wallet, NodeRpc},
+    seed::{new_keypair, take_seed_arg, SeededRpc},
+    signet::signet_miner,
+    tree::{save_unroll_data, unroll_path},
 };
 
-use bitcoin::opcodes::all::OP_NOP4;
-
-const OP_CTV: Opcode = OP_NOP4;
-const CHILD_FEE: u64 = 500;
-const ROOT_FEE: u64 = 500;
+const NODE_FEE: u64 = 500;
+const RADIX: usize = 2;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let rpc = Client::new(
-        "http://127.0.0.1:18443",
-        Auth::CookieFile(Path::new("./data/regtest/.cookie").to_path_buf()),
-    )?;
-
-    ensure_wallet(&rpc, "devwallet")?;
-
-    let secp = Secp256k1::new();
-    let keypair = Keypair::new(&secp, &mut rand::thread_rng());
-    let (xonly, _) = XOnlyPublicKey::from_keypair(&keypair);
+    let mut args: Vec<String> = std::env::args().collect();
+    let seed = take_seed_arg(&mut args)?;
 
-    let dummy_addr = rpc.get_new_address(None, None)?.require_network(Network::Regtest)?;
-    let cb_block = rpc.generate_to_address(1, &dummy_addr)?[0];
-    let cb_txid = rpc.get_block(&cb_block)?.txdata[0].txid();
-    let cb_tx: Transaction = rpc.get_raw_transaction(&cb_txid, None)?;
-    let cb_value = cb_tx.output[0].value.to_sat();
-
-    let spendable = cb_value - ROOT_FEE - 2 * CHILD_FEE;
-    let child_value = spendable / 2;
-    let leaf_outputs = build_leaf_outputs(&rpc, child_value);
-
-    let left_script = build_ctv_script(&leaf_outputs[0..2]);
-    let right_script = build_ctv_script(&leaf_outputs[2..4]);
-
-    let left_out = TxOut {
-        value: Amount::from_sat(child_value),
-        script_pubkey: left_script.clone(),
+    // Comma separated payout values in sats; four equal leaves by default
+    let payout_values: Option<Vec<u64>> = match args.get(1).map(String::as_str) {
+        None | Some("") => None,
+        Some(values) => Some(values.split(',').map(str::parse).collect::<Result<_, _>>()?),
     };
-    let right_out = TxOut {
-        value: Amount::from_sat(child_value),
-        script_pubkey: right_script.clone(),
+    let fee_rate: u64 = args.get(2).map(|s| s.parse()).transpose()?.unwrap_or(1);
+    let margin: u64 = args.get(3).map(|s| s.parse()).transpose()?.unwrap_or(0);
+    let promote = match args.get(4).map(String::as_str) {
+        None | Some("carry") => false,
+        Some("promote") => true,
+        Some(other) => return Err(format!("unknown dust handling {other}, expected carry or promote").into()),
     };
-
-    let root_tx = Transaction {
-        version: bitcoin::transaction::Version(3),
-        lock_time: bitcoin::absolute::LockTime::ZERO,
-        input: vec![TxIn::default()],
-        output: vec![left_out.clone(), right_out.clone()],
+    let shape = match args.get(5).map(String::as_str) {
+        None | Some("balanced") => TreeShape::Balanced,
+        Some("large-shallow") => TreeShape::ValueWeighted { large_shallow: true },
+        Some("small-shallow") => TreeShape::ValueWeighted { large_shallow: false },
+        Some(other) => {
+            return Err(format!("unknown shape {other}, expected balanced, large-shallow or small-shallow").into())
+        }
     };
-    let root_script = build_ctv_script(&root_tx.output);
 
-    let taproot = TaprootBuilder::new()
-        .add_leaf(0, root_script.clone())?
-        .finalize(&secp, xonly)
-        .map_err(|e| format!("taproot finalize failed: {e:?}"))?;
-    let tap_addr = Address::p2tr_tweaked(taproot.output_key(), Network::Regtest);
+    let rpc = connect(None)?;
 
-    println!("Mining to: {}", tap_addr);
-    let final_block = rpc.generate_to_address(1, &tap_addr)?[0];
-    let final_txid = rpc.get_block(&final_block)?.txdata[0].txid();
-    rpc.generate_to_address(100, &dummy_addr)?;
-
-    let spend_tx = Transaction {
-        version: bitcoin::transaction::Version(3),
-        lock_time: bitcoin::absolute::LockTime::ZERO,
-        input: vec![TxIn {
-            previous_output: OutPoint { txid: final_txid, vout: 0 },
-            script_sig: ScriptBuf::new(),
-            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
-            witness: Witness::default(),
-        }],
-        output: vec![left_out.clone(), right_out.clone()],
+    ensure_wallet(&rpc, "devwallet")?;
+    // On our custom signet blocks are signed and mined here instead of by generatetoaddress
+    let signet = signet_miner(&rpc)?;
+    let chain: &dyn NodeRpc = match &signet {
+        Some(miner) => miner,
+        None => &rpc,
     };
+    let node: &dyn NodeRpc = &SeededRpc { rpc: chain, seed: seed.as_ref() };
 
-    let mut spend_tx = spend_tx;
-    let ctrl_block = taproot
-        .control_block(&(root_script.clone(), LeafVersion::TapScript))
-        .ok_or("missing control block")?;
-
-    spend_tx.input[0].witness.push(root_script.to_bytes());
-    spend_tx.input[0].witness.push(ctrl_block.serialize());
-
-    let tx_hex = serialize_hex(&spend_tx);
-    println!("Spend tx: {}", tx_hex);
+    let secp = Secp256k1::new();
+    let keypair = new_keypair(&secp, seed.as_ref())?;
+    let (xonly, _) = XOnlyPublicKey::from_keypair(&keypair);
 
-    let root_spend_txid = rpc.send_raw_transaction(tx_hex)?;
-    println!("Broadcast root txid: {root_spend_txid}");
-    rpc.generate_to_address(1, &dummy_addr)?;
+    let dummy_addr = node.new_address()?;
+    let cb_value = mine_coinbase_value(node, &dummy_addr)?;
 
-    // Broadcast left child tx
-    let left_child = Transaction {
-        version: bitcoin::transaction::Version(3),
-        lock_time: bitcoin::absolute::LockTime::ZERO,
-        input: vec![TxIn {
-            previous_output: OutPoint { txid: root_spend_txid, vout: 0 },
-            script_sig: ScriptBuf::new(),
-            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
-            witness: Witness::default(),
-        }],
-        output: leaf_outputs[0..2].to_vec(),
+    let payout_values = match payout_values {
+        Some(values) => values,
+        None => layered_leaf_values(cb_value, NODE_FEE)?,
     };
-    let left_hex = serialize_hex(&left_child);
-    println!("Left child tx: {}", left_hex);
-    let left_txid = rpc.send_raw_transaction(left_hex)?;
+    let leaf_outputs = build_leaf_outputs(node, &payout_values)?;
 
-    // Broadcast right child tx
-    let right_child = Transaction {
-        version: bitcoin::transaction::Version(3),
-        lock_time: bitcoin::absolute::LockTime::ZERO,
-        input: vec![TxIn {
-            previous_output: OutPoint { txid: root_spend_txid, vout: 1 },
-            script_sig: ScriptBuf::new(),
-            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
-            witness: Witness::default(),
-        }],
-        output: leaf_outputs[2..4].to_vec(),
+    let pool_script = node.new_address()?.script_pubkey();
+    let handling = if promote {
+        DustHandling::Promote
+    } else {
+        DustHandling::CarryOver { pool_script: pool_script.clone() }
     };
-    let right_hex = serialize_hex(&right_child);
-    println!("Right child tx: {}", right_hex);
-    let right_txid = rpc.send_raw_transaction(right_hex)?;
-
-    rpc.generate_to_address(1, &dummy_addr)?;
-    println!("Mined child txids: {}, {}", left_txid, right_txid);
-
-    Ok(())
-}
+    let policy = DustPolicy { fee_rate, margin, handling };
+    let tree = build_dust_aware_tree(&leaf_outputs, RADIX, NODE_FEE, shape, &policy)?;
+    println!("Value weighted unroll size: {} sat·vB", value_weighted_vbytes(&tree.root));
+    for output in &tree.carried {
+        println!("Carrying over {} sat payout as a balance", output.value.to_sat());
+    }
+    for output in &tree.promoted {
+        println!("Promoted {} sat payout to the root", output.value.to_sat());
+    }
 
-fn build_leaf_outputs(rpc: &Client, total_value: u64) -> Vec<TxOut> {
-    let half = (total_value - CHILD_FEE) / 2;
-    (0..4)
-        .map(|_| {
-            let addr = rpc.get_new_address(None, None).unwrap().require_network(Network::Regtest).unwrap();
-            TxOut {
-                value: Amount::from_sat(half),
-                script_pubkey: addr.script_pubkey(),
+    let mut root = tree.root;
+    if root.input_value > cb_value {
+        return Err(Error::InsufficientValue { needed: root.input_value, available: cb_value }.into());
+    }
+    // Whatever the payouts and node fees leave over goes to the pool on the root
+    // rather than quietly into the root's fee
+    let surplus = Amount::from_sat(cb_value - root.input_value);
+    if surplus > Amount::ZERO {
+        let kept = match root.tx.output.iter_mut().find(|output| output.script_pubkey == pool_script) {
+            Some(output) => {
+                output.value += surplus;
+                true
             }
-        })
-        .collect()
-}
+            None if surplus >= pool_script.dust_value() => {
+                root.tx.output.push(TxOut { value: surplus, script_pubkey: pool_script });
+                true
+            }
+            None => false,
+        };
+        if kept {
+            println!("Pool output keeps the {} sat the payouts leave over", surplus.to_sat());
+        } else {
+            println!("{} sat left over is below the dust limit and raises the root fee", surplus.to_sat());
+        }
+    }
+    root.input_value = cb_value;
 
-fn build_ctv_script(outputs: &[TxOut]) -> ScriptBuf {
-    let hash = calc_ctv_hash(outputs);
-    Builder::new().push_slice(&hash).push_opcode(OP_CTV).into_script()
-}
+    // Fills in every prevout once the coinbase txid is known
+    let final_txid = mine_ctv_tree(node, &secp, xonly, &mut root, &dummy_addr)?;
+    save_unroll_data(&unroll_path(&final_txid), &root)?;
 
-fn calc_ctv_hash(outputs: &[TxOut]) -> [u8; 32] {
-    let mut buf = Vec::new();
-    buf.extend(3_i32.to_le_bytes());
-    buf.extend(0_i32.to_le_bytes());
-    buf.extend(1_u32.to_le_bytes());
-    buf.extend(sha256::Hash::hash(&Sequence::ENABLE_RBF_NO_LOCKTIME.0.to_le_bytes()).to_byte_array());
-    buf.extend((outputs.len() as u32).to_le_bytes());
+    println!("Spend tx: {}", serialize_hex(&root.tx));
 
-    let mut out_buf = Vec::new();
-    for o in outputs {
-        o.consensus_encode(&mut out_buf).unwrap();
+    for txid in broadcast_by_level(node, &root, &dummy_addr)? {
+        println!("Broadcast txid: {txid}");
     }
-    buf.extend(sha256::Hash::hash(&out_buf).to_byte_array());
-    buf.extend(0_u32.to_le_bytes());
-
-    sha256::Hash::hash(&buf).to_byte_array()
-}
+    println!("Mined every tree transaction");
 
-fn ensure_wallet(rpc: &Client, name: &str) -> Result<(), bitcoincore_rpc::Error> {
-    let _ = rpc.create_wallet(name, None, None, None, None);
-    if !rpc.list_wallets()?.contains(&name.to_string()) {
-        rpc.load_wallet(name)?;
-    }
     Ok(())
 }
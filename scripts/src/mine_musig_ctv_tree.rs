@@ -0,0 +1,120 @@
+use bitcoin::{
+    Amount, OutPoint, ScriptBuf, TxOut,
+    key::{Keypair, Secp256k1},
+    secp256k1::Message,
+};
+use scripts::{
+    contract::split_evenly,
+    ctv::{get_virtual_bytes, template_tx},
+    error::Error,
+    flow::{mine_and_mature, mine_coinbase_value},
+    musig::{aggregate_nonces, aggregate_partial_sigs, nonce_gen, partial_sign},
+    musig_tree::{build_musig_tree, LeafPayout},
+    rpc::{connect, ensure_wallet, NodeRpc},
+    signet::signet_miner,
+    taproot::key_spend_sighash,
+    tree::{save_unroll_data, unroll_path},
+};
+
+const OWNER_COUNT: usize = 4;
+const RADIX: usize = 2;
+const NODE_FEE: u64 = 500;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let rpc = connect(None)?;
+
+    ensure_wallet(&rpc, "devwallet")?;
+    // On our custom signet blocks are signed and mined here instead of by generatetoaddress
+    let signet = signet_miner(&rpc)?;
+    let node: &dyn NodeRpc = match &signet {
+        Some(miner) => miner,
+        None => &rpc,
+    };
+    let network = node.network()?;
+
+    let secp = Secp256k1::new();
+    let owners: Vec<Keypair> = (0..OWNER_COUNT)
+        .map(|_| Keypair::new(&secp, &mut rand::thread_rng()))
+        .collect();
+
+    let dummy_addr = node.new_address()?;
+    let cb_value = mine_coinbase_value(node, &dummy_addr)?;
+
+    // Every leaf pays its owner's own taproot key; one fee per tree node
+    let fees = 3 * NODE_FEE;
+    let spendable = cb_value
+        .checked_sub(fees)
+        .ok_or(Error::InsufficientValue { needed: fees, available: cb_value })?;
+    let payouts: Vec<LeafPayout> = owners
+        .iter()
+        .zip(split_evenly(spendable, OWNER_COUNT))
+        .map(|(owner, value)| LeafPayout {
+            owner: owner.public_key(),
+            output: TxOut {
+                value: Amount::from_sat(value),
+                script_pubkey: ScriptBuf::new_p2tr(&secp, owner.x_only_public_key().0, None),
+            },
+        })
+        .collect();
+
+    let mut tree = build_musig_tree(&secp, &payouts, RADIX, NODE_FEE, cb_value)?;
+    let root_addr = tree.lock(&[]).ok_or("missing root lock")?.address(network);
+
+    println!("Mining to MuSig2 root: {}", root_addr);
+    let coinbase_txid = mine_and_mature(node, &root_addr, &dummy_addr)?;
+
+    tree.root.bind(OutPoint { txid: coinbase_txid, vout: 0 });
+    save_unroll_data(&unroll_path(&coinbase_txid), &tree.root)?;
+
+    // Unroll the root through its CTV leaf
+    let root_txid = node.send_raw_transaction(&tree.root.tx)?;
+    println!("Broadcast root txid: {root_txid}");
+    node.generate_to_address(1, &dummy_addr)?;
+
+    // The left owners skip their CTV node and spend its output by key path
+    let left_path = [0];
+    let left = tree.node(&left_path).ok_or("missing left node")?;
+    let left_lock = tree.lock(&left_path).ok_or("missing left lock")?;
+    let prevout = tree.root.tx.output[0].clone();
+
+    let mut coop_tx = template_tx(left.tx.output.clone());
+    coop_tx.input[0].previous_output = OutPoint { txid: root_txid, vout: 0 };
+    let sighash = key_spend_sighash(&coop_tx, 0, &[prevout])?;
+
+    let ctx = left_lock.signing_context(&secp)?;
+    let signers: Vec<&Keypair> = owners
+        .iter()
+        .filter(|owner| left_lock.owners.contains(&owner.public_key()))
+        .collect();
+
+    // Round 1: every signer shares a public nonce
+    let (sec_nonces, pub_nonces): (Vec<_>, Vec<_>) = signers.iter().map(|_| nonce_gen(&secp)).unzip();
+    let agg_nonce = aggregate_nonces(&pub_nonces)?;
+    println!("Aggregated {} nonces for key {}", pub_nonces.len(), ctx.aggregate_xonly());
+
+    // Round 2: every signer returns a partial signature
+    let mut partial_sigs = vec![];
+    for (sec_nonce, signer) in sec_nonces.into_iter().zip(&signers) {
+        partial_sigs.push(partial_sign(&secp, &ctx, sec_nonce, signer, &agg_nonce, &sighash)?);
+    }
+    let sig = aggregate_partial_sigs(&secp, &ctx, &agg_nonce, &sighash, &partial_sigs)?;
+    secp.verify_schnorr(&sig, &Message::from_digest(sighash), &ctx.aggregate_xonly())?;
+    coop_tx.input[0].witness.push(sig.as_ref());
+
+    let coop_txid = node.send_raw_transaction(&coop_tx)?;
+    println!("Broadcast cooperative key-path spend: {coop_txid}");
+
+    // The right owners unroll their node unilaterally
+    let right = tree.node(&[1]).ok_or("missing right node")?;
+    let right_txid = node.send_raw_transaction(&right.tx)?;
+    println!("Broadcast right CTV child: {right_txid}");
+
+    node.generate_to_address(1, &dummy_addr)?;
+    println!(
+        "Mined both. Key path spend: {} vB, CTV script path spend: {} vB",
+        get_virtual_bytes(&coop_tx),
+        get_virtual_bytes(&right.tx),
+    );
+
+    Ok(())
+}
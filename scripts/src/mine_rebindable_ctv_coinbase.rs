@@ -0,0 +1,137 @@
+use std::env;
+
+use bitcoincore_rpc::RpcApi;
+use bitcoin::{
+    Address, Amount, OutPoint, Transaction, TxOut,
+    consensus::encode::serialize_hex,
+    key::{Keypair, Secp256k1},
+    taproot::TaprootSpendInfo,
+    ScriptBuf, XOnlyPublicKey,
+};
+use scripts::{
+    contract::split_evenly,
+    csfs::{sign_digest, signed_template_script},
+    ctv::{anchor_output, calc_ctv_hash, get_virtual_bytes, template_tx, ANCHOR_VALUE},
+    error::Error,
+    flow::mine_and_mature,
+    rpc::{connect, ensure_wallet, NodeRpc},
+    signet::signet_miner,
+    taproot::{leaf_spend_info, tapscript_witness},
+    tree::{save_unroll_data, unroll_path, TreeNode},
+};
+
+const FEE_RATE: u64 = 1;
+const TEMPLATE_COUNT: usize = 3;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let chosen: usize = env::args()
+        .nth(1)
+        .unwrap_or((TEMPLATE_COUNT - 1).to_string())
+        .parse()?;
+    if chosen >= TEMPLATE_COUNT {
+        return Err(format!("template index must be below {TEMPLATE_COUNT}").into());
+    }
+
+    let rpc = connect(None)?;
+
+    ensure_wallet(&rpc, "devwallet")?;
+
+    let rpc = connect(Some("devwallet"))?;
+    // On our custom signet blocks are signed and mined here instead of by generatetoaddress
+    let signet = signet_miner(&rpc)?;
+    let node: &dyn NodeRpc = match &signet {
+        Some(miner) => miner,
+        None => &rpc,
+    };
+    let network = node.network()?;
+
+    let secp = Secp256k1::new();
+    let internal = Keypair::new(&secp, &mut rand::thread_rng());
+    let operator = Keypair::new(&secp, &mut rand::thread_rng());
+    let (internal_key, _) = XOnlyPublicKey::from_keypair(&internal);
+    let (operator_key, _) = XOnlyPublicKey::from_keypair(&operator);
+
+    // The coinbase commits only to the operator key, not to any payout
+    let leaf = signed_template_script(&operator_key);
+    let spend_info = leaf_spend_info(&secp, internal_key, &leaf)?;
+    let contract_address = Address::p2tr_tweaked(spend_info.output_key(), network);
+
+    println!("Mining to rebindable CTV address: {}", contract_address);
+    let dummy_addr = node.new_address()?;
+    let coinbase_txid = mine_and_mature(node, &contract_address, &dummy_addr)?;
+    let coinbase_tx = node.get_raw_transaction(&coinbase_txid)?;
+    let coinbase_value = coinbase_tx.output[0].value.to_sat();
+
+    // After the block is found the operator signs successive payout updates
+    let mut signed = vec![];
+    for version in 0..TEMPLATE_COUNT {
+        let template = build_payout_template(node, coinbase_value, version + 2, &leaf, &spend_info)?;
+        let ctv_hash = calc_ctv_hash(&template.output);
+        let sig = sign_digest(&secp, &operator, ctv_hash);
+        println!("Operator signed template v{version} ({} outputs): {}", template.output.len(), hex::encode(ctv_hash));
+        signed.push((template, ctv_hash, sig));
+    }
+
+    let coinbase_outpoint = OutPoint { txid: coinbase_tx.txid(), vout: 0 };
+
+    // A template the operator never signed must not be spendable
+    let mut forged = build_payout_template(node, coinbase_value, 1, &leaf, &spend_info)?;
+    forged.input[0].previous_output = coinbase_outpoint;
+    let forged_hash = calc_ctv_hash(&forged.output);
+    forged.input[0].witness = tapscript_witness(&[&forged_hash, &signed[0].2.serialize()], &leaf, &spend_info)?;
+    let forged_result = &rpc.test_mempool_accept(&[&forged])?[0];
+    if forged_result.allowed {
+        return Err("the node accepted a template the operator never signed".into());
+    }
+    println!(
+        "Unsigned template rejected: {}",
+        forged_result.reject_reason.as_deref().unwrap_or("-"),
+    );
+
+    let (mut spend_tx, ctv_hash, sig) = signed.swap_remove(chosen);
+    spend_tx.input[0].previous_output = coinbase_outpoint;
+    spend_tx.input[0].witness = tapscript_witness(&[&ctv_hash, &sig.serialize()], &leaf, &spend_info)?;
+
+    let unroll = TreeNode::new(spend_tx.clone(), coinbase_value);
+    save_unroll_data(&unroll_path(&coinbase_outpoint.txid), &unroll)?;
+
+    println!("Spending with template v{chosen}: {}", serialize_hex(&spend_tx));
+    let txid = node.send_raw_transaction(&spend_tx)?;
+    println!("Broadcasted txid: {txid}");
+
+    node.generate_to_address(1, &dummy_addr)?;
+    println!("Mined txid: {txid}");
+
+    Ok(())
+}
+
+/// Splits `input_value` across `output_count` fresh wallet addresses plus an
+/// anchor, paying `FEE_RATE` for the signed-template witness.
+fn build_payout_template(
+    node: &dyn NodeRpc,
+    input_value: u64,
+    output_count: usize,
+    leaf: &ScriptBuf,
+    spend_info: &TaprootSpendInfo,
+) -> Result<Transaction, Box<dyn std::error::Error>> {
+    let mut outputs = vec![];
+    for _ in 0..output_count {
+        let address = node.new_address()?;
+        outputs.push(TxOut { value: Amount::ZERO, script_pubkey: address.script_pubkey() });
+    }
+    outputs.push(anchor_output());
+
+    // Size the fee with a dummy witness of the same shape as the real one
+    let mut dummy = template_tx(outputs.clone());
+    dummy.input[0].witness = tapscript_witness(&[&[0; 32], &[0; 64]], leaf, spend_info)?;
+    let fee = get_virtual_bytes(&dummy) as u64 * FEE_RATE;
+
+    let needed = fee.saturating_add(ANCHOR_VALUE);
+    let spendable = input_value
+        .checked_sub(needed)
+        .ok_or(Error::InsufficientValue { needed, available: input_value })?;
+    for (output, value) in outputs.iter_mut().zip(split_evenly(spendable, output_count)) {
+        output.value = Amount::from_sat(value);
+    }
+    Ok(template_tx(outputs))
+}
@@ -0,0 +1,146 @@
+use std::path::Path;
+
+use bitcoin::{
+    Amount, TxOut,
+    key::Secp256k1,
+    XOnlyPublicKey,
+};
+use scripts::{
+    balances::{BalanceStore, CarryPlan, BALANCE_STORE},
+    builder::build_ctv_tree,
+    contract::broadcast_by_level,
+    error::Error,
+    flow::{mine_coinbase_value, mine_ctv_tree},
+    rpc::{connect, ensure_wallet, NodeRpc},
+    seed::{new_keypair, take_seed_arg, SeededRpc},
+    signet::signet_miner,
+    shares::{load_shares, parse_scheme, PayoutManifest, SHARE_LEDGER},
+    tree::{save_unroll_data, unroll_path},
+};
+
+const NODE_FEE: u64 = 500;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args: Vec<String> = std::env::args().collect();
+    let seed = take_seed_arg(&mut args)?;
+
+    // e.g. pplns:1000000, tides:125000 or fpps:125000
+    let scheme = parse_scheme(args.get(1).map(String::as_str).unwrap_or("pplns:1000000"))?;
+    // Tree radix, or "flat" to pay every miner straight from the coinbase spend
+    let radix: Option<usize> = match args.get(2).map(String::as_str) {
+        None | Some("flat") => None,
+        Some(radix) => Some(radix.parse()?),
+    };
+    // Miners owed less than this many sats are carried over to a later block
+    let threshold: Option<u64> = args.get(3).map(|s| s.parse()).transpose()?;
+    let mut store = BalanceStore::load(Path::new(BALANCE_STORE))?;
+
+    let shares = load_shares(Path::new(SHARE_LEDGER))?;
+    if shares.is_empty() {
+        return Err(format!("no shares in {SHARE_LEDGER}, record some with `just record-share`").into());
+    }
+
+    let rpc = connect(None)?;
+
+    ensure_wallet(&rpc, "devwallet")?;
+    // On our custom signet blocks are signed and mined here instead of by generatetoaddress
+    let signet = signet_miner(&rpc)?;
+    let chain: &dyn NodeRpc = match &signet {
+        Some(miner) => miner,
+        None => &rpc,
+    };
+    let node: &dyn NodeRpc = &SeededRpc { rpc: chain, seed: seed.as_ref() };
+    let network = node.network()?;
+
+    let secp = Secp256k1::new();
+    let keypair = new_keypair(&secp, seed.as_ref())?;
+    let (xonly, _) = XOnlyPublicKey::from_keypair(&keypair);
+
+    let dummy_addr = node.new_address()?;
+    let pool_addr = node.new_address()?;
+    let cb_value = mine_coinbase_value(node, &dummy_addr)?;
+
+    // Anything the scheme does not owe miners goes to the pool
+    let outputs = |manifest: &PayoutManifest| -> Result<Vec<TxOut>, Box<dyn std::error::Error>> {
+        let mut outputs = manifest.to_outputs(network)?;
+        if manifest.retained > 0 {
+            outputs.push(TxOut { value: Amount::from_sat(manifest.retained), script_pubkey: pool_addr.script_pubkey() });
+        }
+        Ok(outputs)
+    };
+
+    // With a threshold, carried amounts join the pool output until they are released
+    let carry = |manifest: PayoutManifest| -> (PayoutManifest, Option<CarryPlan>) {
+        match threshold {
+            Some(threshold) => {
+                let plan = store.plan(&manifest, threshold);
+                let paid = PayoutManifest { entries: plan.payouts.entries.clone(), retained: plan.pool_value };
+                (paid, Some(plan))
+            }
+            None => (manifest, None),
+        }
+    };
+
+    // Tree fees come off the top before the split, but the split decides how
+    // many outputs and so how many nodes there are. Repeat until the two
+    // agree; if carries flip the count between two sizes, reserve the larger.
+    let mut reserved = 0;
+    let mut tried = vec![];
+    let (manifest, plan, radix) = loop {
+        let fees = reserved as u64 * NODE_FEE;
+        let reward = cb_value
+            .checked_sub(fees)
+            .ok_or(Error::InsufficientValue { needed: fees, available: cb_value })?;
+        let (manifest, plan) = carry(scheme.manifest(&shares, reward)?);
+        let draft_outputs = outputs(&manifest)?;
+        let tree_radix = radix.unwrap_or(draft_outputs.len().max(2));
+        let node_count = build_ctv_tree(&draft_outputs, tree_radix, NODE_FEE)?.transactions().len();
+        if node_count == reserved || (node_count < reserved && tried.contains(&node_count)) {
+            break (manifest, plan, tree_radix);
+        }
+        tried.push(reserved);
+        reserved = node_count;
+    };
+    if let Some(plan) = &plan {
+        println!(
+            "Carrying {} sat, releasing {} sat of earlier balances, {} sat still owed after this block",
+            plan.carried,
+            plan.released,
+            plan.balances_after.values().sum::<u64>(),
+        );
+    }
+
+    println!("{} payouts:", scheme.name());
+    for entry in &manifest.entries {
+        println!("  {} weight {:>10} paid {:>12} sat", entry.address, entry.weight, entry.value);
+    }
+    if manifest.retained > 0 {
+        println!("  pool retains {} sat", manifest.retained);
+    }
+
+    let mut root = build_ctv_tree(&outputs(&manifest)?, radix, NODE_FEE)?;
+    if root.input_value > cb_value {
+        return Err(Error::InsufficientValue { needed: root.input_value, available: cb_value }.into());
+    }
+    if root.input_value < cb_value {
+        println!("{} sat of reserved tree fees go unused and raise the root fee", cb_value - root.input_value);
+    }
+    root.input_value = cb_value;
+
+    let final_txid = mine_ctv_tree(node, &secp, xonly, &mut root, &dummy_addr)?;
+    save_unroll_data(&unroll_path(&final_txid), &root)?;
+
+    // The coinbase is mined, so the carries it pays into the pool output are real
+    if let Some(plan) = plan {
+        store.commit(plan, final_txid, &pool_addr.script_pubkey());
+        store.reconcile()?;
+        store.save(Path::new(BALANCE_STORE))?;
+    }
+
+    for txid in broadcast_by_level(node, &root, &dummy_addr)? {
+        println!("Broadcast txid: {txid}");
+    }
+    println!("Paid {} miners from coinbase {final_txid}", manifest.entries.len());
+
+    Ok(())
+}
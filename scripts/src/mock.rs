@@ -0,0 +1,434 @@
+//! In-memory regtest chain implementing `NodeRpc`, so the mining flows can run
+//! under `cargo test` without bitcoind.
+//!
+//! Transactions are checked for missing or double spent inputs, coinbase
+//! maturity, value conservation, CTV template hashes (bare, in tapscript and
+//! behind a recovery leaf's CSV), taproot key-path signatures and taproot
+//! script-path commitments. Relative timelocks are not enforced, other scripts
+//! are not executed and non-taproot inputs are not checked at all.
+//!
+//! A transaction conflicting with the mempool replaces what it conflicts with
+//! if those are v3 or signal BIP-125 replaceability and it pays more fee, as
+//! on a node without full RBF. Blocks take every mempool transaction unless a
+//! feerate floor is set, which leaves cheaper ones (counting children that
+//! pay for them) stuck.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use bitcoin::{
+    absolute::LockTime,
+    block::{Header, Version as BlockVersion},
+    hashes::{sha256, Hash},
+    key::Secp256k1,
+    script::{Builder, ScriptBuf},
+    secp256k1::{schnorr, Message, SecretKey},
+    sighash::{Prevouts, SighashCache, TapSighashType},
+    taproot::ControlBlock,
+    transaction::Version,
+    Address, Amount, Block, BlockHash, CompactTarget, Network, OutPoint, Sequence, Transaction, TxIn,
+    TxMerkleNode, TxOut, Txid, Witness, XOnlyPublicKey,
+};
+
+use crate::chain::{ChainSource, TxStatus};
+use crate::ctv::{ctv_hash_from_script, get_virtual_bytes, template_hash, timeout_from_script};
+use crate::error::Error;
+use crate::flow::COINBASE_MATURITY;
+use crate::rpc::NodeRpc;
+
+const SUBSIDY_HALVING_INTERVAL: usize = 150;
+const INITIAL_SUBSIDY: u64 = 5_000_000_000;
+
+#[derive(Clone, Debug)]
+struct Utxo {
+    output: TxOut,
+    height: usize,
+    coinbase: bool,
+}
+
+#[derive(Default)]
+struct MockChain {
+    blocks: Vec<Block>,
+    transactions: HashMap<Txid, Transaction>,
+    utxos: HashMap<OutPoint, Utxo>,
+    mempool: Vec<Transaction>,
+    address_count: u64,
+    /// Feerate in sat/vB a transaction needs to get into a block.
+    min_feerate: u64,
+}
+
+/// A regtest chain that lives in memory and mines instantly.
+pub struct MockRpc {
+    chain: RefCell<MockChain>,
+    network: Network,
+}
+
+impl Default for MockRpc {
+    fn default() -> Self {
+        MockRpc::new()
+    }
+}
+
+impl MockRpc {
+    pub fn new() -> Self {
+        MockRpc::with_network(Network::Regtest)
+    }
+
+    /// The same chain, but handing out addresses for `network`.
+    pub fn with_network(network: Network) -> Self {
+        MockRpc { chain: RefCell::default(), network }
+    }
+
+    /// Height of the tip, or `None` before the first block.
+    pub fn height(&self) -> Option<usize> {
+        self.chain.borrow().blocks.len().checked_sub(1)
+    }
+
+    /// The unspent confirmed output at `outpoint`, if any.
+    pub fn get_tx_out(&self, outpoint: &OutPoint) -> Option<TxOut> {
+        self.chain.borrow().utxos.get(outpoint).map(|utxo| utxo.output.clone())
+    }
+
+    pub fn mempool(&self) -> Vec<Txid> {
+        self.chain.borrow().mempool.iter().map(Transaction::txid).collect()
+    }
+
+    /// Leaves transactions paying less than `sat_per_vb` out of new blocks.
+    pub fn set_min_block_feerate(&self, sat_per_vb: u64) {
+        self.chain.borrow_mut().min_feerate = sat_per_vb;
+    }
+
+    /// Whether `txid` is in a block.
+    pub fn is_confirmed(&self, txid: &Txid) -> bool {
+        let chain = self.chain.borrow();
+        chain.transactions.contains_key(txid) && !chain.mempool.iter().any(|tx| tx.txid() == *txid)
+    }
+}
+
+impl NodeRpc for MockRpc {
+    fn network(&self) -> Result<Network, Error> {
+        Ok(self.network)
+    }
+
+    fn new_address(&self) -> Result<Address, Error> {
+        let mut chain = self.chain.borrow_mut();
+        chain.address_count += 1;
+
+        // Deterministic keys keep mock runs reproducible
+        let seed = sha256::Hash::hash(&chain.address_count.to_le_bytes());
+        let secret = SecretKey::from_slice(seed.as_byte_array())?;
+        let (xonly, _) = secret.x_only_public_key(&Secp256k1::new());
+        Ok(Address::p2tr(&Secp256k1::new(), xonly, None, self.network))
+    }
+
+    fn get_block(&self, hash: &BlockHash) -> Result<Block, Error> {
+        let chain = self.chain.borrow();
+        chain
+            .blocks
+            .iter()
+            .find(|block| block.block_hash() == *hash)
+            .cloned()
+            .ok_or_else(|| Error::Node(format!("block {hash} not found")))
+    }
+
+    fn generate_to_address(&self, blocks: u64, address: &Address) -> Result<Vec<BlockHash>, Error> {
+        let mut chain = self.chain.borrow_mut();
+        let mut hashes = vec![];
+        for _ in 0..blocks {
+            hashes.push(chain.mine_block(address));
+        }
+        Ok(hashes)
+    }
+
+    fn send_raw_transaction(&self, tx: &Transaction) -> Result<Txid, Error> {
+        let mut chain = self.chain.borrow_mut();
+        let replaced = chain.accept(tx)?;
+        for txid in replaced {
+            chain.mempool.retain(|m| m.txid() != txid);
+            chain.transactions.remove(&txid);
+        }
+        chain.transactions.insert(tx.txid(), tx.clone());
+        chain.mempool.push(tx.clone());
+        Ok(tx.txid())
+    }
+
+    fn get_raw_transaction(&self, txid: &Txid) -> Result<Transaction, Error> {
+        self.chain
+            .borrow()
+            .transactions
+            .get(txid)
+            .cloned()
+            .ok_or_else(|| Error::Node(format!("No such mempool or blockchain transaction {txid}")))
+    }
+}
+
+impl ChainSource for MockRpc {
+    fn fetch_transaction(&self, txid: &Txid) -> Result<Option<Transaction>, Error> {
+        Ok(self.chain.borrow().transactions.get(txid).cloned())
+    }
+
+    fn transaction_status(&self, txid: &Txid) -> Result<TxStatus, Error> {
+        let chain = self.chain.borrow();
+        if chain.mempool.iter().any(|tx| tx.txid() == *txid) {
+            return Ok(TxStatus::Mempool);
+        }
+        Ok(match chain.blocks.iter().position(|block| block.txdata.iter().any(|tx| tx.txid() == *txid)) {
+            Some(height) => TxStatus::Confirmed { height: height as u64 },
+            None => TxStatus::Unbroadcast,
+        })
+    }
+
+    fn outspends(&self, txid: &Txid) -> Result<Vec<Option<Txid>>, Error> {
+        let chain = self.chain.borrow();
+        let Some(tx) = chain.transactions.get(txid) else {
+            return Ok(vec![]);
+        };
+        let spenders: Vec<&Transaction> = chain.blocks.iter().flat_map(|block| &block.txdata).chain(&chain.mempool).collect();
+        Ok((0..tx.output.len() as u32)
+            .map(|vout| {
+                let outpoint = OutPoint { txid: *txid, vout };
+                spenders
+                    .iter()
+                    .find(|spender| spender.input.iter().any(|input| input.previous_output == outpoint))
+                    .map(|spender| spender.txid())
+            })
+            .collect())
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<Txid, Error> {
+        self.send_raw_transaction(tx)
+    }
+
+    fn tip_height(&self) -> Result<u64, Error> {
+        self.height().map(|height| height as u64).ok_or_else(|| Error::Node("no blocks yet".into()))
+    }
+}
+
+impl MockChain {
+    fn mine_block(&mut self, address: &Address) -> BlockHash {
+        let height = self.blocks.len();
+        let mempool = self.select_for_block();
+        let fees: u64 = mempool.iter().map(|tx| self.fee(tx)).sum();
+        self.mempool.retain(|tx| !mempool.contains(tx));
+        let subsidy = INITIAL_SUBSIDY >> (height / SUBSIDY_HALVING_INTERVAL).min(63);
+        let coinbase = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                // BIP-34 height push keeps every coinbase txid unique
+                script_sig: Builder::new().push_int(height as i64).push_int(0).into_script(),
+                sequence: Sequence::MAX,
+                witness: Witness::default(),
+            }],
+            output: vec![TxOut { value: Amount::from_sat(subsidy + fees), script_pubkey: address.script_pubkey() }],
+        };
+
+        let mut txdata = vec![coinbase];
+        txdata.extend(mempool);
+        let mut block = Block {
+            header: Header {
+                version: BlockVersion::TWO,
+                prev_blockhash: self.blocks.last().map(Block::block_hash).unwrap_or(BlockHash::all_zeros()),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 1_700_000_000 + height as u32 * 600,
+                bits: CompactTarget::from_consensus(0x207fffff),
+                nonce: 0,
+            },
+            txdata,
+        };
+        block.header.merkle_root = block.compute_merkle_root().unwrap_or(TxMerkleNode::all_zeros());
+
+        for (i, tx) in block.txdata.iter().enumerate() {
+            for input in tx.input.iter().filter(|_| i > 0) {
+                self.utxos.remove(&input.previous_output);
+            }
+            for (vout, output) in tx.output.iter().enumerate() {
+                if output.script_pubkey.is_op_return() {
+                    continue;
+                }
+                let utxo = Utxo { output: output.clone(), height, coinbase: i == 0 };
+                self.utxos.insert(OutPoint { txid: tx.txid(), vout: vout as u32 }, utxo);
+            }
+            self.transactions.insert(tx.txid(), tx.clone());
+        }
+
+        let hash = block.block_hash();
+        self.blocks.push(block);
+        hash
+    }
+
+    /// Mempool transactions for the next block, parents first. Below the
+    /// feerate floor a transaction still gets in if it and the children
+    /// spending it clear the floor together.
+    fn select_for_block(&self) -> Vec<Transaction> {
+        let feerate_ok = |txs: &[&Transaction]| {
+            let fee: u64 = txs.iter().map(|tx| self.fee(tx)).sum();
+            let vsize: usize = txs.iter().map(|tx| get_virtual_bytes(tx)).sum();
+            fee >= self.min_feerate * vsize as u64
+        };
+
+        let mut pending: Vec<&Transaction> = self.mempool.iter().collect();
+        let mut selected = vec![];
+        while let Some(i) = pending.iter().position(|tx| {
+            let txid = tx.txid();
+            let waits_on_parent = tx.input.iter().any(|input| pending.iter().any(|p| p.txid() == input.previous_output.txid));
+            let mut package = vec![*tx];
+            package.extend(pending.iter().filter(|c| c.input.iter().any(|input| input.previous_output.txid == txid)));
+            !waits_on_parent && (feerate_ok(&[tx]) || feerate_ok(&package))
+        }) {
+            selected.push(pending.remove(i).clone());
+        }
+        selected
+    }
+
+    /// Mempool transactions spending `txid`, their descendants included.
+    fn descendants(&self, txid: Txid) -> Vec<Txid> {
+        let mut found = vec![];
+        for tx in &self.mempool {
+            if tx.input.iter().any(|input| input.previous_output.txid == txid) {
+                found.push(tx.txid());
+                found.extend(self.descendants(tx.txid()));
+            }
+        }
+        found
+    }
+
+    /// Output being spent at `outpoint`, from the chain or an unconfirmed parent.
+    fn spent_output(&self, outpoint: &OutPoint) -> Option<Utxo> {
+        if let Some(utxo) = self.utxos.get(outpoint) {
+            return Some(utxo.clone());
+        }
+        let parent = self.mempool.iter().find(|tx| tx.txid() == outpoint.txid)?;
+        let output = parent.output.get(outpoint.vout as usize)?.clone();
+        Some(Utxo { output, height: self.blocks.len(), coinbase: false })
+    }
+
+    fn fee(&self, tx: &Transaction) -> u64 {
+        let input_value: u64 = tx
+            .input
+            .iter()
+            .filter_map(|input| self.spent_output(&input.previous_output))
+            .map(|utxo| utxo.output.value.to_sat())
+            .sum();
+        input_value.saturating_sub(tx.output.iter().map(|o| o.value.to_sat()).sum())
+    }
+
+    /// Checks `tx` against the chain and mempool, returning the mempool
+    /// transactions it replaces.
+    fn accept(&self, tx: &Transaction) -> Result<Vec<Txid>, Error> {
+        let txid = tx.txid();
+        if self.transactions.contains_key(&txid) {
+            return Err(Error::Node(format!("transaction {txid} already in block chain or mempool")));
+        }
+
+        let next_height = self.blocks.len();
+        let mut prevouts = vec![];
+        let mut conflicts = vec![];
+        for input in &tx.input {
+            let outpoint = input.previous_output;
+            let conflict = self.mempool.iter().find(|m| m.input.iter().any(|i| i.previous_output == outpoint));
+            let Some(utxo) = self.spent_output(&outpoint) else {
+                return Err(Error::Node(format!("bad-txns-inputs-missingorspent: {outpoint}")));
+            };
+            if let Some(conflict) = conflict {
+                // Like a node without full RBF, only opted-in transactions are replaced
+                if conflict.version != Version(3) && !conflict.is_explicitly_rbf() {
+                    return Err(Error::Node(format!("txn-mempool-conflict: {outpoint} is spent by {}", conflict.txid())));
+                }
+                conflicts.push(conflict.txid());
+            }
+            if utxo.coinbase && ((next_height - utxo.height) as u64) < COINBASE_MATURITY {
+                return Err(Error::Node(format!("bad-txns-premature-spend-of-coinbase: {outpoint}")));
+            }
+            prevouts.push(utxo.output);
+        }
+
+        let input_value: u64 = prevouts.iter().map(|o| o.value.to_sat()).sum();
+        let output_value: u64 = tx.output.iter().map(|o| o.value.to_sat()).sum();
+        if output_value > input_value {
+            return Err(Error::Node(format!("bad-txns-in-belowout: {input_value} < {output_value}")));
+        }
+
+        for (index, prevout) in prevouts.iter().enumerate() {
+            check_input(tx, index, prevout, &prevouts)?;
+        }
+
+        let mut replaced = vec![];
+        for txid in conflicts {
+            if !replaced.contains(&txid) {
+                replaced.push(txid);
+                replaced.extend(self.descendants(txid));
+            }
+        }
+        let replaced_fee: u64 = self.mempool.iter().filter(|m| replaced.contains(&m.txid())).map(|m| self.fee(m)).sum();
+        if !replaced.is_empty() && input_value - output_value <= replaced_fee {
+            return Err(Error::Node(format!(
+                "insufficient fee: replacing {} transaction(s) paying {replaced_fee} sat needs more",
+                replaced.len()
+            )));
+        }
+        Ok(replaced)
+    }
+}
+
+/// Checks the parts of the spent script the mock understands.
+fn check_input(
+    tx: &Transaction,
+    index: usize,
+    prevout: &TxOut,
+    prevouts: &[TxOut],
+) -> Result<(), Error> {
+    let spk = &prevout.script_pubkey;
+    let check_ctv = |hash: [u8; 32]| -> Result<(), Error> {
+        if template_hash(tx, index as u32) != hash {
+            return Err(Error::Node(format!("input {index}: CTV template hash mismatch")));
+        }
+        Ok(())
+    };
+
+    if let Some(hash) = ctv_hash_from_script(spk) {
+        return check_ctv(hash);
+    }
+    if !spk.is_p2tr() {
+        return Ok(());
+    }
+
+    let output_key = XOnlyPublicKey::from_slice(&spk.as_bytes()[2..34])?;
+    let witness: Vec<&[u8]> = tx.input[index].witness.iter().collect();
+    let secp = Secp256k1::verification_only();
+
+    match witness.as_slice() {
+        [] => Err(Error::Node(format!("input {index}: empty taproot witness"))),
+        [sig] => {
+            let sighash_type = match sig.len() {
+                64 => TapSighashType::Default,
+                65 => TapSighashType::from_consensus_u8(sig[64])?,
+                _ => return Err(Error::Node(format!("input {index}: bad key path signature length"))),
+            };
+            let sighash = SighashCache::new(tx).taproot_key_spend_signature_hash(
+                index,
+                &Prevouts::All(prevouts),
+                sighash_type,
+            )?;
+            let sig = schnorr::Signature::from_slice(&sig[..64])?;
+            secp.verify_schnorr(&sig, &Message::from_digest(sighash.to_byte_array()), &output_key)
+                .map_err(|_| Error::Node(format!("input {index}: invalid key path signature")))?;
+            Ok(())
+        }
+        [.., script, control] => {
+            let script = ScriptBuf::from_bytes(script.to_vec());
+            let control = ControlBlock::decode(control)?;
+            if !control.verify_taproot_commitment(&secp, output_key, &script) {
+                return Err(Error::Node(format!("input {index}: control block does not commit to the script")));
+            }
+            // A recovery leaf's CSV is not checked, only the template behind it
+            match ctv_hash_from_script(&script)
+                .or_else(|| timeout_from_script(&script).map(|(_, hash)| hash))
+            {
+                Some(hash) => check_ctv(hash),
+                None => Ok(()),
+            }
+        }
+    }
+}
@@ -0,0 +1,351 @@
+//! Minimal BIP-327 MuSig2: key aggregation with an optional taproot tweak,
+//! nonce exchange, partial signing and signature aggregation.
+//!
+//! Nonces come straight from the OS RNG instead of BIP-327's NonceGen hash,
+//! which is fine for a regtest playground but nothing more.
+
+use bitcoin::{
+    hashes::{sha256, Hash, HashEngine},
+    key::{Keypair, Secp256k1},
+    secp256k1::{schnorr::Signature, All, PublicKey, Scalar, SecretKey},
+    taproot::{TapNodeHash, TapTweakHash},
+    XOnlyPublicKey,
+};
+
+use crate::error::Error;
+
+/// secp256k1 group order, for reducing hash outputs into scalars.
+const CURVE_ORDER: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+    0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x41,
+];
+
+/// Aggregate key of a signer set, optionally tweaked (e.g. with the BIP-341
+/// taproot tweak). A key listed twice counts twice, so its holder must sign
+/// once per occurrence.
+#[derive(Clone, Debug)]
+pub struct KeyAggContext {
+    pubkeys: Vec<PublicKey>,
+    coefficients: Vec<Scalar>,
+    aggregate: PublicKey,
+    /// Whether the tweaks negated the untweaked key (BIP-327 `gacc`).
+    gacc_negated: bool,
+    /// Sum of the tweaks added to the key (BIP-327 `tacc`), `None` for zero.
+    tweak: Option<SecretKey>,
+}
+
+impl KeyAggContext {
+    pub fn new(secp: &Secp256k1<All>, pubkeys: &[PublicKey]) -> Result<Self, Error> {
+        if pubkeys.is_empty() {
+            return Err(Error::Taproot("cannot aggregate an empty key set".into()));
+        }
+
+        let serialized: Vec<[u8; 33]> = pubkeys.iter().map(PublicKey::serialize).collect();
+        let list_hash = tagged_hash("KeyAgg list", &serialized.iter().map(|pk| &pk[..]).collect::<Vec<_>>());
+        let second = pubkeys.iter().find(|pk| **pk != pubkeys[0]);
+
+        let mut coefficients = vec![];
+        let mut terms = vec![];
+        for pk in pubkeys {
+            let coefficient = if Some(pk) == second {
+                Scalar::ONE
+            } else {
+                hash_to_scalar("KeyAgg coefficient", &[&list_hash, &pk.serialize()])
+            };
+            terms.push(pk.mul_tweak(secp, &coefficient)?);
+            coefficients.push(coefficient);
+        }
+        let aggregate = PublicKey::combine_keys(&terms.iter().collect::<Vec<_>>())?;
+
+        Ok(KeyAggContext {
+            pubkeys: pubkeys.to_vec(),
+            coefficients,
+            aggregate,
+            gacc_negated: false,
+            tweak: None,
+        })
+    }
+
+    /// Applies the taproot output key tweak so the context signs for the
+    /// P2TR output with this aggregate as internal key.
+    pub fn with_taproot_tweak(
+        &self,
+        secp: &Secp256k1<All>,
+        merkle_root: Option<TapNodeHash>,
+    ) -> Result<Self, Error> {
+        let tweak = TapTweakHash::from_key_and_tweak(self.aggregate_xonly(), merkle_root).to_scalar();
+        self.with_tweak(secp, tweak, true)
+    }
+
+    /// BIP-327 ApplyTweak: adds `tweak` times G to the aggregate, after
+    /// negating it to even Y first if `xonly`.
+    pub fn with_tweak(&self, secp: &Secp256k1<All>, tweak: Scalar, xonly: bool) -> Result<Self, Error> {
+        let negate = xonly && self.aggregate_is_odd();
+        let base = if negate { self.aggregate.negate(secp) } else { self.aggregate };
+        let aggregate = base
+            .add_exp_tweak(secp, &tweak)
+            .map_err(|_| Error::Taproot("tweaked aggregate key is the point at infinity".into()))?;
+
+        // tacc = tweak + g * tacc
+        let previous = if negate { self.tweak.map(SecretKey::negate) } else { self.tweak };
+        let tweak = add_scalar(previous, &tweak).ok();
+
+        Ok(KeyAggContext {
+            aggregate,
+            gacc_negated: self.gacc_negated != negate,
+            tweak,
+            ..self.clone()
+        })
+    }
+
+    pub fn aggregate(&self) -> PublicKey {
+        self.aggregate
+    }
+
+    pub fn aggregate_xonly(&self) -> XOnlyPublicKey {
+        self.aggregate.x_only_public_key().0
+    }
+
+    fn coefficient(&self, pubkey: &PublicKey) -> Option<Scalar> {
+        self.pubkeys.iter().position(|pk| pk == pubkey).map(|i| self.coefficients[i])
+    }
+
+    fn aggregate_is_odd(&self) -> bool {
+        self.aggregate.x_only_public_key().1 == bitcoin::secp256k1::Parity::Odd
+    }
+}
+
+/// A signer's secret nonce pair. Must be used for exactly one signature.
+pub struct SecNonce {
+    k1: SecretKey,
+    k2: SecretKey,
+}
+
+impl SecNonce {
+    /// Reads `k1 || k2`, the first 64 bytes of a BIP-327 secnonce.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() != 64 {
+            return Err(Error::Parse(format!("secret nonce must be 64 bytes, got {}", bytes.len())));
+        }
+        Ok(SecNonce { k1: SecretKey::from_slice(&bytes[..32])?, k2: SecretKey::from_slice(&bytes[32..])? })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PubNonce {
+    pub r1: PublicKey,
+    pub r2: PublicKey,
+}
+
+impl PubNonce {
+    pub fn serialize(&self) -> [u8; 66] {
+        let mut bytes = [0; 66];
+        bytes[..33].copy_from_slice(&self.r1.serialize());
+        bytes[33..].copy_from_slice(&self.r2.serialize());
+        bytes
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() != 66 {
+            return Err(Error::Parse(format!("public nonce must be 66 bytes, got {}", bytes.len())));
+        }
+        Ok(PubNonce { r1: PublicKey::from_slice(&bytes[..33])?, r2: PublicKey::from_slice(&bytes[33..])? })
+    }
+}
+
+/// Sum of every signer's nonces. Either point may be the point at infinity
+/// (`None`), which BIP-327 encodes as 33 zero bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AggNonce {
+    pub r1: Option<PublicKey>,
+    pub r2: Option<PublicKey>,
+}
+
+impl AggNonce {
+    pub fn serialize(&self) -> [u8; 66] {
+        let mut bytes = [0; 66];
+        if let Some(r1) = self.r1 {
+            bytes[..33].copy_from_slice(&r1.serialize());
+        }
+        if let Some(r2) = self.r2 {
+            bytes[33..].copy_from_slice(&r2.serialize());
+        }
+        bytes
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() != 66 {
+            return Err(Error::Parse(format!("aggregate nonce must be 66 bytes, got {}", bytes.len())));
+        }
+        Ok(AggNonce { r1: point_or_infinity(&bytes[..33])?, r2: point_or_infinity(&bytes[33..])? })
+    }
+}
+
+fn point_or_infinity(bytes: &[u8]) -> Result<Option<PublicKey>, Error> {
+    if bytes.iter().all(|b| *b == 0) {
+        return Ok(None);
+    }
+    Ok(Some(PublicKey::from_slice(bytes)?))
+}
+
+pub fn nonce_gen(secp: &Secp256k1<All>) -> (SecNonce, PubNonce) {
+    let k1 = SecretKey::new(&mut rand::thread_rng());
+    let k2 = SecretKey::new(&mut rand::thread_rng());
+    let pub_nonce = PubNonce { r1: k1.public_key(secp), r2: k2.public_key(secp) };
+    (SecNonce { k1, k2 }, pub_nonce)
+}
+
+pub fn aggregate_nonces(nonces: &[PubNonce]) -> Result<AggNonce, Error> {
+    if nonces.is_empty() {
+        return Err(Error::Taproot("cannot aggregate an empty nonce set".into()));
+    }
+    // Combining only fails when the nonces cancel out
+    let r1: Vec<&PublicKey> = nonces.iter().map(|n| &n.r1).collect();
+    let r2: Vec<&PublicKey> = nonces.iter().map(|n| &n.r2).collect();
+    Ok(AggNonce { r1: PublicKey::combine_keys(&r1).ok(), r2: PublicKey::combine_keys(&r2).ok() })
+}
+
+/// Final nonce `R`, nonce coefficient `b` and challenge `e` for a session.
+struct Session {
+    r: PublicKey,
+    b: Scalar,
+    e: Scalar,
+}
+
+fn session(
+    secp: &Secp256k1<All>,
+    ctx: &KeyAggContext,
+    agg_nonce: &AggNonce,
+    msg: &[u8; 32],
+) -> Result<Session, Error> {
+    let q = ctx.aggregate_xonly().serialize();
+    let b = hash_to_scalar("MuSig/noncecoef", &[&agg_nonce.serialize(), &q, msg]);
+
+    // R = R1 + b * R2, replaced by G if that is the point at infinity
+    let b_r2 = agg_nonce.r2.map(|r2| r2.mul_tweak(secp, &b)).transpose()?;
+    let r = match (agg_nonce.r1, b_r2) {
+        (Some(r1), Some(b_r2)) => r1.combine(&b_r2).ok(),
+        (r1, b_r2) => r1.or(b_r2),
+    };
+    let r = match r {
+        Some(r) => r,
+        None => SecretKey::from_slice(&Scalar::ONE.to_be_bytes())?.public_key(secp),
+    };
+    let e = hash_to_scalar("BIP0340/challenge", &[&r.x_only_public_key().0.serialize(), &q, msg]);
+    Ok(Session { r, b, e })
+}
+
+/// Signer's partial signature over `msg`. Consumes the secret nonce so it
+/// cannot be reused.
+pub fn partial_sign(
+    secp: &Secp256k1<All>,
+    ctx: &KeyAggContext,
+    sec_nonce: SecNonce,
+    keypair: &Keypair,
+    agg_nonce: &AggNonce,
+    msg: &[u8; 32],
+) -> Result<Scalar, Error> {
+    let pubkey = keypair.public_key();
+    let coefficient = ctx.coefficient(&pubkey).ok_or_else(|| Error::Taproot("signer is not part of the aggregate key".into()))?;
+    let Session { r, b, e } = session(secp, ctx, agg_nonce, msg)?;
+
+    let (mut k1, mut k2) = (sec_nonce.k1, sec_nonce.k2);
+    if r.x_only_public_key().1 == bitcoin::secp256k1::Parity::Odd {
+        k1 = k1.negate();
+        k2 = k2.negate();
+    }
+
+    // d = g * gacc * sk, where g negates for an odd aggregate key
+    let mut d = keypair.secret_key();
+    if ctx.aggregate_is_odd() != ctx.gacc_negated {
+        d = d.negate();
+    }
+
+    let s = k1
+        .add_tweak(&Scalar::from(k2.mul_tweak(&b)?))?
+        .add_tweak(&Scalar::from(d.mul_tweak(&e)?.mul_tweak(&coefficient)?))?;
+    Ok(Scalar::from(s))
+}
+
+/// Combines every signer's partial signature into a BIP-340 signature for the
+/// (tweaked) aggregate key.
+pub fn aggregate_partial_sigs(
+    secp: &Secp256k1<All>,
+    ctx: &KeyAggContext,
+    agg_nonce: &AggNonce,
+    msg: &[u8; 32],
+    partial_sigs: &[Scalar],
+) -> Result<Signature, Error> {
+    let Session { r, e, .. } = session(secp, ctx, agg_nonce, msg)?;
+
+    let mut s: Option<SecretKey> = None;
+    for partial in partial_sigs {
+        s = Some(add_scalar(s, partial)?);
+    }
+    if let Some(tweak) = ctx.tweak {
+        let mut et = tweak.mul_tweak(&e)?;
+        if ctx.aggregate_is_odd() {
+            et = et.negate();
+        }
+        s = Some(add_scalar(s, &Scalar::from(et))?);
+    }
+    let s = s.ok_or_else(|| Error::Taproot("no partial signatures".into()))?;
+
+    let mut sig = [0u8; 64];
+    sig[..32].copy_from_slice(&r.x_only_public_key().0.serialize());
+    sig[32..].copy_from_slice(&s.secret_bytes());
+    Ok(Signature::from_slice(&sig)?)
+}
+
+fn add_scalar(acc: Option<SecretKey>, value: &Scalar) -> Result<SecretKey, bitcoin::secp256k1::Error> {
+    match acc {
+        Some(acc) => acc.add_tweak(value),
+        None => SecretKey::from_slice(&value.to_be_bytes()),
+    }
+}
+
+fn tagged_hash(tag: &str, parts: &[&[u8]]) -> [u8; 32] {
+    let tag_hash = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag_hash.as_byte_array());
+    engine.input(tag_hash.as_byte_array());
+    for part in parts {
+        engine.input(part);
+    }
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+fn hash_to_scalar(tag: &str, parts: &[&[u8]]) -> Scalar {
+    let hash = tagged_hash(tag, parts);
+    Scalar::from_be_bytes(hash).unwrap_or_else(|_| Scalar::from_be_bytes(sub_order(hash)).unwrap())
+}
+
+/// `value - n` for a 256-bit value at or above the group order.
+fn sub_order(value: [u8; 32]) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    let mut borrow = 0i16;
+    for i in (0..32).rev() {
+        let diff = value[i] as i16 - CURVE_ORDER[i] as i16 - borrow;
+        borrow = if diff < 0 { 1 } else { 0 };
+        out[i] = diff.rem_euclid(256) as u8;
+    }
+    out
+}
+
+/// Runs both MuSig2 rounds for signers whose keys are all held locally, as
+/// the regtest demos do when simulating leaf owners.
+pub fn sign_with_all(
+    secp: &Secp256k1<All>,
+    ctx: &KeyAggContext,
+    signers: &[&Keypair],
+    msg: &[u8; 32],
+) -> Result<Signature, Error> {
+    let (sec_nonces, pub_nonces): (Vec<_>, Vec<_>) = signers.iter().map(|_| nonce_gen(secp)).unzip();
+    let agg_nonce = aggregate_nonces(&pub_nonces)?;
+
+    let mut partial_sigs = vec![];
+    for (sec_nonce, keypair) in sec_nonces.into_iter().zip(signers) {
+        partial_sigs.push(partial_sign(secp, ctx, sec_nonce, keypair, &agg_nonce, msg)?);
+    }
+    aggregate_partial_sigs(secp, ctx, &agg_nonce, msg, &partial_sigs)
+}
@@ -0,0 +1,156 @@
+//! CTV payout trees whose nodes are P2TR outputs keyed to the MuSig2 aggregate
+//! of every leaf owner beneath them, with the CTV template as the only script
+//! leaf. Owners can unroll a node alone through the script path or spend it
+//! together through the key path.
+
+use std::collections::BTreeMap;
+
+use bitcoin::{
+    Address, Network, ScriptBuf, TxOut, Witness,
+    key::Secp256k1,
+    secp256k1::{All, PublicKey},
+    taproot::{TaprootBuilder, TaprootSpendInfo},
+};
+
+use crate::builder::{split_evenly, sum_values};
+use crate::ctv::{build_ctv_script, template_tx};
+use crate::error::Error;
+use crate::musig::KeyAggContext;
+use crate::taproot::tapscript_witness;
+use crate::tree::TreeNode;
+
+/// A payout output and the key of the miner it belongs to.
+#[derive(Clone, Debug)]
+pub struct LeafPayout {
+    pub owner: PublicKey,
+    pub output: TxOut,
+}
+
+/// How the output a tree node spends is locked.
+#[derive(Clone, Debug)]
+pub struct NodeLock {
+    /// Every leaf owner beneath the node, once each however many leaves they own.
+    pub owners: Vec<PublicKey>,
+    /// Untweaked aggregate of `owners`, the taproot internal key.
+    pub key_agg: KeyAggContext,
+    pub ctv_script: ScriptBuf,
+    pub spend_info: TaprootSpendInfo,
+}
+
+impl NodeLock {
+    pub fn new(
+        secp: &Secp256k1<All>,
+        owners: Vec<PublicKey>,
+        outputs: &[TxOut],
+    ) -> Result<Self, Error> {
+        let key_agg = KeyAggContext::new(secp, &owners)?;
+        let ctv_script = build_ctv_script(outputs);
+        let spend_info = TaprootBuilder::new()
+            .add_leaf(0, ctv_script.clone())?
+            .finalize(secp, key_agg.aggregate_xonly())
+            .map_err(|e| Error::Taproot(format!("taproot finalize failed: {e:?}")))?;
+        Ok(NodeLock { owners, key_agg, ctv_script, spend_info })
+    }
+
+    pub fn script_pubkey(&self) -> ScriptBuf {
+        ScriptBuf::new_p2tr_tweaked(self.spend_info.output_key())
+    }
+
+    pub fn address(&self, network: Network) -> Address {
+        Address::p2tr_tweaked(self.spend_info.output_key(), network)
+    }
+
+    /// Key aggregation context for a cooperative key-path spend of this output.
+    pub fn signing_context(&self, secp: &Secp256k1<All>) -> Result<KeyAggContext, Error> {
+        self.key_agg.with_taproot_tweak(secp, self.spend_info.merkle_root())
+    }
+
+    pub fn ctv_witness(&self) -> Result<Witness, Error> {
+        tapscript_witness(&[], &self.ctv_script, &self.spend_info)
+    }
+}
+
+/// A CTV tree plus the lock on the output each node spends, keyed by the path
+/// of vouts from the root (the root itself is `[]`).
+#[derive(Clone, Debug)]
+pub struct MusigTree {
+    pub root: TreeNode,
+    pub locks: BTreeMap<Vec<u32>, NodeLock>,
+}
+
+impl MusigTree {
+    pub fn node(&self, path: &[u32]) -> Option<&TreeNode> {
+        path.iter().try_fold(&self.root, |node, vout| node.child(*vout))
+    }
+
+    pub fn lock(&self, path: &[u32]) -> Option<&NodeLock> {
+        self.locks.get(path)
+    }
+}
+
+/// Builds a tree with at most `radix` outputs per node, paying `node_fee` at
+/// every level below the root. The root spends a `root_value` coinbase output
+/// and pays whatever is left over as fee.
+pub fn build_musig_tree(
+    secp: &Secp256k1<All>,
+    payouts: &[LeafPayout],
+    radix: usize,
+    node_fee: u64,
+    root_value: u64,
+) -> Result<MusigTree, Error> {
+    if payouts.is_empty() || radix < 2 {
+        return Err(Error::InvalidTemplate("a tree needs at least one payout and a radix of two or more".into()));
+    }
+
+    let mut locks = BTreeMap::new();
+    let mut root = build_node(secp, payouts, radix, node_fee, vec![], &mut locks)?;
+    if root_value < root.input_value {
+        return Err(Error::InsufficientValue { needed: root.input_value, available: root_value });
+    }
+    root.input_value = root_value;
+    Ok(MusigTree { root, locks })
+}
+
+fn build_node(
+    secp: &Secp256k1<All>,
+    payouts: &[LeafPayout],
+    radix: usize,
+    node_fee: u64,
+    path: Vec<u32>,
+    locks: &mut BTreeMap<Vec<u32>, NodeLock>,
+) -> Result<TreeNode, Error> {
+    let mut outputs = vec![];
+    let mut children = vec![];
+
+    if payouts.len() <= radix {
+        outputs.extend(payouts.iter().map(|p| p.output.clone()));
+    } else {
+        for (vout, chunk) in split_evenly(payouts, radix).into_iter().enumerate() {
+            let mut child_path = path.clone();
+            child_path.push(vout as u32);
+            let child = build_node(secp, chunk, radix, node_fee, child_path.clone(), locks)?;
+            outputs.push(TxOut {
+                value: bitcoin::Amount::from_sat(child.input_value),
+                script_pubkey: locks[&child_path].script_pubkey(),
+            });
+            children.push((vout as u32, child));
+        }
+    }
+
+    // An owner with several leaves below signs once, so their key goes in once
+    let mut owners: Vec<PublicKey> = vec![];
+    for payout in payouts {
+        if !owners.contains(&payout.owner) {
+            owners.push(payout.owner);
+        }
+    }
+    let lock = NodeLock::new(secp, owners, &outputs)?;
+    let mut tx = template_tx(outputs);
+    tx.input[0].witness = lock.ctv_witness()?;
+    locks.insert(path, lock);
+
+    let input_value = sum_values(&tx.output)?
+        .checked_add(node_fee)
+        .ok_or_else(|| Error::Policy("tree value overflows u64".into()))?;
+    Ok(TreeNode { tx, input_value, children })
+}
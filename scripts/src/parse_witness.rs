@@ -1,36 +1,50 @@
-use bitcoincore_rpc::{Auth, Client, RpcApi};
 use bitcoin::{
-    Txid, TxIn, ScriptBuf, Address,
+    Txid, TxIn, ScriptBuf, Address, Network,
     opcodes::all::OP_NOP4,
     blockdata::script::Instruction,
     Opcode,
 };
-use std::{env, path::Path};
-use hex;
+use std::env;
+use scripts::{
+    chain::{take_source_arg, ChainSource},
+    csfs::OP_CSFS,
+    rpc::{connect, take_network_arg, Chain, NodeRpc},
+};
 
 const OP_CTV: Opcode = OP_NOP4;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let txid_str = env::args().nth(1).expect("txid required");
-    let txid: Txid = txid_str.parse()?;
+    let mut args: Vec<String> = env::args().collect();
+    let source = take_source_arg(&mut args)?;
+    let chain = take_network_arg(&mut args)?;
 
-    let maybe_index = env::args().nth(2);
+    let txid_str = args.get(1).expect("txid required");
+    let txid: Txid = txid_str.parse()?;
 
-    let rpc = Client::new(
-        "http://127.0.0.1:18443/wallet/devwallet",
-        Auth::CookieFile(Path::new("./data/regtest/.cookie").to_path_buf()),
-    )?;
+    let maybe_index = args.get(2);
+
+    // An indexer cannot say which chain it serves, so that comes from
+    // --network (or $NETWORK); bitcoind is asked
+    let (source, network): (Box<dyn ChainSource>, Network) = match (source, chain) {
+        (Some(source), chain) => (source, chain.map_or_else(Chain::from_env, Ok)?.network()),
+        (None, Some(chain)) => (Box::new(connect(Some("devwallet"))?), chain.network()),
+        (None, None) => {
+            let rpc = connect(Some("devwallet"))?;
+            let network = rpc.network()?;
+            (Box::new(rpc), network)
+        }
+    };
 
-    let tx = rpc.get_raw_transaction(&txid, None)?;
+    let tx = source.fetch_transaction(&txid)?.ok_or("transaction not found")?;
 
     match maybe_index {
         Some(index_str) => {
             let index: usize = index_str.parse()?;
-            print_input_analysis(&rpc, &txid, index, &tx.input[index])?;
+            print_input_analysis(source.as_ref(), network, &txid, index, &tx.input[index])?;
         }
         None => {
             for (i, input) in tx.input.iter().enumerate() {
-                print_input_analysis(&rpc, &txid, i, input)?;
+                print_input_analysis(source.as_ref(), network, &txid, i, input)?;
             }
         }
     }
@@ -39,7 +53,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 fn print_input_analysis(
-    rpc: &Client,
+    source: &dyn ChainSource,
+    network: Network,
     txid: &Txid,
     index: usize,
     input: &TxIn,
@@ -54,11 +69,11 @@ fn print_input_analysis(
     let prev_txid = input.previous_output.txid;
     let vout = input.previous_output.vout;
 
-    let prev_tx = rpc.get_raw_transaction(&prev_txid, None)?;
+    let prev_tx = source.fetch_transaction(&prev_txid)?.ok_or("previous transaction not found")?;
     let spent_output = &prev_tx.output[vout as usize];
     let spk = &spent_output.script_pubkey;
 
-    let spend_type = classify_spk(spk);
+    let spend_type = classify_spk(spk, network);
     println!("  scriptPubKey type: {spend_type}");
 
     let contains_ctv = spk.instructions().any(|i| {
@@ -85,7 +100,7 @@ fn print_input_analysis(
         }
         "p2wsh" => {
             println!("  Witness script spend (P2WSH)\n");
-            parse_script_witness(&input.witness.last().unwrap())?;
+            parse_script_witness(input.witness.last().unwrap())?;
         }
         _ => {
             println!("  Unknown or non-segwit input type\n");
@@ -106,6 +121,9 @@ fn parse_script_witness(witness_bytes: &[u8]) -> Result<(), Box<dyn std::error::
             Ok(Instruction::Op(op)) if op == OP_CTV => {
                 println!("    Op(OP_CTV)");
             }
+            Ok(Instruction::Op(op)) if op == OP_CSFS => {
+                println!("    Op(OP_CHECKSIGFROMSTACK)");
+            }
             Ok(Instruction::Op(op)) => {
                 println!("    Op({:?})", op);
             }
@@ -121,8 +139,8 @@ fn parse_script_witness(witness_bytes: &[u8]) -> Result<(), Box<dyn std::error::
     Ok(())
 }
 
-fn classify_spk(spk: &ScriptBuf) -> String {
-    if let Ok(addr) = Address::from_script(spk, bitcoin::Network::Regtest) {
+fn classify_spk(spk: &ScriptBuf, network: Network) -> String {
+    if let Ok(addr) = Address::from_script(spk, network) {
         match addr.payload() {
             bitcoin::address::Payload::WitnessProgram(witprog) => {
                 match (witprog.version(), witprog.program().len()) {
@@ -0,0 +1,52 @@
+use std::path::Path;
+
+use bitcoincore_rpc::RpcApi;
+use bitcoin::{ScriptBuf, Txid};
+use scripts::{
+    balances::{tree_totals, BalanceStore, BALANCE_STORE},
+    rpc::connect,
+    tree::{load_unroll_data, unroll_path},
+};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let store = BalanceStore::load(Path::new(BALANCE_STORE))?;
+    store.reconcile()?;
+    println!(
+        "Books balance: {} blocks, {} sat owed to {} miners",
+        store.blocks.len(),
+        store.outstanding(),
+        store.balances.len(),
+    );
+
+    // Check every recorded block against the chain and the tree it committed to
+    let rpc = connect(None)?;
+    let mut pool_held = 0;
+    for block in &store.blocks {
+        let txid: Txid = block.coinbase_txid.parse()?;
+        let confirmations = rpc.get_raw_transaction_info(&txid, None)?.confirmations.unwrap_or(0);
+        if confirmations == 0 {
+            return Err(format!("coinbase {txid} is not in the chain").into());
+        }
+
+        let root = load_unroll_data(&unroll_path(&txid))?;
+        let (paid, pool) = tree_totals(&root, &ScriptBuf::from_hex(&block.pool_script)?);
+        if paid != block.paid || pool != block.pool_value {
+            return Err(format!(
+                "coinbase {txid} tree pays miners {paid} and the pool {pool} sat, recorded {} and {}",
+                block.paid, block.pool_value
+            )
+            .into());
+        }
+        pool_held += pool;
+        println!("  {txid} ok: {confirmations} confirmations, carried {} released {}", block.carried, block.released);
+    }
+
+    let retained: u64 = store.blocks.iter().map(|b| b.retained).sum();
+    println!("Pool outputs hold {pool_held} sat: {retained} retained and {} owed to miners", pool_held - retained);
+
+    for (address, balance) in &store.balances {
+        println!("  {address} {balance} sat");
+    }
+
+    Ok(())
+}
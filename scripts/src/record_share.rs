@@ -0,0 +1,31 @@
+use std::env;
+use std::path::Path;
+
+use bitcoincore_rpc::RpcApi;
+use bitcoin::Address;
+use scripts::{
+    rpc::{connect, ensure_wallet, Chain},
+    shares::{append_share, Share, SHARE_LEDGER},
+};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let difficulty: u64 = env::args().nth(1).expect("share difficulty required").parse()?;
+
+    let network = Chain::from_env()?.network();
+
+    // Credit a fresh wallet address unless the miner names one
+    let address = match env::args().nth(2) {
+        Some(address) if !address.is_empty() => address.parse::<Address<_>>()?.require_network(network)?,
+        _ => {
+            let rpc = connect(None)?;
+            ensure_wallet(&rpc, "devwallet")?;
+            rpc.get_new_address(None, None)?.require_network(network)?
+        }
+    };
+
+    let share = Share::now(&address, difficulty);
+    append_share(Path::new(SHARE_LEDGER), &share)?;
+    println!("Recorded share of difficulty {difficulty} for {address}");
+
+    Ok(())
+}
@@ -0,0 +1,186 @@
+use std::cell::Cell;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use bitcoincore_rpc::{Auth, Client, RpcApi};
+use bitcoin::{Address, Block, BlockHash, Network, Transaction, Txid};
+
+use crate::error::Error;
+
+/// The chain a devenv bitcoind runs, picked with `$NETWORK`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chain {
+    Regtest,
+    Signet,
+    Testnet,
+    Testnet4,
+    Bitcoin,
+}
+
+impl Chain {
+    /// `$NETWORK`, or regtest when it is unset.
+    pub fn from_env() -> Result<Chain, Error> {
+        match std::env::var("NETWORK") {
+            Ok(name) if !name.is_empty() => name.parse(),
+            _ => Ok(Chain::Regtest),
+        }
+    }
+
+    /// The network addresses are encoded for. Testnet4 shares testnet's.
+    pub fn network(self) -> Network {
+        match self {
+            Chain::Regtest => Network::Regtest,
+            Chain::Signet => Network::Signet,
+            Chain::Testnet | Chain::Testnet4 => Network::Testnet,
+            Chain::Bitcoin => Network::Bitcoin,
+        }
+    }
+
+    /// bitcoind's default RPC port on this chain.
+    pub fn rpc_port(self) -> u16 {
+        match self {
+            Chain::Regtest => 18443,
+            Chain::Signet => 38332,
+            Chain::Testnet => 18332,
+            Chain::Testnet4 => 48332,
+            Chain::Bitcoin => 8332,
+        }
+    }
+
+    /// Where bitcoind writes the RPC cookie under `./data`.
+    pub fn cookie_path(self) -> PathBuf {
+        let dir = match self {
+            Chain::Regtest => "regtest",
+            Chain::Signet => "signet",
+            Chain::Testnet => "testnet3",
+            Chain::Testnet4 => "testnet4",
+            Chain::Bitcoin => "",
+        };
+        PathBuf::from("./data").join(dir).join(".cookie")
+    }
+}
+
+impl FromStr for Chain {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "regtest" => Ok(Chain::Regtest),
+            "signet" => Ok(Chain::Signet),
+            "testnet" | "testnet3" | "test" => Ok(Chain::Testnet),
+            "testnet4" => Ok(Chain::Testnet4),
+            "bitcoin" | "mainnet" | "main" => Ok(Chain::Bitcoin),
+            other => Err(Error::Parse(format!("unknown network {other:?}"))),
+        }
+    }
+}
+
+/// Removes `--network <chain>` from the arguments, leaving the positional
+/// ones. For tools reading an indexer, which cannot be asked for its chain.
+pub fn take_network_arg(args: &mut Vec<String>) -> Result<Option<Chain>, Error> {
+    let Some(i) = args.iter().position(|arg| arg == "--network") else {
+        return Ok(None);
+    };
+    if i + 1 >= args.len() {
+        return Err(Error::Parse("--network needs a chain name".into()));
+    }
+    let name = args.remove(i + 1);
+    args.remove(i);
+    Ok(Some(name.parse()?))
+}
+
+/// A bitcoind RPC client that asks the node for its chain once and keeps the
+/// answer, instead of on every address it hands out.
+pub struct NodeClient {
+    client: Client,
+    network: Cell<Option<Network>>,
+}
+
+impl NodeClient {
+    pub fn new(client: Client) -> Self {
+        NodeClient { client, network: Cell::new(None) }
+    }
+}
+
+impl RpcApi for NodeClient {
+    fn call<T: for<'a> serde::de::Deserialize<'a>>(
+        &self,
+        cmd: &str,
+        args: &[serde_json::Value],
+    ) -> bitcoincore_rpc::Result<T> {
+        self.client.call(cmd, args)
+    }
+}
+
+/// Connects to the devenv bitcoind for `$NETWORK`, optionally scoped to a wallet.
+pub fn connect(wallet: Option<&str>) -> Result<NodeClient, Error> {
+    let chain = Chain::from_env()?;
+    let url = format!("http://127.0.0.1:{}", chain.rpc_port());
+    let url = match wallet {
+        Some(name) => format!("{url}/wallet/{name}"),
+        None => url,
+    };
+    Ok(NodeClient::new(Client::new(&url, Auth::CookieFile(chain.cookie_path()))?))
+}
+
+pub fn ensure_wallet(rpc: &NodeClient, wallet_name: &str) -> Result<(), bitcoincore_rpc::Error> {
+    let _ = rpc.create_wallet(wallet_name, None, None, None, None);
+    if !rpc.list_wallets()?.contains(&wallet_name.to_string()) {
+        rpc.load_wallet(wallet_name)?;
+    }
+    Ok(())
+}
+
+/// The node calls the mining flows need, so they can run against bitcoind or
+/// the in-memory chain in `mock`.
+pub trait NodeRpc {
+    /// The chain the node is on, which every address it hands out is for.
+    fn network(&self) -> Result<Network, Error>;
+
+    /// A fresh wallet address.
+    fn new_address(&self) -> Result<Address, Error>;
+
+    fn get_block(&self, hash: &BlockHash) -> Result<Block, Error>;
+
+    fn generate_to_address(&self, blocks: u64, address: &Address) -> Result<Vec<BlockHash>, Error>;
+
+    fn send_raw_transaction(&self, tx: &Transaction) -> Result<Txid, Error>;
+
+    fn get_raw_transaction(&self, txid: &Txid) -> Result<Transaction, Error>;
+}
+
+impl NodeRpc for NodeClient {
+    /// Read from the raw `getblockchaininfo`, whose typed form cannot parse
+    /// chains newer than our `bitcoin` crate, such as `testnet4`.
+    fn network(&self) -> Result<Network, Error> {
+        if let Some(network) = self.network.get() {
+            return Ok(network);
+        }
+        let info: serde_json::Value = self.call("getblockchaininfo", &[])?;
+        let chain = info["chain"].as_str().ok_or_else(|| Error::Node("getblockchaininfo has no chain".into()))?;
+        let network = chain.parse::<Chain>()?.network();
+        self.network.set(Some(network));
+        Ok(network)
+    }
+
+    fn new_address(&self) -> Result<Address, Error> {
+        let network = NodeRpc::network(self)?;
+        Ok(RpcApi::get_new_address(self, None, None)?.require_network(network)?)
+    }
+
+    fn get_block(&self, hash: &BlockHash) -> Result<Block, Error> {
+        Ok(RpcApi::get_block(self, hash)?)
+    }
+
+    fn generate_to_address(&self, blocks: u64, address: &Address) -> Result<Vec<BlockHash>, Error> {
+        Ok(RpcApi::generate_to_address(self, blocks, address)?)
+    }
+
+    fn send_raw_transaction(&self, tx: &Transaction) -> Result<Txid, Error> {
+        Ok(RpcApi::send_raw_transaction(self, tx)?)
+    }
+
+    fn get_raw_transaction(&self, txid: &Txid) -> Result<Transaction, Error> {
+        Ok(RpcApi::get_raw_transaction(self, txid, None)?)
+    }
+}
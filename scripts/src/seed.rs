@@ -0,0 +1,120 @@
+//! Deterministic keys and addresses for reproducible runs. With `--seed` the
+//! mining scripts derive their internal keys and every address they pay from
+//! a BIP-32 master key instead of `thread_rng` and the node's wallet, so the
+//! same seed and chain give the same CTV hashes and transactions.
+
+use std::cell::Cell;
+use std::str::FromStr;
+
+use bitcoin::{
+    bip32::{ChildNumber, DerivationPath, Xpriv},
+    key::{Keypair, Secp256k1},
+    secp256k1::All,
+    Address, Block, BlockHash, Network, Transaction, Txid,
+};
+
+use crate::error::Error;
+use crate::rpc::NodeRpc;
+
+/// Hardened path the contract keys are derived under, one child per key.
+const KEY_PATH: &str = "m/0'";
+/// BIP-86 receive path, so the addresses match a descriptor wallet.
+const ADDRESS_PATH: &str = "m/86'/{coin}'/0'/0";
+
+pub struct Seed {
+    master: Xpriv,
+    secp: Secp256k1<All>,
+    next_key: Cell<u32>,
+    next_address: Cell<u32>,
+}
+
+impl Seed {
+    /// A seed from 16 to 64 bytes of hex.
+    pub fn from_hex(hex: &str) -> Result<Self, Error> {
+        let bytes = hex::decode(hex)?;
+        if !(16..=64).contains(&bytes.len()) {
+            return Err(Error::Parse(format!("seed must be 16 to 64 bytes, got {}", bytes.len())));
+        }
+        Ok(Seed {
+            master: Xpriv::new_master(Network::Regtest, &bytes)?,
+            secp: Secp256k1::new(),
+            next_key: Cell::new(0),
+            next_address: Cell::new(0),
+        })
+    }
+
+    /// The next contract key, `m/0'/<n>'`.
+    pub fn keypair(&self) -> Result<Keypair, Error> {
+        let index = self.next_key.replace(self.next_key.get() + 1);
+        let path = DerivationPath::from_str(KEY_PATH)?.child(ChildNumber::from_hardened_idx(index)?);
+        Ok(self.master.derive_priv(&self.secp, &path)?.to_keypair(&self.secp))
+    }
+
+    /// The next key path P2TR address on `network`, `m/86'/<coin>'/0'/0/<n>`
+    /// with coin type 0 on mainnet and 1 everywhere else.
+    pub fn address(&self, network: Network) -> Result<Address, Error> {
+        let index = self.next_address.replace(self.next_address.get() + 1);
+        let coin = if network == Network::Bitcoin { "0" } else { "1" };
+        let path = DerivationPath::from_str(&ADDRESS_PATH.replace("{coin}", coin))?
+            .child(ChildNumber::from_normal_idx(index)?);
+        let (xonly, _) = self.master.derive_priv(&self.secp, &path)?.to_keypair(&self.secp).x_only_public_key();
+        Ok(Address::p2tr(&self.secp, xonly, None, network))
+    }
+}
+
+/// A seeded key if there is a seed, a random one otherwise.
+pub fn new_keypair(secp: &Secp256k1<All>, seed: Option<&Seed>) -> Result<Keypair, Error> {
+    match seed {
+        Some(seed) => seed.keypair(),
+        None => Ok(Keypair::new(secp, &mut rand::thread_rng())),
+    }
+}
+
+/// Removes `--seed <hex>` from the arguments, leaving the positional ones.
+pub fn take_seed_arg(args: &mut Vec<String>) -> Result<Option<Seed>, Error> {
+    let Some(i) = args.iter().position(|arg| arg == "--seed") else {
+        return Ok(None);
+    };
+    if i + 1 >= args.len() {
+        return Err(Error::Parse("--seed needs a hex value".into()));
+    }
+    let hex = args.remove(i + 1);
+    args.remove(i);
+    Ok(Some(Seed::from_hex(&hex)?))
+}
+
+/// Passes every call through to `rpc`, but hands out seeded addresses when
+/// there is a seed.
+pub struct SeededRpc<'a, R: ?Sized> {
+    pub rpc: &'a R,
+    pub seed: Option<&'a Seed>,
+}
+
+impl<R: NodeRpc + ?Sized> NodeRpc for SeededRpc<'_, R> {
+    fn network(&self) -> Result<Network, Error> {
+        self.rpc.network()
+    }
+
+    fn new_address(&self) -> Result<Address, Error> {
+        match self.seed {
+            Some(seed) => seed.address(self.rpc.network()?),
+            None => self.rpc.new_address(),
+        }
+    }
+
+    fn get_block(&self, hash: &BlockHash) -> Result<Block, Error> {
+        self.rpc.get_block(hash)
+    }
+
+    fn generate_to_address(&self, blocks: u64, address: &Address) -> Result<Vec<BlockHash>, Error> {
+        self.rpc.generate_to_address(blocks, address)
+    }
+
+    fn send_raw_transaction(&self, tx: &Transaction) -> Result<Txid, Error> {
+        self.rpc.send_raw_transaction(tx)
+    }
+
+    fn get_raw_transaction(&self, txid: &Txid) -> Result<Transaction, Error> {
+        self.rpc.get_raw_transaction(txid)
+    }
+}
@@ -0,0 +1,251 @@
+//! Append-only share ledger and the payout schemes (PPLNS, TIDES, FPPS) that
+//! turn it into a payout manifest for the CTV builders.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bitcoin::{Address, Amount, Network, TxOut};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// Ledger file the share scripts append to, one JSON share per line.
+pub const SHARE_LEDGER: &str = "./data/shares.jsonl";
+
+/// A share a miner submitted, credited to the address it wants paid to.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Share {
+    pub address: String,
+    pub difficulty: u64,
+    /// Unix time in seconds.
+    pub timestamp: u64,
+}
+
+impl Share {
+    pub fn now(address: &Address, difficulty: u64) -> Self {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        Share { address: address.to_string(), difficulty, timestamp }
+    }
+}
+
+/// Appends `share` to the ledger at `path`. Existing lines are never rewritten.
+pub fn append_share(path: &Path, share: &Share) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(share)?)?;
+    Ok(())
+}
+
+/// Every share in the ledger, oldest first. A missing ledger has no shares.
+pub fn load_shares(path: &Path) -> Result<Vec<Share>, Error> {
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+/// One miner's cut of a block reward.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub address: String,
+    /// Difficulty this miner has inside the window.
+    pub weight: u64,
+    pub value: u64,
+}
+
+/// Who gets paid what for a found block, ready to become tree leaves.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PayoutManifest {
+    pub entries: Vec<ManifestEntry>,
+    /// Part of the reward no miner is owed, kept by the pool.
+    pub retained: u64,
+}
+
+impl PayoutManifest {
+    pub fn total(&self) -> u64 {
+        self.entries.iter().map(|e| e.value).sum()
+    }
+
+    /// Payout outputs in manifest order, for the flat and tree builders.
+    pub fn to_outputs(&self, network: Network) -> Result<Vec<TxOut>, Error> {
+        self.entries
+            .iter()
+            .map(|entry| {
+                let address = entry.address.parse::<Address<_>>()?.require_network(network)?;
+                Ok(TxOut { value: Amount::from_sat(entry.value), script_pubkey: address.script_pubkey() })
+            })
+            .collect()
+    }
+}
+
+/// Splits `reward` over the newest shares adding up to `window` difficulty.
+/// The oldest share in the window only counts for the part that fits.
+/// Entries are ordered by first appearance in the window, newest first.
+pub fn pplns_manifest(
+    shares: &[Share],
+    window: u64,
+    reward: u64,
+) -> Result<PayoutManifest, Error> {
+    let mut weights: Vec<(String, u64)> = vec![];
+    let mut remaining = window;
+    for share in shares.iter().rev() {
+        if remaining == 0 {
+            break;
+        }
+        let counted = share.difficulty.min(remaining);
+        remaining -= counted;
+        match weights.iter_mut().find(|(address, _)| *address == share.address) {
+            Some((_, weight)) => *weight += counted,
+            None => weights.push((share.address.clone(), counted)),
+        }
+    }
+
+    let total: u64 = weights.iter().map(|(_, w)| w).sum();
+    if total == 0 {
+        return Err(Error::Payout("no shares in the PPLNS window".into()));
+    }
+
+    let mut entries: Vec<ManifestEntry> = weights
+        .into_iter()
+        .map(|(address, weight)| {
+            let value = (reward as u128 * weight as u128 / total as u128) as u64;
+            ManifestEntry { address, weight, value }
+        })
+        .collect();
+
+    // Rounding dust goes to the miner with the most weight
+    let paid: u64 = entries.iter().map(|e| e.value).sum();
+    if let Some(top) = entries.iter_mut().max_by_key(|e| e.weight) {
+        top.value += reward - paid;
+    }
+    Ok(PayoutManifest { entries, retained: 0 })
+}
+
+/// Turns a share ledger and the reward of a found block into payouts.
+pub trait PayoutScheme {
+    fn name(&self) -> &'static str;
+
+    fn manifest(&self, shares: &[Share], reward: u64) -> Result<PayoutManifest, Error>;
+}
+
+/// Pay per last N shares, N being a fixed amount of share difficulty.
+#[derive(Clone, Copy, Debug)]
+pub struct Pplns {
+    pub window: u64,
+}
+
+impl PayoutScheme for Pplns {
+    fn name(&self) -> &'static str {
+        "PPLNS"
+    }
+
+    fn manifest(&self, shares: &[Share], reward: u64) -> Result<PayoutManifest, Error> {
+        pplns_manifest(shares, self.window, reward)
+    }
+}
+
+/// Ocean's TIDES: PPLNS whose window is a multiple of the network difficulty,
+/// so it stretches and shrinks with the network instead of the pool.
+#[derive(Clone, Copy, Debug)]
+pub struct Tides {
+    pub network_difficulty: u64,
+    pub multiplier: u64,
+}
+
+impl Tides {
+    pub const DEFAULT_MULTIPLIER: u64 = 8;
+}
+
+impl PayoutScheme for Tides {
+    fn name(&self) -> &'static str {
+        "TIDES"
+    }
+
+    fn manifest(&self, shares: &[Share], reward: u64) -> Result<PayoutManifest, Error> {
+        pplns_manifest(shares, self.network_difficulty.saturating_mul(self.multiplier), reward)
+    }
+}
+
+/// Full pay per share: every share submitted after `since` earns its
+/// expected value of the block reward, fees included, whether or not the
+/// pool found the block. Whatever those shares are not owed stays with the
+/// pool; if they are owed more than this block pays, everyone is scaled
+/// down and the pool settles the difference off-chain.
+#[derive(Clone, Copy, Debug)]
+pub struct Fpps {
+    pub network_difficulty: u64,
+    /// Unix time of the previous payout; older shares were already paid.
+    pub since: u64,
+}
+
+impl PayoutScheme for Fpps {
+    fn name(&self) -> &'static str {
+        "FPPS"
+    }
+
+    fn manifest(&self, shares: &[Share], reward: u64) -> Result<PayoutManifest, Error> {
+        if self.network_difficulty == 0 {
+            return Err(Error::Payout("FPPS needs a non-zero network difficulty".into()));
+        }
+
+        let mut weights: Vec<(String, u64)> = vec![];
+        for share in shares.iter().filter(|share| share.timestamp > self.since) {
+            match weights.iter_mut().find(|(address, _)| *address == share.address) {
+                Some((_, weight)) => *weight += share.difficulty,
+                None => weights.push((share.address.clone(), share.difficulty)),
+            }
+        }
+        let total: u64 = weights.iter().map(|(_, w)| w).sum();
+        if total == 0 {
+            return Err(Error::Payout("no shares since the last FPPS payout".into()));
+        }
+
+        // Every difficulty unit earns reward / network difficulty
+        let owed = reward as u128 * total as u128 / self.network_difficulty as u128;
+        let payable = owed.min(reward as u128) as u64;
+        let mut entries: Vec<ManifestEntry> = weights
+            .into_iter()
+            .map(|(address, weight)| {
+                let value = (payable as u128 * weight as u128 / total as u128) as u64;
+                ManifestEntry { address, weight, value }
+            })
+            .collect();
+
+        let paid: u64 = entries.iter().map(|e| e.value).sum();
+        if let Some(top) = entries.iter_mut().max_by_key(|e| e.weight) {
+            top.value += payable - paid;
+        }
+        Ok(PayoutManifest { entries, retained: reward - payable })
+    }
+}
+
+/// Parses `pplns:<window>`, `tides:<network difficulty>[:<multiplier>]` or
+/// `fpps:<network difficulty>[:<since>]`.
+pub fn parse_scheme(spec: &str) -> Result<Box<dyn PayoutScheme>, Error> {
+    let parts: Vec<&str> = spec.split(':').collect();
+    let number = |i: usize, default: Option<u64>| -> Result<u64, Error> {
+        match (parts.get(i), default) {
+            (Some(value), _) => Ok(value.parse()?),
+            (None, Some(default)) => Ok(default),
+            (None, None) => Err(Error::Parse(format!("payout scheme {spec} is missing a parameter"))),
+        }
+    };
+
+    match parts[0] {
+        "pplns" => Ok(Box::new(Pplns { window: number(1, None)? })),
+        "tides" => Ok(Box::new(Tides {
+            network_difficulty: number(1, None)?,
+            multiplier: number(2, Some(Tides::DEFAULT_MULTIPLIER))?,
+        })),
+        "fpps" => Ok(Box::new(Fpps { network_difficulty: number(1, None)?, since: number(2, Some(0))? })),
+        other => Err(Error::Parse(format!("unknown payout scheme {other}, expected pplns, tides or fpps"))),
+    }
+}
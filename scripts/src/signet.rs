@@ -0,0 +1,387 @@
+//! Block production for a local custom signet whose challenge key we hold.
+//!
+//! Instead of `generatetoaddress`, every block is built from
+//! `getblocktemplate`, signed with the challenge key (the BIP-325 solution
+//! goes in an extra push of the coinbase witness commitment), ground to the
+//! signet proof of work and submitted, the way blocks are made on any signet.
+//! The challenge is a P2WPKH: solutions are checked without taproot rules,
+//! so a P2TR challenge would be anyone-can-spend.
+
+use std::fs;
+use std::path::Path;
+
+use bitcoincore_rpc::RpcApi;
+use bitcoin::{
+    absolute::LockTime,
+    block::{Header, Version as BlockVersion},
+    consensus::{deserialize, Encodable},
+    ecdsa,
+    hashes::Hash,
+    key::Secp256k1,
+    opcodes::{all::OP_RETURN, OP_0},
+    script::{Builder, Instruction, PushBytesBuf},
+    secp256k1::{Message, PublicKey, SecretKey},
+    sighash::{EcdsaSighashType, SighashCache},
+    transaction::Version,
+    Address, Amount, Block, BlockHash, CompactTarget, Network, OutPoint, ScriptBuf, Sequence,
+    Transaction, TxIn, TxMerkleNode, TxOut, Txid, WPubkeyHash, Witness,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::rpc::{Chain, NodeClient, NodeRpc};
+
+/// Where `signet_key` keeps the challenge key.
+pub const SIGNET_KEY: &str = "./data/signet_key.json";
+
+/// Marks the solution push in the witness commitment output.
+const SIGNET_HEADER: [u8; 4] = [0xec, 0xc7, 0xda, 0xa2];
+const WITNESS_COMMITMENT_HEADER: [u8; 4] = [0xaa, 0x21, 0xa9, 0xed];
+const WITNESS_RESERVED_VALUE: [u8; 32] = [0; 32];
+
+/// The key whose signature makes a block valid on our signet.
+pub struct SignetKey {
+    secret: SecretKey,
+}
+
+impl From<SecretKey> for SignetKey {
+    fn from(secret: SecretKey) -> Self {
+        SignetKey { secret }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyFile {
+    secret_key: String,
+}
+
+impl SignetKey {
+    pub fn generate() -> Self {
+        SignetKey { secret: SecretKey::new(&mut rand::thread_rng()) }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let file: KeyFile = serde_json::from_str(&fs::read_to_string(path)?)?;
+        Ok(SignetKey { secret: SecretKey::from_slice(&hex::decode(file.secret_key)?)? })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = KeyFile { secret_key: hex::encode(self.secret.secret_bytes()) };
+        fs::write(path, serde_json::to_string_pretty(&file)?)?;
+        Ok(())
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.secret.public_key(&Secp256k1::new())
+    }
+
+    /// The script to start bitcoind with as `-signetchallenge`.
+    pub fn challenge(&self) -> ScriptBuf {
+        ScriptBuf::new_p2wpkh(&WPubkeyHash::hash(&self.public_key().serialize()))
+    }
+}
+
+/// The parts of a `getblocktemplate` result a block is built from.
+#[derive(Clone, Debug, Deserialize)]
+pub struct BlockTemplate {
+    pub version: i32,
+    #[serde(rename = "previousblockhash")]
+    pub previous_block_hash: BlockHash,
+    pub transactions: Vec<TemplateTransaction>,
+    #[serde(rename = "coinbasevalue")]
+    pub coinbase_value: u64,
+    /// Compact target in hex.
+    pub bits: String,
+    pub height: u32,
+    #[serde(rename = "curtime")]
+    pub current_time: u32,
+    #[serde(default)]
+    pub signet_challenge: ScriptBuf,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TemplateTransaction {
+    /// The raw transaction in hex.
+    pub data: String,
+}
+
+pub fn get_block_template(rpc: &NodeClient) -> Result<BlockTemplate, Error> {
+    let request = serde_json::json!({ "rules": ["segwit", "signet"] });
+    Ok(rpc.call("getblocktemplate", &[request])?)
+}
+
+/// An unsigned block on `template` whose coinbase pays everything to
+/// `payout`, with a witness commitment for the solution to go in.
+pub fn build_block(template: &BlockTemplate, payout: &ScriptBuf) -> Result<Block, Error> {
+    let coinbase = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            // BIP-34 height, padded so the script is never shorter than 2 bytes
+            script_sig: Builder::new().push_int(i64::from(template.height)).push_opcode(OP_0).into_script(),
+            sequence: Sequence::MAX,
+            witness: Witness::from_slice(&[WITNESS_RESERVED_VALUE]),
+        }],
+        output: vec![TxOut { value: Amount::from_sat(template.coinbase_value), script_pubkey: payout.clone() }],
+    };
+
+    let mut txdata = vec![coinbase];
+    for tx in &template.transactions {
+        txdata.push(deserialize(&hex::decode(&tx.data)?)?);
+    }
+
+    let mut block = Block {
+        header: Header {
+            version: BlockVersion::from_consensus(template.version),
+            prev_blockhash: template.previous_block_hash,
+            merkle_root: TxMerkleNode::all_zeros(),
+            time: template.current_time,
+            bits: CompactTarget::from_consensus(u32::from_str_radix(&template.bits, 16)?),
+            nonce: 0,
+        },
+        txdata,
+    };
+
+    // The coinbase's wtxid counts as zero, so the commitment can go in after
+    let witness_root = block.witness_root().ok_or(Error::InvalidTemplate("block has no transactions".into()))?;
+    let commitment = Block::compute_witness_commitment(&witness_root, &WITNESS_RESERVED_VALUE);
+    let mut data = PushBytesBuf::from(WITNESS_COMMITMENT_HEADER);
+    data.extend_from_slice(commitment.as_byte_array()).map_err(|e| Error::InvalidTemplate(e.to_string()))?;
+    block.txdata[0].output.push(TxOut {
+        value: Amount::ZERO,
+        script_pubkey: Builder::new().push_opcode(OP_RETURN).push_slice(data).into_script(),
+    });
+    update_merkle_root(&mut block)?;
+    Ok(block)
+}
+
+/// The BIP-325 `to_spend` transaction for `block`. Its scriptSig commits to
+/// the header fields and to the merkle root of the block with the solution
+/// cut out of its signet push, so adding the solution does not change what
+/// was signed.
+pub fn to_spend(block: &Block, challenge: &ScriptBuf) -> Result<Transaction, Error> {
+    let mut unsolved = block.clone();
+    let index = commitment_index(&unsolved)?;
+    let (script, _) = split_solution(&unsolved.txdata[0].output[index].script_pubkey)?;
+    unsolved.txdata[0].output[index].script_pubkey = script;
+    let merkle_root = unsolved.compute_merkle_root().ok_or(Error::InvalidTemplate("block has no transactions".into()))?;
+
+    let mut block_data = vec![];
+    block.header.version.consensus_encode(&mut block_data)?;
+    block.header.prev_blockhash.consensus_encode(&mut block_data)?;
+    merkle_root.consensus_encode(&mut block_data)?;
+    block.header.time.consensus_encode(&mut block_data)?;
+    let block_data = PushBytesBuf::try_from(block_data).map_err(|e| Error::InvalidTemplate(e.to_string()))?;
+
+    Ok(Transaction {
+        version: Version(0),
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: Builder::new().push_opcode(OP_0).push_slice(block_data).into_script(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut { value: Amount::ZERO, script_pubkey: challenge.clone() }],
+    })
+}
+
+/// The BIP-325 `to_sign` transaction, spending `to_spend`'s only output; the
+/// block solution is its scriptSig and witness.
+pub fn to_sign(to_spend: &Transaction) -> Transaction {
+    Transaction {
+        version: Version(0),
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint { txid: to_spend.txid(), vout: 0 },
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut { value: Amount::ZERO, script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script() }],
+    }
+}
+
+/// Signs `block` for the challenge of `key` and puts the solution in the
+/// witness commitment, replacing any earlier one. The header still needs
+/// its proof of work afterwards.
+pub fn sign_block(block: &mut Block, key: &SignetKey) -> Result<(), Error> {
+    let secp = Secp256k1::new();
+    let challenge = key.challenge();
+
+    // Sign over a bare signet header push, which is what the solution is cut back to
+    let index = commitment_index(block)?;
+    let commitment = strip_signet_push(&block.txdata[0].output[index].script_pubkey)?;
+    block.txdata[0].output[index].script_pubkey = Builder::from(commitment.to_bytes()).push_slice(SIGNET_HEADER).into_script();
+    update_merkle_root(block)?;
+    let to_spend = to_spend(block, &challenge)?;
+    let to_sign = to_sign(&to_spend);
+
+    let sighash = SighashCache::new(&to_sign).p2wpkh_signature_hash(0, &challenge, Amount::ZERO, EcdsaSighashType::All)?;
+    let signature = ecdsa::Signature {
+        sig: secp.sign_ecdsa(&Message::from_digest(sighash.to_byte_array()), &key.secret),
+        hash_ty: EcdsaSighashType::All,
+    };
+    let witness = Witness::p2wpkh(&signature, &key.public_key());
+
+    // Solution: the scriptSig then the witness stack, as consensus encoded
+    let mut solution = PushBytesBuf::from(SIGNET_HEADER);
+    let mut encoded = vec![];
+    ScriptBuf::new().consensus_encode(&mut encoded)?;
+    witness.consensus_encode(&mut encoded)?;
+    solution.extend_from_slice(&encoded).map_err(|e| Error::InvalidTemplate(e.to_string()))?;
+
+    block.txdata[0].output[index].script_pubkey = Builder::from(commitment.into_bytes()).push_slice(solution).into_script();
+    update_merkle_root(block)
+}
+
+/// The signet solution in `block`'s witness commitment, if there is one.
+pub fn block_solution(block: &Block) -> Result<Option<(ScriptBuf, Witness)>, Error> {
+    let index = commitment_index(block)?;
+    let (_, solution) = split_solution(&block.txdata[0].output[index].script_pubkey)?;
+    let Some(solution) = solution else {
+        return Ok(None);
+    };
+    let mut reader = solution.as_slice();
+    let script_sig = bitcoin::consensus::Decodable::consensus_decode(&mut reader)?;
+    let witness = bitcoin::consensus::Decodable::consensus_decode(&mut reader)?;
+    if !reader.is_empty() {
+        return Err(Error::Parse("trailing bytes after the signet solution".into()));
+    }
+    Ok(Some((script_sig, witness)))
+}
+
+/// Grinds the nonce, and the time whenever the nonces run out, until the
+/// header meets its own target.
+pub fn grind(header: &mut Header) {
+    let target = header.target();
+    loop {
+        for nonce in 0..=u32::MAX {
+            header.nonce = nonce;
+            if target.is_met_by(header.block_hash()) {
+                return;
+            }
+        }
+        header.time += 1;
+    }
+}
+
+/// Mines one block paying its coinbase to `payout`: template, signature,
+/// proof of work, `submitblock`.
+pub fn mine_block(rpc: &NodeClient, key: &SignetKey, payout: &ScriptBuf) -> Result<BlockHash, Error> {
+    let template = get_block_template(rpc)?;
+    if template.signet_challenge != key.challenge() {
+        return Err(Error::Node(format!(
+            "the node's signet challenge is {}, not our key's {}",
+            template.signet_challenge.to_hex_string(),
+            key.challenge().to_hex_string(),
+        )));
+    }
+    let mut block = build_block(&template, payout)?;
+    sign_block(&mut block, key)?;
+    grind(&mut block.header);
+    rpc.submit_block(&block)?;
+    Ok(block.block_hash())
+}
+
+/// Passes every call through to `rpc` except `generate_to_address`, which
+/// signs and mines real signet blocks instead.
+pub struct SignetMiner<'a> {
+    pub rpc: &'a NodeClient,
+    pub key: SignetKey,
+}
+
+/// A miner for our custom signet when `$NETWORK` is signet, `None` on every
+/// other chain, where `generatetoaddress` does the mining.
+pub fn signet_miner(rpc: &NodeClient) -> Result<Option<SignetMiner<'_>>, Error> {
+    if Chain::from_env()? != Chain::Signet {
+        return Ok(None);
+    }
+    let path = Path::new(SIGNET_KEY);
+    if !path.exists() {
+        return Err(Error::Node(format!("no signet key at {SIGNET_KEY}, start the node with `just signet-node`")));
+    }
+    Ok(Some(SignetMiner { rpc, key: SignetKey::load(path)? }))
+}
+
+impl NodeRpc for SignetMiner<'_> {
+    fn network(&self) -> Result<Network, Error> {
+        NodeRpc::network(self.rpc)
+    }
+
+    fn new_address(&self) -> Result<Address, Error> {
+        NodeRpc::new_address(self.rpc)
+    }
+
+    fn get_block(&self, hash: &BlockHash) -> Result<Block, Error> {
+        NodeRpc::get_block(self.rpc, hash)
+    }
+
+    fn generate_to_address(&self, blocks: u64, address: &Address) -> Result<Vec<BlockHash>, Error> {
+        (0..blocks).map(|_| mine_block(self.rpc, &self.key, &address.script_pubkey())).collect()
+    }
+
+    fn send_raw_transaction(&self, tx: &Transaction) -> Result<Txid, Error> {
+        NodeRpc::send_raw_transaction(self.rpc, tx)
+    }
+
+    fn get_raw_transaction(&self, txid: &Txid) -> Result<Transaction, Error> {
+        NodeRpc::get_raw_transaction(self.rpc, txid)
+    }
+}
+
+/// Index of the witness commitment output; like bitcoind, the last one wins.
+fn commitment_index(block: &Block) -> Result<usize, Error> {
+    let coinbase = block.txdata.first().ok_or(Error::InvalidTemplate("block has no coinbase".into()))?;
+    coinbase
+        .output
+        .iter()
+        .rposition(|output| {
+            let script = output.script_pubkey.as_bytes();
+            script.len() >= 38 && script[0] == OP_RETURN.to_u8() && script[1] == 0x24 && script[2..6] == WITNESS_COMMITMENT_HEADER
+        })
+        .ok_or(Error::InvalidTemplate("coinbase has no witness commitment".into()))
+}
+
+/// Splits a witness commitment script into the solution its signet push
+/// carries and the script with that push cut back to the bare header, the
+/// way bitcoind does. A push of just the header carries no solution.
+fn split_solution(script: &ScriptBuf) -> Result<(ScriptBuf, Option<Vec<u8>>), Error> {
+    let mut builder = Builder::new();
+    let mut solution = None;
+    for instruction in script.instructions() {
+        match instruction.map_err(|e| Error::Parse(e.to_string()))? {
+            Instruction::PushBytes(push)
+                if solution.is_none() && push.len() > SIGNET_HEADER.len() && push.as_bytes().starts_with(&SIGNET_HEADER) =>
+            {
+                solution = Some(push.as_bytes()[SIGNET_HEADER.len()..].to_vec());
+                builder = builder.push_slice(SIGNET_HEADER);
+            }
+            Instruction::PushBytes(push) => builder = builder.push_slice(push),
+            Instruction::Op(op) => builder = builder.push_opcode(op),
+        }
+    }
+    Ok((builder.into_script(), solution))
+}
+
+/// A witness commitment script without any signet push.
+fn strip_signet_push(script: &ScriptBuf) -> Result<ScriptBuf, Error> {
+    let mut builder = Builder::new();
+    for instruction in script.instructions() {
+        match instruction.map_err(|e| Error::Parse(e.to_string()))? {
+            Instruction::PushBytes(push) if push.as_bytes().starts_with(&SIGNET_HEADER) => {}
+            Instruction::PushBytes(push) => builder = builder.push_slice(push),
+            Instruction::Op(op) => builder = builder.push_opcode(op),
+        }
+    }
+    Ok(builder.into_script())
+}
+
+fn update_merkle_root(block: &mut Block) -> Result<(), Error> {
+    block.header.merkle_root = block.compute_merkle_root().ok_or(Error::InvalidTemplate("block has no transactions".into()))?;
+    Ok(())
+}
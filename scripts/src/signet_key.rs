@@ -0,0 +1,19 @@
+use std::path::Path;
+
+use scripts::signet::{SignetKey, SIGNET_KEY};
+
+// Prints the challenge of our custom signet, generating its key on first use.
+// The output is meant for bitcoind's `-signetchallenge`, so it is only the hex.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let path = Path::new(SIGNET_KEY);
+    let key = if path.exists() {
+        SignetKey::load(path)?
+    } else {
+        let key = SignetKey::generate();
+        key.save(path)?;
+        eprintln!("Generated a signet challenge key in {SIGNET_KEY}");
+        key
+    };
+    println!("{}", key.challenge().to_hex_string());
+    Ok(())
+}
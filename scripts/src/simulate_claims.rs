@@ -0,0 +1,57 @@
+use std::env;
+use std::path::PathBuf;
+
+use bitcoin::Txid;
+use scripts::{
+    exit_cost::{leaf_exits, plan_claims},
+    tree::{load_unroll_data, unroll_path},
+};
+
+const DEFAULT_FEE_RATE: &str = "10";
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let source = env::args().nth(1).expect("coinbase txid or unroll file required");
+    // Comma separated leaf paths as printed by leaf_exit_cost, or "all"
+    let claims = env::args().nth(2).unwrap_or("all".to_string());
+    let fee_rate: u64 = env::args().nth(3).unwrap_or(DEFAULT_FEE_RATE.to_string()).parse()?;
+
+    let path = match source.parse::<Txid>() {
+        Ok(txid) => unroll_path(&txid),
+        Err(_) => PathBuf::from(source),
+    };
+    let root = load_unroll_data(&path)?;
+
+    let claims: Vec<Vec<u32>> = if claims == "all" {
+        leaf_exits(&root).into_iter().map(|exit| exit.path).collect()
+    } else {
+        claims
+            .split(',')
+            .map(|leaf| leaf.split('.').map(str::parse).collect::<Result<_, _>>())
+            .collect::<Result<_, _>>()?
+    };
+    let plan = plan_claims(&root, &claims)?;
+
+    println!("Transactions to broadcast:");
+    for (path, vsize, claimers) in &plan.transactions {
+        let path: Vec<String> = path.iter().map(u32::to_string).collect();
+        let path = if path.is_empty() { "root".to_string() } else { path.join(".") };
+        println!("  {path:<10} {vsize:>5} vB  shared by {claimers}");
+    }
+    println!("  total      {:>5} vB  {} sat at {fee_rate} sat/vB", plan.vbytes(), plan.vbytes() as u64 * fee_rate);
+
+    println!();
+    println!("{:<12} {:>14} {:>12} {:>12} {:>10}", "leaf", "value", "alone", "shared", "saved");
+    for share in &plan.shares {
+        let path: Vec<String> = share.exit.path.iter().map(u32::to_string).collect();
+        println!(
+            "{:<12} {:>14} {:>12} {:>12} {:>10}",
+            path.join("."),
+            share.exit.value,
+            share.exit.cost(fee_rate),
+            share.cost(fee_rate),
+            share.savings(fee_rate),
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use bitcoin::{
+    ScriptBuf, Transaction, TxOut, Witness, XOnlyPublicKey,
+    hashes::Hash,
+    key::{Keypair, Secp256k1},
+    secp256k1::{schnorr::Signature, All, Message},
+    sighash::{Prevouts, SighashCache, TapSighashType},
+    taproot::{LeafVersion, TapLeafHash, TaprootBuilder, TaprootSpendInfo},
+};
+
+use crate::error::Error;
+
+/// Single-leaf taproot tree around `leaf`.
+pub fn leaf_spend_info(
+    secp: &Secp256k1<All>,
+    internal_key: XOnlyPublicKey,
+    leaf: &ScriptBuf,
+) -> Result<TaprootSpendInfo, Error> {
+    TaprootBuilder::new()
+        .add_leaf(0, leaf.clone())?
+        .finalize(secp, internal_key)
+        .map_err(|e| Error::Taproot(format!("taproot finalize failed: {e:?}")))
+}
+
+/// BIP-341 script-path signature for `input_index` of `tx` spending through `leaf`.
+pub fn sign_script_spend(
+    secp: &Secp256k1<All>,
+    keypair: &Keypair,
+    tx: &Transaction,
+    input_index: usize,
+    prevouts: &[TxOut],
+    leaf: &ScriptBuf,
+) -> Result<Signature, Error> {
+    let sighash = SighashCache::new(tx).taproot_script_spend_signature_hash(
+        input_index,
+        &Prevouts::All(prevouts),
+        TapLeafHash::from_script(leaf, LeafVersion::TapScript),
+        TapSighashType::Default,
+    )?;
+    Ok(secp.sign_schnorr(&Message::from_digest(sighash.to_byte_array()), keypair))
+}
+
+/// Script-path witness: `stack` items (bottom first), then the leaf and its control block.
+pub fn tapscript_witness(
+    stack: &[&[u8]],
+    leaf: &ScriptBuf,
+    spend_info: &TaprootSpendInfo,
+) -> Result<Witness, Error> {
+    let ctrl_block = spend_info
+        .control_block(&(leaf.clone(), LeafVersion::TapScript))
+        .ok_or_else(|| Error::Taproot("missing control block".into()))?;
+
+    let mut witness = Witness::new();
+    for item in stack {
+        witness.push(item);
+    }
+    witness.push(leaf.as_bytes());
+    witness.push(ctrl_block.serialize());
+    Ok(witness)
+}
+
+/// BIP-341 key-path sighash for `input_index` of `tx`.
+pub fn key_spend_sighash(
+    tx: &Transaction,
+    input_index: usize,
+    prevouts: &[TxOut],
+) -> Result<[u8; 32], Error> {
+    let sighash = SighashCache::new(tx).taproot_key_spend_signature_hash(
+        input_index,
+        &Prevouts::All(prevouts),
+        TapSighashType::Default,
+    )?;
+    Ok(sighash.to_byte_array())
+}
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bitcoin::{
+    OutPoint, Transaction, TxOut, Txid,
+    consensus::encode::{deserialize, serialize_hex},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::ctv::{calc_ctv_hash, get_virtual_bytes};
+use crate::error::Error;
+
+/// Directory the mining scripts write unroll data into, keyed by coinbase txid.
+pub const UNROLL_DIR: &str = "./data/trees";
+
+/// One transaction in a CTV payout tree. `tx` spends a CTV-locked output worth
+/// `input_value`; `children` are the nodes spending its outputs, keyed by vout.
+/// Outputs without a child are payouts (or anchors).
+#[derive(Clone, Debug)]
+pub struct TreeNode {
+    pub tx: Transaction,
+    pub input_value: u64,
+    pub children: Vec<(u32, TreeNode)>,
+}
+
+impl TreeNode {
+    pub fn new(tx: Transaction, input_value: u64) -> Self {
+        TreeNode { tx, input_value, children: vec![] }
+    }
+
+    pub fn with_child(mut self, vout: u32, child: TreeNode) -> Self {
+        self.children.push((vout, child));
+        self
+    }
+
+    pub fn txid(&self) -> Txid {
+        self.tx.txid()
+    }
+
+    pub fn output_value(&self) -> u64 {
+        self.tx.output.iter().map(|o| o.value.to_sat()).sum()
+    }
+
+    pub fn fee(&self) -> u64 {
+        self.input_value.saturating_sub(self.output_value())
+    }
+
+    pub fn vsize(&self) -> usize {
+        get_virtual_bytes(&self.tx)
+    }
+
+    /// Template hash the parent output commits to for this node.
+    pub fn ctv_hash(&self) -> [u8; 32] {
+        calc_ctv_hash(&self.tx.output)
+    }
+
+    pub fn child(&self, vout: u32) -> Option<&TreeNode> {
+        self.children.iter().find(|(v, _)| *v == vout).map(|(_, c)| c)
+    }
+
+    /// Outputs of this node that are not unrolled any further.
+    pub fn leaf_outputs(&self) -> impl Iterator<Item = (u32, &TxOut)> {
+        self.tx
+            .output
+            .iter()
+            .enumerate()
+            .map(|(vout, o)| (vout as u32, o))
+            .filter(|(vout, _)| self.child(*vout).is_none())
+    }
+
+    /// Sets this node's prevout and recomputes the prevouts of every descendant.
+    /// CTV does not commit to prevouts, so this can happen after the tree is built.
+    pub fn bind(&mut self, prevout: OutPoint) {
+        self.tx.input[0].previous_output = prevout;
+        let txid = self.tx.txid();
+        for (vout, child) in &mut self.children {
+            child.bind(OutPoint { txid, vout: *vout });
+        }
+    }
+
+    /// The node in this subtree with `txid`.
+    pub fn find(&self, txid: &Txid) -> Option<&TreeNode> {
+        if self.txid() == *txid {
+            return Some(self);
+        }
+        self.children.iter().find_map(|(_, child)| child.find(txid))
+    }
+
+    /// All transactions in the tree, parents before children.
+    pub fn transactions(&self) -> Vec<&Transaction> {
+        let mut txs = vec![&self.tx];
+        for (_, child) in &self.children {
+            txs.extend(child.transactions());
+        }
+        txs
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct UnrollFile {
+    root_value: u64,
+    transactions: Vec<String>,
+}
+
+pub fn unroll_path(coinbase_txid: &Txid) -> PathBuf {
+    Path::new(UNROLL_DIR).join(format!("{coinbase_txid}.json"))
+}
+
+/// Writes the bound tree as a list of raw transactions so anyone holding the
+/// file can unroll it later.
+pub fn save_unroll_data(path: &Path, root: &TreeNode) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file = UnrollFile {
+        root_value: root.input_value,
+        transactions: root.transactions().into_iter().map(serialize_hex).collect(),
+    };
+    fs::write(path, serde_json::to_string_pretty(&file)?)?;
+    Ok(())
+}
+
+/// Rebuilds a tree from unroll data by matching each transaction's prevout
+/// to the transaction that created it.
+pub fn load_unroll_data(path: &Path) -> Result<TreeNode, Error> {
+    let file: UnrollFile = serde_json::from_str(&fs::read_to_string(path)?)?;
+
+    let mut spenders: HashMap<OutPoint, Transaction> = HashMap::new();
+    for tx_hex in &file.transactions {
+        let tx: Transaction = deserialize(&hex::decode(tx_hex)?)?;
+        spenders.insert(tx.input[0].previous_output, tx);
+    }
+
+    let txids: Vec<Txid> = spenders.values().map(|tx| tx.txid()).collect();
+    let root_prevout = *spenders
+        .keys()
+        .find(|outpoint| !txids.contains(&outpoint.txid))
+        .ok_or_else(|| Error::InvalidTemplate("unroll data has no root transaction".into()))?;
+    let root_tx = spenders.remove(&root_prevout).ok_or_else(|| Error::InvalidTemplate("unroll data has no root transaction".into()))?;
+    Ok(attach_children(TreeNode::new(root_tx, file.root_value), &mut spenders))
+}
+
+/// Every tree saved in `dir`, in file name order. A missing directory holds none.
+pub fn load_all_unroll_data(dir: &Path) -> Result<Vec<TreeNode>, Error> {
+    let mut paths = match fs::read_dir(dir) {
+        Ok(entries) => entries.map(|entry| Ok(entry?.path())).collect::<Result<Vec<_>, Error>>()?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    paths.retain(|path| path.extension().is_some_and(|ext| ext == "json"));
+    paths.sort();
+    paths.iter().map(|path| load_unroll_data(path)).collect()
+}
+
+fn attach_children(mut node: TreeNode, spenders: &mut HashMap<OutPoint, Transaction>) -> TreeNode {
+    let txid = node.txid();
+    for (vout, output) in node.tx.output.clone().iter().enumerate() {
+        let outpoint = OutPoint { txid, vout: vout as u32 };
+        if let Some(tx) = spenders.remove(&outpoint) {
+            let child = attach_children(TreeNode::new(tx, output.value.to_sat()), spenders);
+            node.children.push((vout as u32, child));
+        }
+    }
+    node
+}
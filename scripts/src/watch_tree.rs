@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bitcoin::{OutPoint, Txid};
+use scripts::{
+    chain::ChainSource,
+    ctv::is_anchor,
+    electrum::{script_hash, take_electrum_arg, ElectrumClient},
+    explorer::{explore, ExploredNode, TxStatus},
+    tree::{load_unroll_data, unroll_path},
+};
+
+const DEFAULT_ELECTRUM: &str = "127.0.0.1:60401";
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args: Vec<String> = env::args().collect();
+    let electrum = match take_electrum_arg(&mut args)? {
+        Some(electrum) => electrum,
+        None => ElectrumClient::new(DEFAULT_ELECTRUM)?,
+    };
+
+    let start_str = args.get(1).cloned().expect("txid or txid:vout required");
+    let start: OutPoint = match start_str.split_once(':') {
+        Some(_) => start_str.parse()?,
+        None => OutPoint { txid: start_str.parse::<Txid>()?, vout: 0 },
+    };
+    let unroll_file = args.get(2).map(PathBuf::from).unwrap_or_else(|| unroll_path(&start.txid));
+    let unroll = load_unroll_data(&unroll_file)?;
+
+    // The coinbase output and every output the tree creates, so we hear about
+    // each unroll transaction when it pays its outputs and when they are spent.
+    // Anchors all share one script, so subscribing to them would report every
+    // anchor on the chain.
+    let coinbase = electrum.fetch_transaction(&start.txid)?.ok_or("coinbase not found")?;
+    let start_output = coinbase.output.get(start.vout as usize).ok_or("vout out of range")?;
+    let mut outputs = vec![(start, &start_output.script_pubkey)];
+    for tx in unroll.transactions() {
+        for (vout, output) in tx.output.iter().enumerate() {
+            if !is_anchor(&output.script_pubkey) && !output.script_pubkey.is_op_return() {
+                outputs.push((OutPoint { txid: tx.txid(), vout: vout as u32 }, &output.script_pubkey));
+            }
+        }
+    }
+    let mut watched = HashMap::new();
+    for (outpoint, script) in outputs {
+        electrum.subscribe(script)?;
+        watched.insert(script_hash(script), outpoint);
+    }
+    println!("Watching {} scripts of the tree under {start} for unroll transactions", watched.len());
+
+    let mut statuses = HashMap::new();
+    loop {
+        let root = explore(&electrum, start, Some(&unroll))?;
+        let mut current = HashMap::new();
+        if let Some(node) = &root.child {
+            collect_statuses(node, &mut current);
+        }
+        for (txid, status) in &current {
+            if statuses.get(txid) != Some(status) && *status != TxStatus::Unbroadcast {
+                println!("tx {txid} {status}");
+            }
+        }
+        statuses = current;
+
+        let unclaimed = root.child.as_ref().map(ExploredNode::unclaimed_leaves).unwrap_or_default();
+        let unclaimed_value: u64 = unclaimed.iter().map(|o| o.value).sum();
+        println!("Remaining leaves to claim: {} ({} sat)", unclaimed.len(), unclaimed_value);
+        if unclaimed.is_empty() {
+            return Ok(());
+        }
+
+        // Wait for a change, then let the rest of that block's or broadcast's
+        // notifications arrive before walking the tree again
+        let Some(hash) = electrum.wait_for_change(None)? else {
+            continue;
+        };
+        if let Some(outpoint) = watched.get(&hash) {
+            println!("Activity on {outpoint}");
+        }
+        while electrum.wait_for_change(Some(Duration::from_secs(1)))?.is_some() {}
+    }
+}
+
+fn collect_statuses(node: &ExploredNode, statuses: &mut HashMap<Txid, TxStatus>) {
+    statuses.insert(node.tx.txid(), node.status);
+    for child in node.outputs.iter().filter_map(|output| output.child.as_ref()) {
+        collect_statuses(child, statuses);
+    }
+}
@@ -0,0 +1,506 @@
+//! Keeps CTV payout trees unrolling until every node has confirmed. For each
+//! coinbase with unroll data it waits out coinbase maturity, broadcasts every
+//! node whose parent has confirmed and, when a node sits in the mempool for
+//! `stuck_after` blocks, bumps it through its anchor with a TRUC child paid
+//! from the watchtower's own fee key. A child that sits as long again is
+//! replaced at double the feerate.
+//!
+//! Only one node per branch is in flight at a time: a TRUC transaction can
+//! have just one unconfirmed child, and the anchor bump has to be it. For the
+//! same reason the fee output a bump spends must already be confirmed.
+//!
+//! What was broadcast and bumped, and which output pays for the next bump, is
+//! kept in `WATCHTOWER_STATE` so a restarted daemon carries on where it was.
+//! The state is saved after each pass, so on start `reconcile` looks up bumps
+//! broadcast after the last save by following the fee output's spenders.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use bitcoin::{
+    absolute::LockTime,
+    key::{Keypair, Secp256k1, TapTweak},
+    secp256k1::{Message, SecretKey},
+    transaction::Version,
+    Address, Amount, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::chain::{ChainSource, TxStatus};
+use crate::ctv::{get_virtual_bytes, is_anchor};
+use crate::error::Error;
+use crate::flow::COINBASE_MATURITY;
+use crate::taproot::key_spend_sighash;
+use crate::tree::TreeNode;
+
+/// Where the watchtower keeps what it has done between runs.
+pub const WATCHTOWER_STATE: &str = "./data/watchtower.json";
+/// Where the key paying for anchor bumps lives.
+pub const WATCHTOWER_KEY: &str = "./data/watchtower_key.json";
+
+/// The key-path P2TR key that funds anchor bumps and takes their change.
+pub struct FeeKey {
+    keypair: Keypair,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyFile {
+    secret_key: String,
+}
+
+impl FeeKey {
+    pub fn generate() -> Self {
+        FeeKey { keypair: Keypair::new(&Secp256k1::new(), &mut rand::thread_rng()) }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let file: KeyFile = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let secret = SecretKey::from_slice(&hex::decode(file.secret_key)?)?;
+        Ok(FeeKey { keypair: Keypair::from_secret_key(&Secp256k1::new(), &secret) })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = KeyFile { secret_key: hex::encode(self.keypair.secret_bytes()) };
+        fs::write(path, serde_json::to_string_pretty(&file)?)?;
+        Ok(())
+    }
+
+    pub fn address(&self, network: Network) -> Address {
+        Address::p2tr(&Secp256k1::new(), self.keypair.x_only_public_key().0, None, network)
+    }
+
+    pub fn script_pubkey(&self) -> ScriptBuf {
+        ScriptBuf::new_p2tr(&Secp256k1::new(), self.keypair.x_only_public_key().0, None)
+    }
+
+    /// Puts a key-path signature on input `index`.
+    fn sign(&self, tx: &mut Transaction, index: usize, prevouts: &[TxOut]) -> Result<(), Error> {
+        let secp = Secp256k1::new();
+        let sighash = key_spend_sighash(tx, index, prevouts)?;
+        let tweaked = self.keypair.tap_tweak(&secp, None).to_inner();
+        let sig = secp.sign_schnorr(&Message::from_digest(sighash), &tweaked);
+        tx.input[index].witness = Witness::from_slice(&[sig.as_ref()]);
+        Ok(())
+    }
+}
+
+/// An output paying the fee key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeUtxo {
+    pub outpoint: OutPoint,
+    pub value: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct WatchtowerState {
+    /// What the next bump spends: the funding output, then each bump's change.
+    pub fee_utxo: Option<FeeUtxo>,
+    /// Trees by the coinbase they spend.
+    pub trees: BTreeMap<Txid, TreeState>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TreeState {
+    /// Every node has confirmed, so the tree needs no more watching.
+    pub unrolled: bool,
+    pub nodes: BTreeMap<Txid, NodeState>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct NodeState {
+    /// Tip height when the node was first seen in the mempool.
+    pub broadcast_at: Option<u64>,
+    pub confirmed_at: Option<u64>,
+    pub bump: Option<Bump>,
+}
+
+/// The child currently paying for a node through its anchor.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Bump {
+    pub child: Txid,
+    /// Spent again by a replacement, so replacements conflict with the child.
+    pub fee_input: FeeUtxo,
+    /// Package feerate in sat/vB.
+    pub feerate: u64,
+    /// Tip height when the child was broadcast.
+    pub at: u64,
+}
+
+impl WatchtowerState {
+    /// The saved state, or a fresh one on the first run.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match fs::read_to_string(path) {
+            Ok(json) => Ok(serde_json::from_str(&json)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(WatchtowerState::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Writes through a temporary file so a kill mid-write leaves the old state.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Pays future bumps from the output of `tx` that pays the fee key.
+    pub fn fund(&mut self, tx: &Transaction, key: &FeeKey) -> Result<FeeUtxo, Error> {
+        let script = key.script_pubkey();
+        let vout = tx
+            .output
+            .iter()
+            .position(|output| output.script_pubkey == script)
+            .ok_or_else(|| Error::Node(format!("{} does not pay the watchtower's fee key", tx.txid())))?;
+        let utxo = FeeUtxo { outpoint: OutPoint { txid: tx.txid(), vout: vout as u32 }, value: tx.output[vout].value.to_sat() };
+        self.fee_utxo = Some(utxo);
+        Ok(utxo)
+    }
+
+    /// Takes up bumps that were broadcast but not saved, which a daemon
+    /// stopped mid-pass leaves behind: replacements of a recorded bump, then
+    /// the bumps spending `fee_utxo`. A fee output spent by anything but a
+    /// bump is dropped, so the watchtower asks to be funded again.
+    pub fn reconcile<S: ChainSource + ?Sized>(
+        &mut self,
+        source: &S,
+        trees: &[TreeNode],
+        key: &FeeKey,
+    ) -> Result<Vec<Event>, Error> {
+        let tip = source.tip_height()?;
+        let mut events = vec![];
+
+        for tree in self.trees.values_mut() {
+            for (txid, entry) in &mut tree.nodes {
+                let Some(bump) = &mut entry.bump else {
+                    continue;
+                };
+                let Some(spent_by) = spender(source, bump.fee_input.outpoint)? else {
+                    continue;
+                };
+                if spent_by == bump.child {
+                    continue;
+                }
+                let Some((child, node, _)) = as_bump(source, &spent_by, trees, key)? else {
+                    continue;
+                };
+                if node.txid() != *txid {
+                    continue;
+                }
+                if self.fee_utxo.is_some_and(|utxo| utxo.outpoint.txid == bump.child) {
+                    self.fee_utxo = Some(change_of(&child));
+                }
+                bump.child = spent_by;
+                bump.feerate = package_feerate(node, &child, bump.fee_input);
+                bump.at = tip;
+                events.push(Event::Recovered { txid: *txid, child: spent_by });
+            }
+        }
+
+        while let Some(utxo) = self.fee_utxo {
+            let Some(spent_by) = spender(source, utxo.outpoint)? else {
+                break;
+            };
+            let Some((child, node, coinbase)) = as_bump(source, &spent_by, trees, key)? else {
+                self.fee_utxo = None;
+                break;
+            };
+            let entry = self.trees.entry(coinbase).or_default().nodes.entry(node.txid()).or_default();
+            entry.broadcast_at.get_or_insert(tip);
+            let feerate = package_feerate(node, &child, utxo);
+            entry.bump = Some(Bump { child: spent_by, fee_input: utxo, feerate, at: tip });
+            self.fee_utxo = Some(change_of(&child));
+            events.push(Event::Recovered { txid: node.txid(), child: spent_by });
+        }
+        Ok(events)
+    }
+}
+
+/// The transaction spending `outpoint`, if any.
+fn spender<S: ChainSource + ?Sized>(source: &S, outpoint: OutPoint) -> Result<Option<Txid>, Error> {
+    Ok(source.outspends(&outpoint.txid)?.get(outpoint.vout as usize).copied().flatten())
+}
+
+/// `txid` if it is a bump of one of `trees`: its first input spends a node's
+/// anchor and its change goes back to the fee key. Comes with the node and
+/// the coinbase of its tree.
+fn as_bump<'a, S: ChainSource + ?Sized>(
+    source: &S,
+    txid: &Txid,
+    trees: &'a [TreeNode],
+    key: &FeeKey,
+) -> Result<Option<(Transaction, &'a TreeNode, Txid)>, Error> {
+    let Some(child) = source.fetch_transaction(txid)? else {
+        return Ok(None);
+    };
+    if child.output.first().map(|output| &output.script_pubkey) != Some(&key.script_pubkey()) {
+        return Ok(None);
+    }
+    let parent = child.input[0].previous_output;
+    let found = trees.iter().find_map(|root| {
+        let node = root.find(&parent.txid)?;
+        let anchor = node.tx.output.get(parent.vout as usize)?;
+        is_anchor(&anchor.script_pubkey).then(|| (node, root.tx.input[0].previous_output.txid))
+    });
+    Ok(found.map(|(node, coinbase)| (child, node, coinbase)))
+}
+
+fn change_of(child: &Transaction) -> FeeUtxo {
+    FeeUtxo { outpoint: OutPoint { txid: child.txid(), vout: 0 }, value: child.output[0].value.to_sat() }
+}
+
+/// The feerate `child` pays for itself and `node` together, in sat/vB.
+fn package_feerate(node: &TreeNode, child: &Transaction, fee_input: FeeUtxo) -> u64 {
+    let anchor = node.tx.output[child.input[0].previous_output.vout as usize].value.to_sat();
+    let child_fee = (fee_input.value + anchor).saturating_sub(child.output[0].value.to_sat());
+    (node.fee() + child_fee) / (node.vsize() + get_virtual_bytes(child)) as u64
+}
+
+/// When a node counts as stuck and how hard to bump it.
+#[derive(Clone, Copy, Debug)]
+pub struct Policy {
+    /// Blocks a node (or its bump) may sit in the mempool before bumping.
+    pub stuck_after: u64,
+    /// Package feerate in sat/vB of the first bump.
+    pub bump_feerate: u64,
+    /// Replacements stop doubling the feerate here.
+    pub max_feerate: u64,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy { stuck_after: 3, bump_feerate: 10, max_feerate: 1000 }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    Maturing { coinbase: Txid, blocks_left: u64 },
+    CoinbaseMissing { coinbase: Txid },
+    Broadcast { txid: Txid },
+    Rejected { txid: Txid, reason: String },
+    Confirmed { txid: Txid, height: u64 },
+    Bumped { txid: Txid, child: Txid, feerate: u64 },
+    CannotBump { txid: Txid, reason: String },
+    Recovered { txid: Txid, child: Txid },
+    Unrolled { coinbase: Txid },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Maturing { coinbase, blocks_left } => write!(f, "coinbase {coinbase} matures in {blocks_left} blocks"),
+            Event::CoinbaseMissing { coinbase } => write!(f, "coinbase {coinbase} is not on chain"),
+            Event::Broadcast { txid } => write!(f, "broadcast {txid}"),
+            Event::Rejected { txid, reason } => write!(f, "{txid} rejected: {reason}"),
+            Event::Confirmed { txid, height } => write!(f, "{txid} confirmed at height {height}"),
+            Event::Bumped { txid, child, feerate } => write!(f, "bumped {txid} to {feerate} sat/vB with {child}"),
+            Event::CannotBump { txid, reason } => write!(f, "cannot bump {txid}: {reason}"),
+            Event::Recovered { txid, child } => write!(f, "found bump {child} of {txid} from before the last save"),
+            Event::Unrolled { coinbase } => write!(f, "tree under {coinbase} is fully unrolled"),
+        }
+    }
+}
+
+/// One pass over `trees`: records confirmations, broadcasts what can be and
+/// bumps what is stuck, updating `state`.
+pub fn tick<S: ChainSource + ?Sized>(
+    source: &S,
+    state: &mut WatchtowerState,
+    trees: &[TreeNode],
+    key: &FeeKey,
+    policy: &Policy,
+) -> Result<Vec<Event>, Error> {
+    let mut pass = Pass { source, tip: source.tip_height()?, key, policy, fee_utxo: &mut state.fee_utxo, events: vec![] };
+
+    for root in trees {
+        let coinbase = root.tx.input[0].previous_output.txid;
+        let tree = state.trees.entry(coinbase).or_default();
+        if tree.unrolled {
+            continue;
+        }
+        match pass.source.transaction_status(&coinbase)? {
+            TxStatus::Confirmed { height } => {
+                // The first block that may spend a coinbase is 100 after it
+                let spendable_at = height + COINBASE_MATURITY;
+                if pass.tip + 1 < spendable_at {
+                    let blocks_left = spendable_at - pass.tip - 1;
+                    pass.events.push(Event::Maturing { coinbase, blocks_left });
+                    continue;
+                }
+            }
+            _ => {
+                pass.events.push(Event::CoinbaseMissing { coinbase });
+                continue;
+            }
+        }
+        if pass.visit(root, tree)? {
+            tree.unrolled = true;
+            pass.events.push(Event::Unrolled { coinbase });
+        }
+    }
+    Ok(pass.events)
+}
+
+struct Pass<'a, S: ?Sized> {
+    source: &'a S,
+    tip: u64,
+    key: &'a FeeKey,
+    policy: &'a Policy,
+    fee_utxo: &'a mut Option<FeeUtxo>,
+    events: Vec<Event>,
+}
+
+impl<S: ChainSource + ?Sized> Pass<'_, S> {
+    /// Moves `node`'s subtree along, returning whether all of it has confirmed.
+    fn visit(&mut self, node: &TreeNode, tree: &mut TreeState) -> Result<bool, Error> {
+        let txid = node.txid();
+        let entry = tree.nodes.entry(txid).or_default();
+        if entry.confirmed_at.is_none() {
+            match self.source.transaction_status(&txid)? {
+                TxStatus::Confirmed { height } => {
+                    entry.confirmed_at = Some(height);
+                    self.events.push(Event::Confirmed { txid, height });
+                }
+                TxStatus::Mempool => {
+                    // Someone else may have broadcast it
+                    entry.broadcast_at.get_or_insert(self.tip);
+                    self.bump_if_stuck(node, entry)?;
+                    return Ok(false);
+                }
+                TxStatus::Unbroadcast => {
+                    // A node dropped from the mempool took its bump with it,
+                    // leaving the bump's fee input unspent
+                    if let Some(bump) = entry.bump.take() {
+                        if self.fee_utxo.is_some_and(|utxo| utxo.outpoint.txid == bump.child) {
+                            *self.fee_utxo = Some(bump.fee_input);
+                        }
+                    }
+                    match self.source.broadcast(&node.tx) {
+                        Ok(_) => {
+                            entry.broadcast_at = Some(self.tip);
+                            self.events.push(Event::Broadcast { txid });
+                        }
+                        Err(e) => self.events.push(Event::Rejected { txid, reason: e.to_string() }),
+                    }
+                    return Ok(false);
+                }
+            }
+        }
+
+        let mut confirmed = true;
+        for (_, child) in &node.children {
+            confirmed &= self.visit(child, tree)?;
+        }
+        Ok(confirmed)
+    }
+
+    fn bump_if_stuck(&mut self, node: &TreeNode, entry: &mut NodeState) -> Result<(), Error> {
+        let txid = node.txid();
+        let waiting_since = match &entry.bump {
+            Some(bump) => bump.at,
+            None => entry.broadcast_at.unwrap_or(self.tip),
+        };
+        if self.tip < waiting_since + self.policy.stuck_after {
+            return Ok(());
+        }
+
+        let (fee_input, feerate) = match &entry.bump {
+            Some(bump) if bump.feerate >= self.policy.max_feerate => {
+                let reason = format!("already at the {} sat/vB maximum", self.policy.max_feerate);
+                self.events.push(Event::CannotBump { txid, reason });
+                return Ok(());
+            }
+            Some(bump) => (bump.fee_input, (bump.feerate * 2).min(self.policy.max_feerate)),
+            None => {
+                let Some(utxo) = *self.fee_utxo else {
+                    self.events.push(Event::CannotBump { txid, reason: "no fee output, fund the watchtower".into() });
+                    return Ok(());
+                };
+                if !matches!(self.source.transaction_status(&utxo.outpoint.txid)?, TxStatus::Confirmed { .. }) {
+                    let reason = format!("fee output {} is unconfirmed", utxo.outpoint);
+                    self.events.push(Event::CannotBump { txid, reason });
+                    return Ok(());
+                }
+                (utxo, self.policy.bump_feerate)
+            }
+        };
+
+        let child = match cpfp_child(&node.tx, node.fee(), fee_input, feerate, self.key) {
+            Ok(child) => child,
+            Err(e) => {
+                self.events.push(Event::CannotBump { txid, reason: e.to_string() });
+                return Ok(());
+            }
+        };
+        match self.source.broadcast(&child) {
+            Ok(child_txid) => {
+                *self.fee_utxo = Some(FeeUtxo {
+                    outpoint: OutPoint { txid: child_txid, vout: 0 },
+                    value: child.output[0].value.to_sat(),
+                });
+                entry.bump = Some(Bump { child: child_txid, fee_input, feerate, at: self.tip });
+                self.events.push(Event::Bumped { txid, child: child_txid, feerate });
+            }
+            Err(e) => self.events.push(Event::CannotBump { txid, reason: e.to_string() }),
+        }
+        Ok(())
+    }
+}
+
+/// A TRUC child spending `parent`'s anchor and `fee_input`, paying enough that
+/// the two reach `feerate` together, with the change back to the fee key.
+pub fn cpfp_child(
+    parent: &Transaction,
+    parent_fee: u64,
+    fee_input: FeeUtxo,
+    feerate: u64,
+    key: &FeeKey,
+) -> Result<Transaction, Error> {
+    let anchor_vout = parent
+        .output
+        .iter()
+        .position(|output| is_anchor(&output.script_pubkey))
+        .ok_or_else(|| Error::InvalidTemplate(format!("{} has no anchor output", parent.txid())))?;
+    let anchor = parent.output[anchor_vout].clone();
+    let fee_prevout = TxOut { value: Amount::from_sat(fee_input.value), script_pubkey: key.script_pubkey() };
+    let prevouts = [anchor.clone(), fee_prevout];
+
+    let input = |previous_output| TxIn {
+        previous_output,
+        script_sig: ScriptBuf::new(),
+        sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+        witness: Witness::new(),
+    };
+    let mut child = Transaction {
+        version: Version(3),
+        lock_time: LockTime::ZERO,
+        input: vec![input(OutPoint { txid: parent.txid(), vout: anchor_vout as u32 }), input(fee_input.outpoint)],
+        output: vec![TxOut { value: Amount::ZERO, script_pubkey: key.script_pubkey() }],
+    };
+
+    // Sign once to size the witness, then again over the real change
+    key.sign(&mut child, 1, &prevouts)?;
+    let child_vsize = get_virtual_bytes(&child) as u64;
+    let package_vsize = get_virtual_bytes(parent) as u64 + child_vsize;
+    let fee = (feerate * package_vsize).saturating_sub(parent_fee).max(child_vsize);
+
+    let available = fee_input.value + anchor.value.to_sat();
+    let dust = key.script_pubkey().dust_value().to_sat();
+    let change = available
+        .checked_sub(fee)
+        .filter(|change| *change >= dust)
+        .ok_or_else(|| Error::InsufficientValue { needed: fee.saturating_add(dust), available })?;
+    child.output[0].value = Amount::from_sat(change);
+    key.sign(&mut child, 1, &prevouts)?;
+    Ok(child)
+}
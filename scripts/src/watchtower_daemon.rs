@@ -0,0 +1,89 @@
+use std::env;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use scripts::{
+    chain::{take_source_arg, ChainSource},
+    rpc::{connect, Chain},
+    tree::{load_all_unroll_data, UNROLL_DIR},
+    watchtower::{tick, FeeKey, Policy, WatchtowerState, WATCHTOWER_KEY, WATCHTOWER_STATE},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+// watchtower [--esplora <url> | --electrum <host:port>] [run [feerate] [stuck after] | address | fund <txid>]
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args: Vec<String> = env::args().collect();
+    let source = take_source_arg(&mut args)?;
+    let key = load_or_generate_key()?;
+    let state_path = Path::new(WATCHTOWER_STATE);
+    let mut state = WatchtowerState::load(state_path)?;
+
+    let command = args.get(1).cloned().unwrap_or("run".to_string());
+    if command == "address" {
+        println!("{}", key.address(Chain::from_env()?.network()));
+        return Ok(());
+    }
+
+    let source: Box<dyn ChainSource> = match source {
+        Some(source) => source,
+        None => Box::new(connect(None)?),
+    };
+
+    match command.as_str() {
+        "fund" => {
+            let txid = args.get(2).ok_or("fund needs the txid paying the fee address")?.parse()?;
+            let tx = source.fetch_transaction(&txid)?.ok_or("transaction not found")?;
+            let utxo = state.fund(&tx, &key)?;
+            state.save(state_path)?;
+            println!("Bumps are paid from {} ({} sat)", utxo.outpoint, utxo.value);
+        }
+        "run" => {
+            let mut policy = Policy::default();
+            if let Some(feerate) = args.get(2) {
+                policy.bump_feerate = feerate.parse()?;
+            }
+            if let Some(blocks) = args.get(3) {
+                policy.stuck_after = blocks.parse()?;
+            }
+            println!("Watching the trees in {UNROLL_DIR}, bumping after {} blocks from {} sat/vB", policy.stuck_after, policy.bump_feerate);
+
+            // A bump broadcast after the last save would otherwise be lost
+            let trees = load_all_unroll_data(Path::new(UNROLL_DIR))?;
+            for event in state.reconcile(source.as_ref(), &trees, &key)? {
+                println!("{event}");
+            }
+            state.save(state_path)?;
+
+            // Only say what changed since the last pass
+            let mut last: Vec<String> = vec![];
+            loop {
+                let trees = load_all_unroll_data(Path::new(UNROLL_DIR))?;
+                let messages = match tick(source.as_ref(), &mut state, &trees, &key, &policy) {
+                    Ok(events) => events.iter().map(ToString::to_string).collect(),
+                    Err(e) => vec![format!("pass failed: {e}")],
+                };
+                state.save(state_path)?;
+                for message in messages.iter().filter(|message| !last.contains(message)) {
+                    println!("{message}");
+                }
+                last = messages;
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+        other => return Err(format!("unknown command {other:?}, expected run, address or fund").into()),
+    }
+    Ok(())
+}
+
+fn load_or_generate_key() -> Result<FeeKey, Box<dyn std::error::Error>> {
+    let path = Path::new(WATCHTOWER_KEY);
+    if path.exists() {
+        return Ok(FeeKey::load(path)?);
+    }
+    let key = FeeKey::generate();
+    key.save(path)?;
+    eprintln!("Generated a fee key for anchor bumps in {WATCHTOWER_KEY}");
+    Ok(key)
+}
@@ -0,0 +1,151 @@
+//! Carry-over balances: small shares accumulating across blocks until they
+//! clear the threshold, and the books reconciling after a save and load.
+
+use bitcoin::{hashes::Hash, Amount, ScriptBuf, TxOut, Txid};
+use scripts::{
+    balances::{tree_totals, BalanceStore},
+    builder::build_ctv_tree,
+    error::Error,
+    shares::{ManifestEntry, PayoutManifest},
+};
+
+const THRESHOLD: u64 = 10_000;
+
+fn manifest(entries: &[(&str, u64)], retained: u64) -> PayoutManifest {
+    PayoutManifest {
+        entries: entries
+            .iter()
+            .map(|(address, value)| ManifestEntry { address: address.to_string(), weight: 1, value: *value })
+            .collect(),
+        retained,
+    }
+}
+
+fn pool_script() -> ScriptBuf {
+    ScriptBuf::from_bytes([[0x51, 0x20].as_slice(), &[0xff; 32]].concat())
+}
+
+fn txid(i: u8) -> Txid {
+    Txid::from_byte_array([i; 32])
+}
+
+/// Plans and commits one block, returning what it paid each miner.
+fn mine(store: &mut BalanceStore, block: &PayoutManifest, i: u8) -> Vec<(String, u64)> {
+    let plan = store.plan(block, THRESHOLD);
+    let paid = plan.payouts.entries.iter().map(|e| (e.address.clone(), e.value)).collect();
+    store.commit(plan, txid(i), &pool_script());
+    store.reconcile().unwrap();
+    paid
+}
+
+#[test]
+fn small_shares_carry_over_until_they_clear_the_threshold() {
+    let mut store = BalanceStore::default();
+    let block = manifest(&[("big", 100_000), ("small", 4_000)], 0);
+
+    assert_eq!(mine(&mut store, &block, 1), vec![("big".to_string(), 100_000)]);
+    assert_eq!(store.balances.get("small"), Some(&4_000));
+    assert_eq!(mine(&mut store, &block, 2), vec![("big".to_string(), 100_000)]);
+    assert_eq!(store.balances.get("small"), Some(&8_000));
+
+    // 8000 carried plus 4000 now clears the threshold; the balance is paid
+    // out of this block's new carries and what the pool retains
+    let block = manifest(&[("big", 100_000), ("small", 4_000), ("tiny", 2_000)], 6_000);
+    let paid = mine(&mut store, &block, 3);
+    assert_eq!(paid, vec![("big".to_string(), 100_000), ("small".to_string(), 12_000)]);
+    assert_eq!(store.balances.get("small"), None);
+    assert_eq!(store.balances.get("tiny"), Some(&2_000));
+
+    let record = store.blocks.last().unwrap();
+    assert_eq!((record.carried, record.released, record.retained), (2_000, 8_000, 6_000));
+    assert_eq!(record.pool_value, 0);
+    assert_eq!(store.outstanding(), 2_000);
+}
+
+#[test]
+fn balances_wait_when_the_block_cannot_fund_them() {
+    let mut store = BalanceStore::default();
+    mine(&mut store, &manifest(&[("a", 9_000), ("b", 50_000)], 0), 1);
+    assert_eq!(store.balances.get("a"), Some(&9_000));
+
+    // Nothing carried or retained in this block to release a's balance from,
+    // but a's own share clears the threshold
+    let paid = mine(&mut store, &manifest(&[("a", 20_000)], 0), 2);
+    assert_eq!(paid, vec![("a".to_string(), 20_000)]);
+    assert_eq!(store.balances.get("a"), Some(&9_000));
+}
+
+#[test]
+fn books_reconcile_after_a_save_and_load() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data").join("balances.json");
+    let mut store = BalanceStore::load(&path).unwrap();
+    assert!(store.blocks.is_empty());
+
+    let blocks = [
+        manifest(&[("a", 3_000), ("b", 50_000), ("c", 5_000)], 1_000),
+        manifest(&[("a", 4_000), ("b", 50_000), ("c", 6_000)], 0),
+        manifest(&[("a", 5_000), ("b", 50_000), ("c", 500)], 2_000),
+    ];
+    for (i, block) in blocks.iter().enumerate() {
+        mine(&mut store, block, i as u8);
+        store.save(&path).unwrap();
+        store = BalanceStore::load(&path).unwrap();
+        store.reconcile().unwrap();
+    }
+    assert_eq!(store.blocks.len(), 3);
+
+    let carried: u64 = store.blocks.iter().map(|b| b.carried).sum();
+    let released: u64 = store.blocks.iter().map(|b| b.released).sum();
+    assert_eq!(carried - released, store.outstanding());
+    for (block, record) in blocks.iter().zip(&store.blocks) {
+        assert_eq!(record.reward, block.total() + block.retained);
+        assert_eq!(record.paid + record.pool_value, record.reward);
+    }
+}
+
+#[test]
+fn tampered_books_fail_to_reconcile() {
+    let mut store = BalanceStore::default();
+    mine(&mut store, &manifest(&[("a", 3_000), ("b", 50_000)], 0), 1);
+
+    let mut owed_more = store.clone();
+    *owed_more.balances.get_mut("a").unwrap() += 1;
+    assert!(matches!(owed_more.reconcile(), Err(Error::Payout(_))));
+
+    let mut overpaid = store.clone();
+    overpaid.blocks[0].paid += 1;
+    assert!(matches!(overpaid.reconcile(), Err(Error::Payout(_))));
+
+    let mut short_pool = store;
+    short_pool.blocks[0].pool_value -= 1;
+    short_pool.blocks[0].paid += 1;
+    assert!(matches!(short_pool.reconcile(), Err(Error::Payout(_))));
+}
+
+#[test]
+fn mined_trees_pay_what_the_record_says() {
+    let mut store = BalanceStore::default();
+    let block = manifest(&[("a", 3_000), ("b", 50_000), ("c", 60_000)], 2_000);
+    let plan = store.plan(&block, THRESHOLD);
+
+    // Miner outputs in manifest order, then the pool output
+    let mut outputs: Vec<TxOut> = plan
+        .payouts
+        .entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| TxOut {
+            value: Amount::from_sat(entry.value),
+            script_pubkey: ScriptBuf::from_bytes([[0x51, 0x20].as_slice(), &[i as u8; 32]].concat()),
+        })
+        .collect();
+    outputs.push(TxOut { value: Amount::from_sat(plan.pool_value), script_pubkey: pool_script() });
+    let root = build_ctv_tree(&outputs, 2, 500).unwrap();
+
+    store.commit(plan, txid(1), &pool_script());
+    let record = &store.blocks[0];
+    assert_eq!(tree_totals(&root, &pool_script()), (record.paid, record.pool_value));
+    assert_eq!(record.pool_value, 5_000);
+    assert_eq!(record.pool_script, pool_script().to_hex_string());
+}
@@ -0,0 +1,205 @@
+//! Where the tree builders put payouts: depth by value in Huffman-shaped
+//! trees, and uneconomical ones carried over or promoted to the root by the
+//! dust-aware builder.
+
+use bitcoin::{Amount, ScriptBuf, TxOut};
+use scripts::{
+    builder::{build_dust_aware_tree, build_shaped_tree, DustHandling, DustPolicy, TreeShape},
+    error::Error,
+    exit_cost::leaf_exits,
+    tree::TreeNode,
+};
+
+const NODE_FEE: u64 = 500;
+const FEE_RATE: u64 = 10;
+
+/// Distinct P2TR-shaped scripts so every payout can be told apart.
+fn payouts(values: &[u64]) -> Vec<TxOut> {
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| TxOut {
+            value: Amount::from_sat(*value),
+            script_pubkey: ScriptBuf::from_bytes([[0x51, 0x20].as_slice(), &[i as u8 + 1; 32]].concat()),
+        })
+        .collect()
+}
+
+fn pool_script() -> ScriptBuf {
+    ScriptBuf::from_bytes([[0x51, 0x20].as_slice(), &[0xff; 32]].concat())
+}
+
+fn policy(margin: u64, handling: DustHandling) -> DustPolicy {
+    DustPolicy { fee_rate: FEE_RATE, margin, handling }
+}
+
+/// Four large payouts and two that cannot pay for two levels of unrolling.
+fn mixed_payouts() -> Vec<TxOut> {
+    payouts(&[5_000_000, 4_000, 6_000_000, 7_000_000, 3_000, 8_000_000])
+}
+
+/// Payout outputs directly on the root, anchors aside.
+fn root_payouts(root: &TreeNode) -> Vec<TxOut> {
+    root.leaf_outputs().map(|(_, output)| output.clone()).collect()
+}
+
+fn sorted(mut outputs: Vec<TxOut>) -> Vec<TxOut> {
+    outputs.sort_by(|a, b| a.script_pubkey.cmp(&b.script_pubkey));
+    outputs
+}
+
+/// (value, depth) of every payout leaf.
+fn depths(root: &TreeNode) -> Vec<(u64, usize)> {
+    leaf_exits(root).iter().map(|exit| (exit.value, exit.ancestors)).collect()
+}
+
+fn value_weighted_depth(root: &TreeNode) -> u64 {
+    depths(root).iter().map(|(value, depth)| value * *depth as u64).sum()
+}
+
+const SKEWED: [u64; 9] = [1_000_000, 2_000, 50_000, 9_000_000, 3_000, 400_000, 2_500, 80_000, 20_000_000];
+
+#[test]
+fn large_shallow_trees_put_bigger_payouts_no_deeper() {
+    for radix in 2..=4 {
+        let root = build_shaped_tree(&payouts(&SKEWED), radix, NODE_FEE, TreeShape::ValueWeighted { large_shallow: true }).unwrap();
+        let depths = depths(&root);
+        assert_eq!(depths.len(), SKEWED.len());
+        for (value, depth) in &depths {
+            for (other_value, other_depth) in &depths {
+                if value > other_value {
+                    assert!(depth <= other_depth, "{value} at {depth} below {other_value} at {other_depth}, radix {radix}");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn small_shallow_trees_put_smaller_payouts_no_deeper() {
+    for radix in 2..=4 {
+        let root = build_shaped_tree(&payouts(&SKEWED), radix, NODE_FEE, TreeShape::ValueWeighted { large_shallow: false }).unwrap();
+        let depths = depths(&root);
+        for (value, depth) in &depths {
+            for (other_value, other_depth) in &depths {
+                if value < other_value {
+                    assert!(depth <= other_depth, "{value} at {depth} below {other_value} at {other_depth}, radix {radix}");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn huffman_trees_minimise_value_weighted_depth() {
+    for radix in 2..=4 {
+        let huffman = build_shaped_tree(&payouts(&SKEWED), radix, NODE_FEE, TreeShape::ValueWeighted { large_shallow: true }).unwrap();
+        let balanced = build_shaped_tree(&payouts(&SKEWED), radix, NODE_FEE, TreeShape::Balanced).unwrap();
+        assert!(value_weighted_depth(&huffman) <= value_weighted_depth(&balanced), "radix {radix}");
+    }
+
+    // Binary Huffman over these values: the largest payout sits right under
+    // the root and the two smallest share the deepest node
+    let root = build_shaped_tree(&payouts(&SKEWED), 2, NODE_FEE, TreeShape::ValueWeighted { large_shallow: true }).unwrap();
+    let depths = depths(&root);
+    let depth_of = |value: u64| depths.iter().find(|(v, _)| *v == value).unwrap().1;
+    assert_eq!(depth_of(20_000_000), 1);
+    let deepest = depths.iter().map(|(_, depth)| *depth).max().unwrap();
+    assert_eq!(depth_of(2_000), deepest);
+    assert_eq!(depth_of(2_500), deepest);
+}
+
+#[test]
+fn remaining_leaves_clear_their_unroll_cost_by_the_margin() {
+    let payouts = mixed_payouts();
+    for margin in [0, 1_000, 10_000] {
+        let handling = DustHandling::CarryOver { pool_script: pool_script() };
+        let tree = build_dust_aware_tree(&payouts, 2, NODE_FEE, TreeShape::Balanced, &policy(margin, handling)).unwrap();
+        // The pool output is held by the pool, not claimed by a miner
+        let is_pool = |path: &[u32]| path.len() == 1 && tree.root.tx.output[path[0] as usize].script_pubkey == pool_script();
+        for exit in leaf_exits(&tree.root).into_iter().filter(|exit| !is_pool(&exit.path)) {
+            assert!(exit.value >= exit.cost(FEE_RATE) + margin, "{exit:?} at margin {margin}");
+        }
+    }
+}
+
+#[test]
+fn uneconomical_payouts_are_carried_over() {
+    let payouts = mixed_payouts();
+    let handling = DustHandling::CarryOver { pool_script: pool_script() };
+    let tree = build_dust_aware_tree(&payouts, 2, NODE_FEE, TreeShape::Balanced, &policy(0, handling)).unwrap();
+
+    assert_eq!(sorted(tree.carried.clone()), vec![payouts[1].clone(), payouts[4].clone()]);
+    assert!(tree.promoted.is_empty());
+
+    // One pool output on the root holds everything carried over
+    let pool: Vec<TxOut> = root_payouts(&tree.root).into_iter().filter(|o| o.script_pubkey == pool_script()).collect();
+    assert_eq!(pool, vec![TxOut { value: Amount::from_sat(7_000), script_pubkey: pool_script() }]);
+    assert_eq!(leaf_exits(&tree.root).len(), 5);
+}
+
+#[test]
+fn uneconomical_payouts_can_be_promoted_to_the_root() {
+    let payouts = mixed_payouts();
+    let tree = build_dust_aware_tree(&payouts, 2, NODE_FEE, TreeShape::Balanced, &policy(0, DustHandling::Promote)).unwrap();
+
+    assert_eq!(sorted(tree.promoted.clone()), vec![payouts[1].clone(), payouts[4].clone()]);
+    assert!(tree.carried.is_empty());
+    let on_root = root_payouts(&tree.root);
+    assert!(tree.promoted.iter().all(|payout| on_root.contains(payout)));
+    assert_eq!(leaf_exits(&tree.root).len(), payouts.len());
+}
+
+#[test]
+fn payouts_below_the_dust_limit_never_enter_the_tree() {
+    let payouts = payouts(&[5_000_000, 200, 6_000_000, 300]);
+    // No feerate or margin, so only the relay dust limit applies; pooled
+    // together the two dust payouts make an output worth relaying
+    let policy = DustPolicy { fee_rate: 0, margin: 0, handling: DustHandling::CarryOver { pool_script: pool_script() } };
+    let tree = build_dust_aware_tree(&payouts, 2, NODE_FEE, TreeShape::Balanced, &policy).unwrap();
+    assert_eq!(tree.carried, vec![payouts[1].clone(), payouts[3].clone()]);
+    let values: Vec<u64> = leaf_exits(&tree.root).iter().map(|exit| exit.value).collect();
+    assert_eq!(values, vec![5_000_000, 6_000_000, 500]);
+}
+
+#[test]
+fn promoted_trees_carry_payouts_below_the_dust_limit() {
+    let payouts = payouts(&[5_000_000, 100, 6_000_000]);
+    let policy = DustPolicy { fee_rate: 0, margin: 0, handling: DustHandling::Promote };
+    let tree = build_dust_aware_tree(&payouts, 2, NODE_FEE, TreeShape::Balanced, &policy).unwrap();
+    assert_eq!(tree.carried, vec![payouts[1].clone()]);
+    assert!(tree.promoted.is_empty());
+    let values: Vec<u64> = leaf_exits(&tree.root).iter().map(|exit| exit.value).collect();
+    assert_eq!(values, vec![5_000_000, 6_000_000]);
+}
+
+#[test]
+fn carry_overs_below_the_dust_limit_get_no_pool_output() {
+    let payouts = payouts(&[5_000_000, 100, 6_000_000]);
+    let policy = DustPolicy { fee_rate: 0, margin: 0, handling: DustHandling::CarryOver { pool_script: pool_script() } };
+    let tree = build_dust_aware_tree(&payouts, 2, NODE_FEE, TreeShape::Balanced, &policy).unwrap();
+    assert_eq!(tree.carried, vec![payouts[1].clone()]);
+    assert!(root_payouts(&tree.root).iter().all(|o| o.script_pubkey != pool_script()));
+    let values: Vec<u64> = leaf_exits(&tree.root).iter().map(|exit| exit.value).collect();
+    assert_eq!(values, vec![5_000_000, 6_000_000]);
+}
+
+#[test]
+fn a_lone_payout_below_the_dust_limit_leaves_no_tree() {
+    let payouts = payouts(&[100]);
+    for handling in [DustHandling::Promote, DustHandling::CarryOver { pool_script: pool_script() }] {
+        let policy = DustPolicy { fee_rate: 0, margin: 0, handling };
+        let err = build_dust_aware_tree(&payouts, 2, NODE_FEE, TreeShape::Balanced, &policy).unwrap_err();
+        assert!(matches!(err, Error::InvalidTemplate(_)), "{err}");
+    }
+}
+
+#[test]
+fn trees_without_dust_are_left_alone() {
+    let payouts = payouts(&[5_000_000, 6_000_000, 7_000_000]);
+    let handling = DustHandling::CarryOver { pool_script: pool_script() };
+    let tree = build_dust_aware_tree(&payouts, 2, NODE_FEE, TreeShape::Balanced, &policy(0, handling)).unwrap();
+    assert!(tree.carried.is_empty() && tree.promoted.is_empty());
+    assert!(root_payouts(&tree.root).iter().all(|o| o.script_pubkey != pool_script()));
+    assert_eq!(leaf_exits(&tree.root).len(), 3);
+}
@@ -0,0 +1,162 @@
+//! Consolidation of collapsed subtrees: share trades, resales, the fee
+//! surplus split and what the collapse saves over a full unroll.
+
+use bitcoin::{
+    key::{Keypair, Secp256k1},
+    secp256k1::All,
+    Amount, OutPoint, ScriptBuf, TxOut, Txid,
+    hashes::Hash,
+};
+use scripts::{
+    collapse::{build_collapse_tx, full_unroll_cost, sign_collapse_tx, ShareTrade},
+    ctv::get_virtual_bytes,
+    error::Error,
+    musig_tree::{build_musig_tree, LeafPayout, MusigTree},
+};
+
+const LEAF_VALUE: u64 = 100_000;
+const NODE_FEE: u64 = 500;
+
+struct Fixture {
+    secp: Secp256k1<All>,
+    owners: Vec<Keypair>,
+    payouts: Vec<LeafPayout>,
+    tree: MusigTree,
+}
+
+/// Four owners with one leaf each, in pairs under the root.
+fn fixture() -> Fixture {
+    let secp = Secp256k1::new();
+    let owners: Vec<Keypair> = (1..=4u8).map(|i| Keypair::from_seckey_slice(&secp, &[i; 32]).unwrap()).collect();
+    let payouts: Vec<LeafPayout> = owners
+        .iter()
+        .map(|owner| LeafPayout {
+            owner: owner.public_key(),
+            output: TxOut {
+                value: Amount::from_sat(LEAF_VALUE),
+                script_pubkey: ScriptBuf::new_p2tr(&secp, owner.x_only_public_key().0, None),
+            },
+        })
+        .collect();
+    let tree = build_musig_tree(&secp, &payouts, 2, NODE_FEE, 4 * LEAF_VALUE + 3 * NODE_FEE).unwrap();
+    Fixture { secp, owners, payouts, tree }
+}
+
+fn prevout() -> OutPoint {
+    OutPoint { txid: Txid::all_zeros(), vout: 0 }
+}
+
+fn trade(f: &Fixture, seller: usize, buyer: usize) -> ShareTrade {
+    ShareTrade { seller: f.owners[seller].public_key(), buyer: f.owners[buyer].public_key() }
+}
+
+fn values(outputs: &[TxOut]) -> Vec<u64> {
+    outputs.iter().map(|o| o.value.to_sat()).collect()
+}
+
+#[test]
+fn without_trades_every_owner_keeps_their_leaf() {
+    let f = fixture();
+    let tx = build_collapse_tx(&f.tree, &[], prevout(), &f.payouts, &[], 0).unwrap();
+    // The three node fees come back as an even bonus
+    assert_eq!(values(&tx.output), vec![LEAF_VALUE + 375; 4]);
+    for (output, payout) in tx.output.iter().zip(&f.payouts) {
+        assert_eq!(output.script_pubkey, payout.output.script_pubkey);
+    }
+}
+
+#[test]
+fn sold_shares_pay_the_buyer() {
+    let f = fixture();
+    let trades = [trade(&f, 1, 0), trade(&f, 3, 2)];
+    let tx = build_collapse_tx(&f.tree, &[], prevout(), &f.payouts, &trades, 0).unwrap();
+    assert_eq!(values(&tx.output), vec![2 * LEAF_VALUE + 750; 2]);
+    assert_eq!(tx.output[0].script_pubkey, f.payouts[0].output.script_pubkey);
+    assert_eq!(tx.output[1].script_pubkey, f.payouts[2].output.script_pubkey);
+}
+
+#[test]
+fn resales_pay_the_last_buyer() {
+    let f = fixture();
+    let trades = [trade(&f, 0, 1), trade(&f, 1, 2)];
+    let tx = build_collapse_tx(&f.tree, &[], prevout(), &f.payouts, &trades, 0).unwrap();
+    // The third owner ends up with the first two leaves and their own
+    assert_eq!(tx.output.len(), 2);
+    assert_eq!(tx.output[0].script_pubkey, f.payouts[2].output.script_pubkey);
+    assert_eq!(tx.output[1].script_pubkey, f.payouts[3].output.script_pubkey);
+    assert_eq!(values(&tx.output), vec![3 * LEAF_VALUE + 750, LEAF_VALUE + 750]);
+}
+
+#[test]
+fn double_sales_and_cycles_are_rejected() {
+    let f = fixture();
+    let sold_twice = [trade(&f, 0, 1), trade(&f, 0, 2)];
+    assert!(matches!(
+        build_collapse_tx(&f.tree, &[], prevout(), &f.payouts, &sold_twice, 0),
+        Err(Error::Payout(_))
+    ));
+
+    let cycle = [trade(&f, 0, 1), trade(&f, 1, 0)];
+    assert!(matches!(
+        build_collapse_tx(&f.tree, &[], prevout(), &f.payouts, &cycle, 0),
+        Err(Error::Payout(_))
+    ));
+}
+
+#[test]
+fn trades_with_owners_outside_the_node_are_ignored() {
+    let f = fixture();
+    // The left node only holds the first two leaves
+    let trades = [trade(&f, 0, 2), trade(&f, 1, 0)];
+    let tx = build_collapse_tx(&f.tree, &[0], prevout(), &f.payouts, &trades, 0).unwrap();
+    assert_eq!(tx.output.len(), 1);
+    assert_eq!(tx.output[0].script_pubkey, f.payouts[0].output.script_pubkey);
+    assert_eq!(values(&tx.output), vec![2 * LEAF_VALUE + NODE_FEE]);
+}
+
+#[test]
+fn fees_and_outputs_spend_exactly_the_node_input() {
+    let f = fixture();
+    for fee_rate in [1, 3, 7] {
+        let tx = build_collapse_tx(&f.tree, &[], prevout(), &f.payouts, &[trade(&f, 3, 1)], fee_rate).unwrap();
+        let paid: u64 = values(&tx.output).iter().sum();
+        // Fees are sized with a 64 byte signature in the witness
+        let mut signed = tx.clone();
+        signed.input[0].witness.push([0; 64]);
+        let fee = get_virtual_bytes(&signed) as u64 * fee_rate;
+        assert_eq!(paid + fee, f.tree.root.input_value);
+
+        // The second owner holds two leaves; leftover sats from the even
+        // split go to the last output
+        let bonuses: Vec<u64> = values(&tx.output).iter().zip([1, 2, 1]).map(|(v, leaves)| v - leaves * LEAF_VALUE).collect();
+        assert_eq!(bonuses[0], bonuses[1]);
+        assert!(bonuses[2] - bonuses[0] < bonuses.len() as u64);
+    }
+}
+
+#[test]
+fn collapse_is_cheaper_than_a_full_unroll() {
+    let f = fixture();
+    let full = full_unroll_cost(&f.tree.root);
+    assert_eq!(full.transactions, 3);
+    assert_eq!(full.fees, 3 * NODE_FEE);
+
+    let tx = build_collapse_tx(&f.tree, &[], prevout(), &f.payouts, &[trade(&f, 1, 0), trade(&f, 3, 2)], 2).unwrap();
+    let mut signed = tx.clone();
+    signed.input[0].witness.push([0; 64]);
+    assert!(get_virtual_bytes(&signed) < full.vbytes);
+}
+
+#[test]
+fn every_owner_under_the_node_must_sign() {
+    let f = fixture();
+    let lock = f.tree.lock(&[]).unwrap();
+    let spent = TxOut { value: Amount::from_sat(f.tree.root.input_value), script_pubkey: lock.script_pubkey() };
+    let mut tx = build_collapse_tx(&f.tree, &[], prevout(), &f.payouts, &[], 1).unwrap();
+    assert!(matches!(
+        sign_collapse_tx(&f.secp, lock, &mut tx, spent.clone(), &f.owners[..3]),
+        Err(Error::Taproot(_))
+    ));
+    sign_collapse_tx(&f.secp, lock, &mut tx, spent, &f.owners).unwrap();
+    assert_eq!(tx.input[0].witness.len(), 1);
+}
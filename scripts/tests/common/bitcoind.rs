@@ -0,0 +1,119 @@
+//! Spawns a throwaway regtest or custom signet bitcoind for the integration tests.
+
+use std::error::Error;
+use std::fs;
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bitcoincore_rpc::{Auth, Client, RpcApi};
+use bitcoin::{ScriptBuf, Txid};
+use scripts::rpc::NodeClient;
+use tempfile::TempDir;
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+const WALLET: &str = "testwallet";
+
+/// A bitcoind from `PATH` running on free ports in a temp datadir with the
+/// devenv's `config/bitcoin.conf` (or `config/signet.conf`). Stopped and
+/// deleted on drop.
+pub struct Bitcoind {
+    /// Node-level client.
+    pub client: Client,
+    /// Client scoped to a freshly created wallet.
+    pub wallet: NodeClient,
+    process: Child,
+    _datadir: TempDir,
+}
+
+impl Bitcoind {
+    pub fn start() -> Result<Self, Box<dyn Error>> {
+        Bitcoind::start_chain("bitcoin.conf", "regtest", &[])
+    }
+
+    /// A regtest node with extra command line settings, such as a
+    /// `-blockmintxfee` that leaves cheap transactions in the mempool.
+    pub fn start_with(args: &[String]) -> Result<Self, Box<dyn Error>> {
+        Bitcoind::start_chain("bitcoin.conf", "regtest", args)
+    }
+
+    /// A custom signet whose blocks must satisfy `challenge`.
+    pub fn start_signet(challenge: &ScriptBuf) -> Result<Self, Box<dyn Error>> {
+        Bitcoind::start_chain("signet.conf", "signet", &[format!("-signetchallenge={}", challenge.to_hex_string())])
+    }
+
+    fn start_chain(conf: &str, chain_dir: &str, args: &[String]) -> Result<Self, Box<dyn Error>> {
+        let datadir = tempfile::tempdir()?;
+        let conf = Path::new(env!("CARGO_MANIFEST_DIR")).join("../config").join(conf);
+        fs::copy(&conf, datadir.path().join("bitcoin.conf"))?;
+
+        // Pin the cookie down, custom signets may not get the usual subdirectory
+        let cookie = datadir.path().join(chain_dir).join(".cookie");
+        fs::create_dir_all(datadir.path().join(chain_dir))?;
+
+        // Command line settings win over the config, so only the ports change
+        let rpc_port = free_port()?;
+        let mut process = Command::new("bitcoind")
+            .arg(format!("-datadir={}", datadir.path().display()))
+            .arg(format!("-rpcport={rpc_port}"))
+            .arg(format!("-port={}", free_port()?))
+            .arg(format!("-rpccookiefile={}", cookie.display()))
+            .arg("-listen=0")
+            .args(args)
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("failed to run bitcoind from PATH: {e}"))?;
+
+        let url = format!("http://127.0.0.1:{rpc_port}");
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        let client = loop {
+            if let Some(status) = process.try_wait()? {
+                return Err(format!("bitcoind exited during startup: {status}").into());
+            }
+            if cookie.exists() {
+                let client = Client::new(&url, Auth::CookieFile(cookie.clone()))?;
+                if client.get_blockchain_info().is_ok() {
+                    break client;
+                }
+            }
+            if Instant::now() > deadline {
+                let _ = process.kill();
+                return Err("bitcoind RPC did not come up in time".into());
+            }
+            thread::sleep(Duration::from_millis(100));
+        };
+
+        client.create_wallet(WALLET, None, None, None, None)?;
+        let wallet = NodeClient::new(Client::new(&format!("{url}/wallet/{WALLET}"), Auth::CookieFile(cookie))?);
+        Ok(Bitcoind { client, wallet, process, _datadir: datadir })
+    }
+
+    /// Whether `txid` is in a block.
+    pub fn is_confirmed(&self, txid: &Txid) -> Result<bool, Box<dyn Error>> {
+        let info = self.client.get_raw_transaction_info(txid, None)?;
+        Ok(info.confirmations.unwrap_or(0) > 0)
+    }
+}
+
+impl Drop for Bitcoind {
+    fn drop(&mut self) {
+        if self.client.stop().is_ok() {
+            let deadline = Instant::now() + STARTUP_TIMEOUT;
+            while Instant::now() < deadline {
+                if let Ok(Some(_)) = self.process.try_wait() {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+        }
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+/// A port nothing is listening on right now.
+fn free_port() -> Result<u16, Box<dyn Error>> {
+    Ok(TcpListener::bind("127.0.0.1:0")?.local_addr()?.port())
+}
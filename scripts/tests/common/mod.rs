@@ -0,0 +1,19 @@
+//! Fixtures shared by the integration tests. Each test crate pulls in its own
+//! subset, so whatever it leaves unused is allowed.
+
+#![allow(dead_code)]
+
+#[cfg(feature = "bitcoind-tests")]
+pub mod bitcoind;
+
+use bitcoin::{Amount, ScriptBuf, TxOut};
+
+/// A P2TR script whose key is `i` repeated, so outputs are told apart by a byte.
+pub fn p2tr(i: u8) -> ScriptBuf {
+    ScriptBuf::from_bytes([[0x51, 0x20].as_slice(), &[i; 32]].concat())
+}
+
+/// `count` payouts of 10k, 20k, ... sats to `p2tr(1)`, `p2tr(2)`, ...
+pub fn payouts(count: u8) -> Vec<TxOut> {
+    (1..=count).map(|i| TxOut { value: Amount::from_sat(10_000 * i as u64), script_pubkey: p2tr(i) }).collect()
+}
@@ -0,0 +1,187 @@
+//! The CSFS script builders: signatures over messages and digests, the exact
+//! bytes of each leaf, and the witness each leaf is spent with, run through a
+//! small tapscript evaluator covering the opcodes the leaves use.
+
+use bitcoin::{
+    hashes::Hash,
+    key::{Keypair, Secp256k1},
+    opcodes::all::{OP_CHECKSIG, OP_OVER, OP_VERIFY},
+    script::Instruction,
+    secp256k1::{schnorr::Signature, Message},
+    sighash::{Prevouts, SighashCache},
+    taproot::{LeafVersion, TapLeafHash},
+    Amount, ScriptBuf, TxOut, Witness, XOnlyPublicKey,
+};
+use scripts::{
+    csfs::{
+        csfs_gated_checksig_script, csfs_script, message_digest, sign_digest, sign_message, signed_template_script,
+        verify_message, OP_CSFS,
+    },
+    ctv::{calc_ctv_hash, template_tx, OP_CTV},
+    taproot::{leaf_spend_info, sign_script_spend, tapscript_witness},
+};
+
+fn keypair(byte: u8) -> (Keypair, XOnlyPublicKey) {
+    let keypair = Keypair::from_seckey_slice(&Secp256k1::new(), &[byte; 32]).unwrap();
+    let (xonly, _) = XOnlyPublicKey::from_keypair(&keypair);
+    (keypair, xonly)
+}
+
+/// Runs `leaf` on the stack items of `witness` (everything before the leaf
+/// and control block), with `sighash` as the message `OP_CHECKSIG` checks and
+/// `template` as the hash `OP_CTV` checks. Returns whether the spend is valid.
+fn spends(leaf: &ScriptBuf, witness: &Witness, sighash: [u8; 32], template: [u8; 32]) -> bool {
+    let secp = Secp256k1::verification_only();
+    let items: Vec<&[u8]> = witness.iter().collect();
+    assert_eq!(items[items.len() - 2], leaf.as_bytes(), "the leaf comes second to last");
+    let mut stack: Vec<Vec<u8>> = items[..items.len() - 2].iter().map(|item| item.to_vec()).collect();
+
+    let schnorr = |sig: &[u8], digest: &[u8], key: &[u8]| {
+        let (Ok(sig), Ok(digest), Ok(key)) =
+            (Signature::from_slice(sig), <[u8; 32]>::try_from(digest), XOnlyPublicKey::from_slice(key))
+        else {
+            return false;
+        };
+        secp.verify_schnorr(&sig, &Message::from_digest(digest), &key).is_ok()
+    };
+    let truth = |ok: bool| if ok { vec![1] } else { vec![] };
+
+    for instruction in leaf.instructions() {
+        match instruction.unwrap() {
+            Instruction::PushBytes(bytes) => stack.push(bytes.as_bytes().to_vec()),
+            Instruction::Op(OP_OVER) => {
+                let Some(item) = stack.len().checked_sub(2).map(|i| stack[i].clone()) else {
+                    return false;
+                };
+                stack.push(item);
+            }
+            Instruction::Op(OP_CSFS) => {
+                // BIP-348 pops the key, then the message, then the signature
+                let (Some(key), Some(message), Some(sig)) = (stack.pop(), stack.pop(), stack.pop()) else {
+                    return false;
+                };
+                stack.push(truth(schnorr(&sig, &message, &key)));
+            }
+            Instruction::Op(OP_CHECKSIG) => {
+                let (Some(key), Some(sig)) = (stack.pop(), stack.pop()) else {
+                    return false;
+                };
+                stack.push(truth(schnorr(&sig, &sighash, &key)));
+            }
+            Instruction::Op(OP_VERIFY) => {
+                if stack.pop().is_none_or(|top| top.is_empty()) {
+                    return false;
+                }
+            }
+            // BIP-119 leaves the hash on the stack
+            Instruction::Op(OP_CTV) => {
+                if stack.last().map(Vec::as_slice) != Some(template.as_slice()) {
+                    return false;
+                }
+            }
+            Instruction::Op(op) => panic!("the evaluator does not cover {op:?}"),
+        }
+    }
+    // Tapscript's clean stack rule
+    stack.len() == 1 && !stack[0].is_empty()
+}
+
+#[test]
+fn signatures_round_trip() {
+    let secp = Secp256k1::new();
+    let (signer, signer_key) = keypair(1);
+    let (_, other_key) = keypair(2);
+
+    let sig = sign_message(&secp, &signer, b"release");
+    assert!(verify_message(&secp, &sig, b"release", &signer_key));
+    assert!(!verify_message(&secp, &sig, b"release!", &signer_key));
+    assert!(!verify_message(&secp, &sig, b"release", &other_key));
+
+    // A message is signed as its digest, and a digest as is
+    let digest = message_digest(b"release");
+    assert_eq!(hex::encode(digest), "a4d451ec23463726f72c43d64c710968f6b602cd653b4de8adee1b556240a829");
+    let msg = Message::from_digest(digest);
+    secp.verify_schnorr(&sig, &msg, &signer_key).unwrap();
+    secp.verify_schnorr(&sign_digest(&secp, &signer, digest), &msg, &signer_key).unwrap();
+}
+
+#[test]
+fn leaves_have_the_documented_bytes() {
+    let (_, oracle) = keypair(1);
+    let (_, owner) = keypair(2);
+    let key = |xonly: &XOnlyPublicKey| format!("20{}", hex::encode(xonly.serialize()));
+
+    // 0xcc is OP_CSFS, 0x69 OP_VERIFY, 0xac OP_CHECKSIG, 0x78 OP_OVER and 0xb3 OP_CTV
+    assert_eq!(csfs_script(&oracle).to_hex_string(), format!("{}cc", key(&oracle)));
+    assert_eq!(
+        csfs_gated_checksig_script(b"release", &oracle, &owner).to_hex_string(),
+        format!("20{}{}cc69{}ac", hex::encode(message_digest(b"release")), key(&oracle), key(&owner))
+    );
+    assert_eq!(signed_template_script(&oracle).to_hex_string(), format!("78{}cc69b3", key(&oracle)));
+}
+
+#[test]
+fn csfs_leaves_take_the_signature_under_the_digest() {
+    let secp = Secp256k1::new();
+    let (signer, signer_key) = keypair(1);
+    let leaf = csfs_script(&signer_key);
+    let spend_info = leaf_spend_info(&secp, keypair(9).1, &leaf).unwrap();
+    let digest = message_digest(b"anything");
+    let sig = sign_digest(&secp, &signer, digest).serialize();
+
+    let witness = tapscript_witness(&[&sig, &digest], &leaf, &spend_info).unwrap();
+    assert!(spends(&leaf, &witness, [0; 32], [0; 32]));
+    let swapped = tapscript_witness(&[&digest, &sig], &leaf, &spend_info).unwrap();
+    assert!(!spends(&leaf, &swapped, [0; 32], [0; 32]));
+}
+
+#[test]
+fn gated_leaves_take_the_owner_signature_under_the_oracle_one() {
+    let secp = Secp256k1::new();
+    let (oracle, oracle_key) = keypair(1);
+    let (owner, owner_key) = keypair(2);
+    let leaf = csfs_gated_checksig_script(b"release", &oracle_key, &owner_key);
+    let spend_info = leaf_spend_info(&secp, keypair(9).1, &leaf).unwrap();
+
+    let prevout = TxOut { value: Amount::from_sat(50_000), script_pubkey: ScriptBuf::new_p2tr_tweaked(spend_info.output_key()) };
+    let tx = template_tx(vec![TxOut { value: Amount::from_sat(49_500), script_pubkey: ScriptBuf::new() }]);
+    let owner_sig = sign_script_spend(&secp, &owner, &tx, 0, std::slice::from_ref(&prevout), &leaf).unwrap().serialize();
+    let sighash = SighashCache::new(&tx)
+        .taproot_script_spend_signature_hash(
+            0,
+            &Prevouts::All(&[prevout]),
+            TapLeafHash::from_script(&leaf, LeafVersion::TapScript),
+            bitcoin::TapSighashType::Default,
+        )
+        .unwrap()
+        .to_byte_array();
+    let oracle_sig = sign_message(&secp, &oracle, b"release").serialize();
+
+    let witness = tapscript_witness(&[&owner_sig, &oracle_sig], &leaf, &spend_info).unwrap();
+    assert!(spends(&leaf, &witness, sighash, [0; 32]));
+    let swapped = tapscript_witness(&[&oracle_sig, &owner_sig], &leaf, &spend_info).unwrap();
+    assert!(!spends(&leaf, &swapped, sighash, [0; 32]));
+    let other_message = sign_message(&secp, &oracle, b"keep").serialize();
+    let unreleased = tapscript_witness(&[&owner_sig, &other_message], &leaf, &spend_info).unwrap();
+    assert!(!spends(&leaf, &unreleased, sighash, [0; 32]));
+}
+
+#[test]
+fn signed_template_leaves_take_the_hash_under_its_signature() {
+    let secp = Secp256k1::new();
+    let (operator, operator_key) = keypair(1);
+    let leaf = signed_template_script(&operator_key);
+    let spend_info = leaf_spend_info(&secp, keypair(9).1, &leaf).unwrap();
+    let template = calc_ctv_hash(&[TxOut { value: Amount::from_sat(49_500), script_pubkey: ScriptBuf::new() }]);
+    let sig = sign_digest(&secp, &operator, template).serialize();
+
+    let witness = tapscript_witness(&[&template, &sig], &leaf, &spend_info).unwrap();
+    assert!(spends(&leaf, &witness, [0; 32], template));
+    let swapped = tapscript_witness(&[&sig, &template], &leaf, &spend_info).unwrap();
+    assert!(!spends(&leaf, &swapped, [0; 32], template));
+
+    // A template the operator did not sign fails even with a valid signature
+    let other = calc_ctv_hash(&[TxOut { value: Amount::from_sat(49_000), script_pubkey: ScriptBuf::new() }]);
+    let forged = tapscript_witness(&[&other, &sig], &leaf, &spend_info).unwrap();
+    assert!(!spends(&leaf, &forged, [0; 32], other));
+}
@@ -0,0 +1,237 @@
+//! The Electrum chain source against a stub server answering from canned
+//! script histories, including notifications for subscribed scripthashes
+//! arriving between responses.
+
+mod common;
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+use bitcoin::{
+    absolute::LockTime, consensus::encode::serialize_hex, hashes::Hash, transaction::Version, Amount, OutPoint,
+    ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+use common::p2tr;
+use scripts::{
+    builder::build_ctv_tree,
+    chain::{ChainSource, TxStatus},
+    electrum::{script_hash, ElectrumClient},
+    explorer::explore,
+    tree::TreeNode,
+};
+use serde_json::{json, Value};
+
+/// Answers each request with the messages `handler` returns for its id,
+/// method and params.
+fn serve<F>(handler: F) -> ElectrumClient
+where
+    F: Fn(u64, &str, &Value) -> Vec<Value> + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let reader = BufReader::new(stream.try_clone().unwrap());
+        for line in reader.lines() {
+            let request: Value = serde_json::from_str(&line.unwrap()).unwrap();
+            let id = request["id"].as_u64().unwrap();
+            let method = request["method"].as_str().unwrap();
+            let messages = match method {
+                "server.version" => vec![ok(id, json!(["stub 1.0", "1.4"]))],
+                _ => handler(id, method, &request["params"]),
+            };
+            for message in messages {
+                stream.write_all(format!("{message}\n").as_bytes()).unwrap();
+            }
+        }
+    });
+    ElectrumClient::new(&addr.to_string()).unwrap()
+}
+
+fn ok(id: u64, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+fn not_found(id: u64) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": 2, "message": "daemon error: No such mempool or blockchain transaction" } })
+}
+
+fn notification(hash: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "method": "blockchain.scripthash.subscribe", "params": [hash, "ab".repeat(32)] })
+}
+
+/// Transactions and script histories of a coinbase paying a four leaf tree
+/// whose root confirmed at height 120, with nothing below it broadcast.
+struct Chain {
+    coinbase: Transaction,
+    root: TreeNode,
+    transactions: HashMap<Txid, Transaction>,
+    histories: HashMap<String, Vec<(Txid, i64)>>,
+}
+
+impl Chain {
+    fn new() -> Chain {
+        let leaves: Vec<TxOut> = (1..=4).map(|i| TxOut { value: Amount::from_sat(10_000_000), script_pubkey: p2tr(i) }).collect();
+        let mut root = build_ctv_tree(&leaves, 2, 500).unwrap();
+        let coinbase = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::from_bytes(vec![0x01, 0x14]),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut { value: Amount::from_sat(root.input_value), script_pubkey: p2tr(0) }],
+        };
+        root.bind(OutPoint { txid: coinbase.txid(), vout: 0 });
+
+        let mut histories: HashMap<String, Vec<(Txid, i64)>> = HashMap::new();
+        histories.insert(script_hash(&coinbase.output[0].script_pubkey), vec![(coinbase.txid(), 20), (root.txid(), 120)]);
+        for output in &root.tx.output {
+            histories.entry(script_hash(&output.script_pubkey)).or_default().push((root.txid(), 120));
+        }
+        let transactions = [(coinbase.txid(), coinbase.clone()), (root.txid(), root.tx.clone())].into();
+        Chain { coinbase, root, transactions, histories }
+    }
+
+    fn answer(&self, id: u64, method: &str, params: &Value) -> Vec<Value> {
+        let param = params[0].as_str().unwrap();
+        match method {
+            "blockchain.transaction.get" => match self.transactions.get(&param.parse::<Txid>().unwrap()) {
+                Some(tx) => vec![ok(id, json!(serialize_hex(tx)))],
+                None => vec![not_found(id)],
+            },
+            "blockchain.scripthash.get_history" => {
+                let history = self.histories.get(param).cloned().unwrap_or_default();
+                let entries: Vec<Value> = history
+                    .into_iter()
+                    .map(|(txid, height)| json!({ "tx_hash": txid.to_string(), "height": height }))
+                    .collect();
+                vec![ok(id, json!(entries))]
+            }
+            "blockchain.scripthash.subscribe" => vec![ok(id, Value::Null)],
+            _ => panic!("unexpected {method}"),
+        }
+    }
+}
+
+#[test]
+fn script_hashes_match_the_protocol_docs() {
+    // The genesis coinbase's P2PKH, from the Electrum protocol documentation
+    let script = ScriptBuf::from_hex("76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac").unwrap();
+    assert_eq!(script_hash(&script), "8b01df4e368ea28f8dc0423bcf7a4923e3a12d307c875e47a0cfbf90b5c39161");
+}
+
+#[test]
+fn explores_a_tree_through_electrum() {
+    let chain = Chain::new();
+    let (coinbase, root) = (chain.coinbase.clone(), chain.root.clone());
+    let electrum = serve(move |id, method, params| chain.answer(id, method, params));
+
+    let start = OutPoint { txid: coinbase.txid(), vout: 0 };
+    let explored = explore(&electrum, start, Some(&root)).unwrap();
+
+    assert_eq!(explored.spent_by, Some(root.txid()));
+    let root_node = explored.child.as_ref().unwrap();
+    assert_eq!(root_node.status, TxStatus::Confirmed { height: 120 });
+    for output in &root_node.outputs {
+        assert_eq!(output.spent_by, None);
+        if let Some(child) = &output.child {
+            assert_eq!(child.status, TxStatus::Unbroadcast);
+        }
+    }
+    assert_eq!(root_node.unclaimed_leaves().len(), 4);
+}
+
+#[test]
+fn unknown_transactions_are_unbroadcast() {
+    let chain = Chain::new();
+    let electrum = serve(move |id, method, params| chain.answer(id, method, params));
+    let txid = Txid::from_byte_array([0x77; 32]);
+
+    assert!(electrum.fetch_transaction(&txid).unwrap().is_none());
+    assert_eq!(electrum.transaction_status(&txid).unwrap(), TxStatus::Unbroadcast);
+    assert!(electrum.outspends(&txid).unwrap().is_empty());
+}
+
+#[test]
+fn other_server_errors_are_not_taken_for_missing_transactions() {
+    let electrum = serve(|id, _, _| {
+        vec![json!({ "jsonrpc": "2.0", "id": id, "error": { "code": -32600, "message": "excessive resource usage" } })]
+    });
+    let txid = Txid::from_byte_array([0x77; 32]);
+
+    let err = electrum.fetch_transaction(&txid).unwrap_err();
+    assert!(err.to_string().contains("excessive resource usage"), "{err}");
+    assert!(electrum.transaction_status(&txid).is_err());
+}
+
+#[test]
+fn not_found_messages_of_other_servers_read_as_missing() {
+    for message in ["Transaction not found", "cannot find tx", "No such mempool or blockchain transaction. Use gettransaction for wallet transactions."] {
+        let electrum = serve(move |id, _, _| vec![json!({ "jsonrpc": "2.0", "id": id, "error": { "code": 2, "message": message } })]);
+        assert!(electrum.fetch_transaction(&Txid::from_byte_array([0x77; 32])).unwrap().is_none(), "{message}");
+    }
+}
+
+#[test]
+fn mempool_transactions_have_no_height() {
+    let mut chain = Chain::new();
+    let root_txid = chain.root.txid();
+    for history in chain.histories.values_mut() {
+        for entry in history.iter_mut().filter(|(txid, _)| *txid == root_txid) {
+            entry.1 = 0;
+        }
+    }
+    let electrum = serve(move |id, method, params| chain.answer(id, method, params));
+
+    assert_eq!(electrum.transaction_status(&root_txid).unwrap(), TxStatus::Mempool);
+}
+
+#[test]
+fn notifications_between_responses_are_queued() {
+    let chain = Chain::new();
+    let coinbase = chain.coinbase.clone();
+    let watched = script_hash(&coinbase.output[0].script_pubkey);
+    let electrum = serve(move |id, method, params| {
+        let mut messages = chain.answer(id, method, params);
+        // A block arrives just before the history is answered
+        if method == "blockchain.scripthash.get_history" {
+            messages.insert(0, notification(&script_hash(&chain.coinbase.output[0].script_pubkey)));
+        }
+        messages
+    });
+
+    assert_eq!(electrum.subscribe(&coinbase.output[0].script_pubkey).unwrap(), None);
+    assert_eq!(electrum.wait_for_change(Some(Duration::from_millis(50))).unwrap(), None);
+
+    assert_eq!(electrum.transaction_status(&coinbase.txid()).unwrap(), TxStatus::Confirmed { height: 20 });
+    assert_eq!(electrum.wait_for_change(Some(Duration::from_millis(50))).unwrap(), Some(watched));
+    assert_eq!(electrum.wait_for_change(Some(Duration::from_millis(50))).unwrap(), None);
+}
+
+#[test]
+fn broadcast_returns_the_txid() {
+    let chain = Chain::new();
+    let root = chain.root.clone();
+    let expected = serialize_hex(&root.tx);
+    let electrum = serve(move |id, method, params| {
+        assert_eq!(method, "blockchain.transaction.broadcast");
+        let hex = params[0].as_str().unwrap();
+        if hex == expected {
+            vec![ok(id, json!(chain.root.txid().to_string()))]
+        } else {
+            vec![json!({ "jsonrpc": "2.0", "id": id, "error": { "code": 1, "message": "bad-txns-inputs-missingorspent" } })]
+        }
+    });
+
+    assert_eq!(electrum.broadcast(&root.tx).unwrap(), root.txid());
+    let mut other = root.tx.clone();
+    other.lock_time = LockTime::from_consensus(1);
+    let err = electrum.broadcast(&other).unwrap_err();
+    assert!(err.to_string().contains("bad-txns-inputs-missingorspent"), "{err}");
+}
@@ -0,0 +1,232 @@
+//! The Esplora chain source against a stub HTTP server serving canned API
+//! responses, and the tree explorer walking a partly unrolled tree through it.
+
+mod common;
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use bitcoin::{
+    absolute::LockTime, consensus::encode::serialize, hashes::Hash, transaction::Version, Amount, BlockHash,
+    OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+use common::p2tr;
+use scripts::{
+    builder::build_ctv_tree,
+    chain::{ChainSource, TxStatus},
+    esplora::EsploraClient,
+    explorer::explore,
+    tree::TreeNode,
+};
+
+/// Canned responses keyed by "METHOD /path": (status, body).
+type Routes = HashMap<String, (u16, Vec<u8>)>;
+
+/// Recorded requests: (method, path, body).
+type Requests = Arc<Mutex<Vec<(String, String, String)>>>;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Encoding {
+    ContentLength,
+    Chunked,
+}
+
+/// Serves `routes` on a free port and records every request. Anything not
+/// routed gets a 404.
+struct Stub {
+    url: String,
+    requests: Requests,
+}
+
+impl Stub {
+    fn serve(prefix: &str, routes: Routes, encoding: Encoding) -> Stub {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}{prefix}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let recorded = Arc::clone(&requests);
+        let prefix = prefix.to_string();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                let mut parts = request_line.split_whitespace();
+                let method = parts.next().unwrap().to_string();
+                let path = parts.next().unwrap().strip_prefix(prefix.as_str()).unwrap().to_string();
+                let (status, response) = routes
+                    .get(&format!("{method} {path}"))
+                    .cloned()
+                    .unwrap_or((404, b"Not Found".to_vec()));
+                recorded.lock().unwrap().push((method, path, String::from_utf8(body).unwrap()));
+
+                match encoding {
+                    Encoding::ContentLength => {
+                        write!(stream, "HTTP/1.1 {status} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", response.len()).unwrap();
+                        stream.write_all(&response).unwrap();
+                    }
+                    Encoding::Chunked => {
+                        write!(stream, "HTTP/1.1 {status} X\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n").unwrap();
+                        let (first, rest) = response.split_at(response.len() / 2);
+                        for chunk in [first, rest] {
+                            write!(stream, "{:x};ext=1\r\n", chunk.len()).unwrap();
+                            stream.write_all(chunk).unwrap();
+                            stream.write_all(b"\r\n").unwrap();
+                        }
+                        stream.write_all(b"0\r\n\r\n").unwrap();
+                    }
+                }
+            }
+        });
+
+        Stub { url, requests }
+    }
+
+    fn client(&self) -> EsploraClient {
+        EsploraClient::new(&self.url).unwrap()
+    }
+}
+
+fn route(routes: &mut Routes, key: &str, body: impl Into<Vec<u8>>) {
+    routes.insert(key.to_string(), (200, body.into()));
+}
+
+/// A coinbase paying a four leaf tree, whose root is confirmed at height 120
+/// and nothing below it is broadcast.
+fn partly_unrolled_tree() -> (Transaction, TreeNode, Routes) {
+    let leaves: Vec<TxOut> = (1..=4).map(|i| TxOut { value: Amount::from_sat(10_000_000), script_pubkey: p2tr(i) }).collect();
+    let mut root = build_ctv_tree(&leaves, 2, 500).unwrap();
+    let coinbase = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: ScriptBuf::from_bytes(vec![0x01, 0x14]),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut { value: Amount::from_sat(root.input_value), script_pubkey: p2tr(0) }],
+    };
+    root.bind(OutPoint { txid: coinbase.txid(), vout: 0 });
+    let root_txid = root.txid();
+
+    let mut routes = HashMap::new();
+    for (tx, height) in [(&coinbase, 20), (&root.tx, 120)] {
+        let txid = tx.txid();
+        route(&mut routes, &format!("GET /tx/{txid}/raw"), serialize(tx));
+        route(&mut routes, &format!("GET /tx/{txid}/status"), format!(r#"{{"confirmed":true,"block_height":{height},"block_hash":"{}"}}"#, BlockHash::all_zeros()));
+    }
+    route(
+        &mut routes,
+        &format!("GET /tx/{}/outspends", coinbase.txid()),
+        format!(r#"[{{"spent":true,"txid":"{root_txid}","vin":0,"status":{{"confirmed":true,"block_height":120}}}}]"#),
+    );
+    let unspent = vec![r#"{"spent":false}"#; root.tx.output.len()].join(",");
+    route(&mut routes, &format!("GET /tx/{root_txid}/outspends"), format!("[{unspent}]"));
+    (coinbase, root, routes)
+}
+
+#[test]
+fn explores_a_tree_through_esplora() {
+    let (coinbase, root, routes) = partly_unrolled_tree();
+    let stub = Stub::serve("", routes, Encoding::ContentLength);
+
+    let start = OutPoint { txid: coinbase.txid(), vout: 0 };
+    let explored = explore(&stub.client(), start, Some(&root)).unwrap();
+
+    assert_eq!(explored.spent_by, Some(root.txid()));
+    let root_node = explored.child.as_ref().unwrap();
+    assert_eq!(root_node.status, TxStatus::Confirmed { height: 120 });
+    for output in &root_node.outputs {
+        if let Some(child) = &output.child {
+            assert_eq!(child.status, TxStatus::Unbroadcast);
+        }
+    }
+    assert_eq!(root_node.unclaimed_leaves().len(), 4);
+}
+
+#[test]
+fn unknown_transactions_are_unbroadcast() {
+    let stub = Stub::serve("", HashMap::new(), Encoding::ContentLength);
+    let client = stub.client();
+    let txid = Txid::from_byte_array([0x77; 32]);
+
+    assert!(client.fetch_transaction(&txid).unwrap().is_none());
+    assert_eq!(client.transaction_status(&txid).unwrap(), TxStatus::Unbroadcast);
+    assert!(client.outspends(&txid).unwrap().is_empty());
+}
+
+#[test]
+fn broadcast_posts_the_raw_hex() {
+    let (coinbase, root, _) = partly_unrolled_tree();
+    let mut routes = HashMap::new();
+    route(&mut routes, "POST /tx", root.txid().to_string());
+    let stub = Stub::serve("/api", routes, Encoding::ContentLength);
+
+    let txid = stub.client().broadcast(&root.tx).unwrap();
+
+    assert_eq!(txid, root.txid());
+    let requests = stub.requests.lock().unwrap();
+    assert_eq!(requests[0].1, "/tx");
+    assert_eq!(requests[0].2, bitcoin::consensus::encode::serialize_hex(&root.tx));
+    assert_ne!(coinbase.txid(), txid);
+}
+
+#[test]
+fn rejected_broadcasts_carry_the_reason() {
+    let (_, root, _) = partly_unrolled_tree();
+    let mut routes = HashMap::new();
+    routes.insert("POST /tx".to_string(), (400, b"sendrawtransaction RPC error: {\"code\":-25,\"message\":\"bad-txns-inputs-missingorspent\"}".to_vec()));
+    let stub = Stub::serve("", routes, Encoding::ContentLength);
+
+    let err = stub.client().broadcast(&root.tx).unwrap_err();
+    assert!(err.to_string().contains("bad-txns-inputs-missingorspent"), "{err}");
+}
+
+#[test]
+fn reads_chunked_responses() {
+    let (coinbase, root, routes) = partly_unrolled_tree();
+    let stub = Stub::serve("", routes, Encoding::Chunked);
+    let client = stub.client();
+
+    assert_eq!(client.outspends(&coinbase.txid()).unwrap(), vec![Some(root.txid())]);
+    assert_eq!(client.fetch_transaction(&coinbase.txid()).unwrap(), Some(coinbase.clone()));
+    assert_eq!(client.transaction_status(&coinbase.txid()).unwrap(), TxStatus::Confirmed { height: 20 });
+}
+
+#[test]
+fn http_and_https_urls_are_accepted() {
+    assert!(EsploraClient::new("https://blockstream.info/api").is_ok());
+    assert!(EsploraClient::new("http://localhost/api/").is_ok());
+    assert!(EsploraClient::new("http://").is_err());
+    assert!(EsploraClient::new("https:///api").is_err());
+    assert!(EsploraClient::new("ftp://localhost").is_err());
+}
+
+#[test]
+fn unreachable_servers_fail_instead_of_hanging() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+
+    let client = EsploraClient::new(&url).unwrap();
+    assert!(client.tip_height().is_err());
+}
@@ -0,0 +1,140 @@
+//! Unilateral exit costs of tree leaves: ancestor counts, unroll vbytes and
+//! the feerates at which a payout stops being worth claiming, and how leaves
+//! claiming together split the ancestors they share.
+
+mod common;
+
+use common::payouts;
+use scripts::{
+    builder::build_ctv_tree,
+    ctv::{anchor_output, template_tx},
+    exit_cost::{leaf_exits, plan_claims},
+    tree::TreeNode,
+};
+
+/// The nodes from the root down to the output at `path`, leaf node included.
+fn nodes_on_path<'a>(root: &'a TreeNode, path: &[u32]) -> Vec<&'a TreeNode> {
+    let mut nodes = vec![root];
+    for vout in &path[..path.len() - 1] {
+        nodes.push(nodes.last().unwrap().child(*vout).unwrap());
+    }
+    nodes
+}
+
+#[test]
+fn leaves_pay_for_every_ancestor() {
+    let root = build_ctv_tree(&payouts(7), 2, 500).unwrap();
+    let exits = leaf_exits(&root);
+    assert_eq!(exits.len(), 7);
+
+    let mut values: Vec<u64> = exits.iter().map(|exit| exit.value).collect();
+    values.sort();
+    assert_eq!(values, payouts(7).iter().map(|o| o.value.to_sat()).collect::<Vec<_>>());
+
+    for exit in &exits {
+        let nodes = nodes_on_path(&root, &exit.path);
+        assert_eq!(exit.ancestors, nodes.len());
+        assert_eq!(exit.vbytes, nodes.iter().map(|node| node.vsize()).sum::<usize>());
+
+        let leaf_node = nodes.last().unwrap();
+        assert_eq!(exit.outpoint.txid, leaf_node.txid());
+        assert_eq!(leaf_node.tx.output[exit.outpoint.vout as usize].value.to_sat(), exit.value);
+        assert_eq!(*exit.path.last().unwrap(), exit.outpoint.vout);
+    }
+}
+
+#[test]
+fn leaves_under_the_root_need_only_the_root() {
+    let root = build_ctv_tree(&payouts(2), 2, 500).unwrap();
+    let exits = leaf_exits(&root);
+    assert_eq!(exits.len(), 2);
+    for (vout, exit) in exits.iter().enumerate() {
+        assert_eq!(exit.path, vec![vout as u32]);
+        assert_eq!(exit.ancestors, 1);
+        assert_eq!(exit.vbytes, root.vsize());
+    }
+}
+
+#[test]
+fn anchors_are_not_leaves() {
+    let outputs = vec![payouts(1)[0].clone(), anchor_output()];
+    let root = TreeNode::new(template_tx(outputs), 10_830);
+    let exits = leaf_exits(&root);
+    assert_eq!(exits.len(), 1);
+    assert_eq!(exits[0].outpoint.vout, 0);
+}
+
+#[test]
+fn payouts_below_their_unroll_cost_are_uneconomical() {
+    let root = build_ctv_tree(&payouts(4), 2, 500).unwrap();
+    let exit = leaf_exits(&root).remove(0);
+    assert_eq!(exit.value, 10_000);
+
+    let break_even = exit.value / exit.vbytes as u64;
+    assert_eq!(exit.cost(break_even), break_even * exit.vbytes as u64);
+    assert!(exit.is_economical(break_even));
+    assert!(!exit.is_economical(break_even + 1));
+    assert!(exit.is_economical(0));
+
+    // A payout exactly equal to its cost gains nothing from the claim
+    let mut exact = exit.clone();
+    exact.value = exit.cost(3);
+    assert!(!exact.is_economical(3));
+
+    assert_eq!(exit.cost(u64::MAX), u64::MAX);
+}
+
+#[test]
+fn group_claims_broadcast_shared_ancestors_once() {
+    let root = build_ctv_tree(&payouts(8), 2, 500).unwrap();
+    let exits = leaf_exits(&root);
+    // Two leaves under the same parent and one on the other side of the root
+    let claims = vec![exits[0].path.clone(), exits[1].path.clone(), exits[7].path.clone()];
+    let plan = plan_claims(&root, &claims).unwrap();
+
+    let paths: Vec<Vec<u32>> = plan.transactions.iter().map(|(path, _, _)| path.clone()).collect();
+    assert_eq!(paths, vec![vec![], vec![0], vec![0, 0], vec![1], vec![1, 1]]);
+    let claimers: Vec<usize> = plan.transactions.iter().map(|(_, _, claimers)| *claimers).collect();
+    assert_eq!(claimers, vec![3, 2, 2, 1, 1]);
+
+    for share in &plan.shares {
+        assert!(share.vbytes < share.exit.vbytes);
+        assert!(share.savings(10) > 0);
+    }
+    assert!(plan_claims(&root, &[vec![0, 0, 2]]).is_err());
+}
+
+#[test]
+fn claim_shares_add_up_to_the_broadcast_vbytes() {
+    let root = build_ctv_tree(&payouts(9), 3, 500).unwrap();
+    let exits = leaf_exits(&root);
+    for count in 1..=exits.len() {
+        let claims: Vec<Vec<u32>> = exits.iter().take(count).map(|exit| exit.path.clone()).collect();
+        let plan = plan_claims(&root, &claims).unwrap();
+        assert_eq!(plan.shares.iter().map(|share| share.vbytes).sum::<usize>(), plan.vbytes());
+        for fee_rate in [1, 7, 13] {
+            let costs: u64 = plan.shares.iter().map(|share| share.cost(fee_rate)).sum();
+            assert_eq!(costs, plan.vbytes() as u64 * fee_rate);
+        }
+        assert_eq!(plan.transactions[0], (vec![], root.vsize(), count));
+    }
+
+    // Two leaves of one node share every transaction, and the last of them
+    // takes the odd vbytes
+    let claims: Vec<Vec<u32>> = exits.iter().take(2).map(|exit| exit.path.clone()).collect();
+    let plan = plan_claims(&root, &claims).unwrap();
+    let total: usize = plan.transactions.iter().map(|(_, vsize, _)| vsize).sum();
+    let odd: usize = plan.transactions.iter().map(|(_, vsize, claimers)| vsize % claimers).sum();
+    assert_eq!(plan.shares[1].vbytes - plan.shares[0].vbytes, odd);
+    assert_eq!(plan.shares[0].vbytes, (total - odd) / 2);
+}
+
+#[test]
+fn repeated_claims_count_once() {
+    let root = build_ctv_tree(&payouts(4), 2, 500).unwrap();
+    let path = leaf_exits(&root)[0].path.clone();
+    let plan = plan_claims(&root, &[path.clone(), path]).unwrap();
+    assert_eq!(plan.shares.len(), 1);
+    assert_eq!(plan.shares[0].vbytes, plan.vbytes());
+    assert_eq!(plan.shares[0].savings(5), 0);
+}
@@ -0,0 +1,80 @@
+//! DOT and Mermaid output of the tree exporter: labels of single nodes, the
+//! shape of deeper trees, and trees read back from unroll data.
+
+mod common;
+
+use bitcoin::{Amount, OutPoint, TxOut, Txid, hashes::Hash};
+use common::{p2tr, payouts};
+use scripts::{
+    builder::build_ctv_tree,
+    ctv::{anchor_output, template_tx},
+    export::{to_dot, to_mermaid},
+    tree::{load_unroll_data, save_unroll_data, TreeNode},
+};
+
+fn single_node() -> TreeNode {
+    let outputs = vec![TxOut { value: Amount::from_sat(50_000), script_pubkey: p2tr(1) }, anchor_output()];
+    TreeNode::new(template_tx(outputs), 50_830)
+}
+
+#[test]
+fn single_node_dot() {
+    let expected = r#"digraph ctv_tree {
+    rankdir=TB;
+    node [fontname="monospace"];
+    n [shape=box, label="txid 39aca0abd7647d41\nvalue 50830 sat\nfee 500 sat\nvsize 107 vB\nctv 56764b2e29cb6772"];
+    n_0 [shape=ellipse, label="payout\n50000 sat"];
+    n -> n_0 [label="0"];
+    n_1 [shape=ellipse, label="anchor\n330 sat"];
+    n -> n_1 [label="1"];
+}
+"#;
+    assert_eq!(to_dot(&single_node()), expected);
+}
+
+#[test]
+fn single_node_mermaid() {
+    let expected = r#"flowchart TD
+    n["txid 39aca0abd7647d41<br/>value 50830 sat<br/>fee 500 sat<br/>vsize 107 vB<br/>ctv 56764b2e29cb6772"]
+    n_0(["payout<br/>50000 sat"])
+    n -->|0| n_0
+    n_1(["anchor<br/>330 sat"])
+    n -->|1| n_1
+"#;
+    assert_eq!(to_mermaid(&single_node()), expected);
+}
+
+#[test]
+fn every_node_and_leaf_is_drawn_once() {
+    // Seven payouts in pairs: 7 transactions, 7 payouts and 13 edges
+    let root = build_ctv_tree(&payouts(7), 2, 500).unwrap();
+    let transactions = root.transactions().len();
+    assert_eq!(transactions, 7);
+
+    let dot = to_dot(&root);
+    assert_eq!(dot.matches("shape=box").count(), transactions);
+    assert_eq!(dot.matches("shape=ellipse").count(), 7);
+    assert_eq!(dot.matches(" -> ").count(), transactions - 1 + 7);
+    assert!(dot.contains("    n_1_0_1 [shape=ellipse"), "{dot}");
+    for tx in root.transactions() {
+        assert!(dot.contains(&format!("txid {}", &tx.txid().to_string()[..16])));
+    }
+
+    let mermaid = to_mermaid(&root);
+    assert_eq!(mermaid.matches(" -->|").count(), transactions - 1 + 7);
+    assert_eq!(mermaid.matches("([\"payout<br/>").count(), 7);
+    assert!(mermaid.contains("    n -->|1| n_1\n"));
+}
+
+#[test]
+fn unroll_data_exports_like_the_built_tree() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tree.json");
+    let mut root = build_ctv_tree(&payouts(5), 2, 500).unwrap();
+    root.bind(OutPoint { txid: Txid::all_zeros(), vout: 0 });
+
+    save_unroll_data(&path, &root).unwrap();
+    let loaded = load_unroll_data(&path).unwrap();
+    assert_eq!(to_dot(&loaded), to_dot(&root));
+    assert_eq!(to_mermaid(&loaded), to_mermaid(&root));
+}
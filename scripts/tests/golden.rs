@@ -0,0 +1,126 @@
+//! Snapshots of the exact transactions, CTV hashes and addresses the builders
+//! produce from a fixed seed. A diff here means a change to the templates,
+//! anchors or transaction construction; if it is intended, rerun with
+//! `UPDATE_GOLDEN=1` and review the changed files in `tests/golden/`.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+
+use bitcoin::{
+    consensus::encode::serialize_hex,
+    hashes::Hash,
+    key::Secp256k1,
+    taproot::{LeafVersion, TaprootBuilder},
+    Address, Network, OutPoint, Txid, XOnlyPublicKey,
+};
+use scripts::{
+    builder::build_ctv_tree,
+    contract::{build_ctv_contract, build_leaf_outputs, finalize_ctv_spend, CtvContract, Payouts},
+    ctv::build_ctv_script,
+    mock::MockRpc,
+    rpc::NodeRpc,
+    seed::{Seed, SeededRpc},
+    tree::TreeNode,
+};
+
+const SEED: &str = "000102030405060708090a0b0c0d0e0f";
+const COINBASE_VALUE: u64 = 5_000_000_000;
+const NODE_FEE: u64 = 500;
+
+fn coinbase_outpoint() -> OutPoint {
+    OutPoint { txid: Txid::from_byte_array([0x42; 32]), vout: 0 }
+}
+
+fn assert_golden(name: &str, actual: &str) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(format!("{name}.txt"));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("missing {}, run with UPDATE_GOLDEN=1 to create it", path.display()));
+    assert!(expected == actual, "{name} changed:\n--- expected\n{expected}\n--- actual\n{actual}");
+}
+
+fn flat_snapshot(outputs: usize, include_anchor: bool) -> String {
+    let rpc = MockRpc::new();
+    let seed = Seed::from_hex(SEED).unwrap();
+    let node = SeededRpc { rpc: &rpc, seed: Some(&seed) };
+    let secp = Secp256k1::new();
+    let (xonly, _) = XOnlyPublicKey::from_keypair(&seed.keypair().unwrap());
+    // mine_ctv_coinbase draws its mining address before the payouts
+    node.new_address().unwrap();
+
+    let CtvContract { taproot_info, address: ctv_address, mut spend_tx, ctv_script, .. } = build_ctv_contract(
+        &secp,
+        xonly,
+        COINBASE_VALUE,
+        1,
+        include_anchor,
+        &Payouts::NewAddresses(outputs),
+        None,
+        &node,
+    )
+    .unwrap();
+    finalize_ctv_spend(&mut spend_tx, coinbase_outpoint().txid, &taproot_info, &ctv_script).unwrap();
+
+    let mut out = String::new();
+    writeln!(out, "address {ctv_address}").unwrap();
+    writeln!(out, "ctv_script {}", ctv_script.to_hex_string()).unwrap();
+    writeln!(out, "spend {}", serialize_hex(&spend_tx)).unwrap();
+    out
+}
+
+fn tree_snapshot(leaves: usize, radix: usize) -> String {
+    let rpc = MockRpc::new();
+    let seed = Seed::from_hex(SEED).unwrap();
+    let node = SeededRpc { rpc: &rpc, seed: Some(&seed) };
+    let secp = Secp256k1::new();
+    let (xonly, _) = XOnlyPublicKey::from_keypair(&seed.keypair().unwrap());
+
+    let values: Vec<u64> = (1..=leaves as u64).map(|i| i * 10_000_000).collect();
+    let leaf_outputs = build_leaf_outputs(&node, &values).unwrap();
+    let mut root = build_ctv_tree(&leaf_outputs, radix, NODE_FEE).unwrap();
+    let root_script = build_ctv_script(&root.tx.output);
+    let taproot = TaprootBuilder::new()
+        .add_leaf(0, root_script.clone())
+        .unwrap()
+        .finalize(&secp, xonly)
+        .unwrap();
+    let control = taproot.control_block(&(root_script.clone(), LeafVersion::TapScript)).unwrap();
+    root.tx.input[0].witness.push(root_script.to_bytes());
+    root.tx.input[0].witness.push(control.serialize());
+    root.bind(coinbase_outpoint());
+
+    let mut out = String::new();
+    writeln!(out, "address {}", Address::p2tr_tweaked(taproot.output_key(), Network::Regtest)).unwrap();
+    write_node(&mut out, &root, "root");
+    out
+}
+
+fn write_node(out: &mut String, node: &TreeNode, path: &str) {
+    writeln!(out, "{path} ctv_hash {}", hex::encode(node.ctv_hash())).unwrap();
+    writeln!(out, "{path} tx {}", serialize_hex(&node.tx)).unwrap();
+    for (vout, child) in &node.children {
+        write_node(out, child, &format!("{path}.{vout}"));
+    }
+}
+
+#[test]
+fn flat_spends() {
+    for outputs in [1, 3, 10] {
+        for anchor in [false, true] {
+            let name = format!("flat_{outputs}_outputs{}", if anchor { "_anchor" } else { "" });
+            assert_golden(&name, &flat_snapshot(outputs, anchor));
+        }
+    }
+}
+
+#[test]
+fn trees() {
+    for (leaves, radix) in [(2, 2), (4, 2), (7, 2), (9, 3), (16, 4)] {
+        assert_golden(&format!("tree_{leaves}_leaves_radix_{radix}"), &tree_snapshot(leaves, radix));
+    }
+}
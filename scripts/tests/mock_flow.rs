@@ -0,0 +1,441 @@
+//! The mine-and-spend flows of the mining scripts, run against the in-memory chain.
+
+use bitcoin::{
+    absolute::LockTime,
+    key::{Keypair, Secp256k1},
+    transaction::Version,
+    Address, Amount, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness, XOnlyPublicKey,
+};
+use scripts::{
+    builder::build_ctv_tree,
+    contract::{
+        broadcast_by_level, build_ctv_contract, build_leaf_outputs, calculate_fee_with_anchor, finalize_ctv_spend,
+        CtvContract, Payouts,
+    },
+    ctv::{
+        calc_ctv_hash, calc_ctv_hash_with_sequence, get_virtual_bytes, template_hash, timeout_from_script,
+        TimeoutFallback, ANCHOR_VALUE,
+    },
+    exit_cost::leaf_exits,
+    explorer::{explore, OutputKind},
+    error::Error,
+    flow::{layered_leaf_values, mine_coinbase_value, mine_ctv_contract, mine_ctv_tree},
+    mock::MockRpc,
+    rpc::NodeRpc,
+    seed::{Seed, SeededRpc},
+    shares::{ManifestEntry, PayoutManifest, PayoutScheme, Pplns, Share},
+    taproot::tapscript_witness,
+};
+
+fn keypair() -> (Secp256k1<bitcoin::secp256k1::All>, XOnlyPublicKey) {
+    let secp = Secp256k1::new();
+    let keypair = Keypair::from_seckey_slice(&secp, &[7; 32]).unwrap();
+    let (xonly, _) = XOnlyPublicKey::from_keypair(&keypair);
+    (secp, xonly)
+}
+
+#[test]
+fn flat_contract_spend_confirms() {
+    let rpc = MockRpc::new();
+    let (secp, xonly) = keypair();
+    let spend_address = rpc.new_address().unwrap();
+
+    let coinbase_value = mine_coinbase_value(&rpc, &spend_address).unwrap();
+
+    let CtvContract { taproot_info, address: ctv_address, mut spend_tx, ctv_script, .. } = build_ctv_contract(
+        &secp,
+        xonly,
+        coinbase_value,
+        1,
+        true,
+        &Payouts::NewAddresses(5),
+        None,
+        &rpc,
+    )
+    .unwrap();
+
+    mine_ctv_contract(&rpc, &taproot_info, &ctv_address, &mut spend_tx, &ctv_script, &spend_address).unwrap();
+    let txid = rpc.send_raw_transaction(&spend_tx).unwrap();
+    rpc.generate_to_address(1, &spend_address).unwrap();
+
+    assert!(rpc.is_confirmed(&txid));
+    assert_eq!(spend_tx.output.len(), 6);
+    for vout in 0..spend_tx.output.len() as u32 {
+        assert!(rpc.get_tx_out(&OutPoint { txid, vout }).is_some());
+    }
+}
+
+#[test]
+fn taproot_outputs_without_unroll_data_are_unknown_until_spent() {
+    let rpc = MockRpc::new();
+    let (secp, xonly) = keypair();
+    let spend_address = rpc.new_address().unwrap();
+
+    let CtvContract { taproot_info, address: ctv_address, mut spend_tx, ctv_script, .. } = build_ctv_contract(
+        &secp,
+        xonly,
+        5_000_000_000,
+        1,
+        true,
+        &Payouts::NewAddresses(3),
+        None,
+        &rpc,
+    )
+    .unwrap();
+    let coinbase_txid = mine_ctv_contract(&rpc, &taproot_info, &ctv_address, &mut spend_tx, &ctv_script, &spend_address).unwrap();
+    let start = OutPoint { txid: coinbase_txid, vout: 0 };
+    assert_eq!(explore(&rpc, start, None).unwrap().kind, OutputKind::Unknown);
+
+    // The CTV spend reveals the leaf, but its P2TR payouts still hide theirs
+    rpc.send_raw_transaction(&spend_tx).unwrap();
+    let explored = explore(&rpc, start, None).unwrap();
+    assert_eq!(explored.kind, OutputKind::Ctv);
+    let kinds: Vec<OutputKind> = explored.child.unwrap().outputs.iter().map(|output| output.kind).collect();
+    assert_eq!(kinds, [vec![OutputKind::Unknown; 3], vec![OutputKind::Anchor]].concat());
+}
+
+#[test]
+fn scheme_payouts_pay_exactly_their_fee_rate() {
+    let rpc = MockRpc::new();
+    let (secp, xonly) = keypair();
+    let miner = rpc.new_address().unwrap();
+
+    // Legacy and taproot miners have outputs of different sizes
+    let pubkey = bitcoin::PublicKey::new(Keypair::from_seckey_slice(&secp, &[9; 32]).unwrap().public_key());
+    let legacy = Address::p2pkh(&pubkey, Network::Regtest);
+    let shares: Vec<Share> = [legacy.to_string(), miner.to_string(), legacy.to_string()]
+        .into_iter()
+        .enumerate()
+        .map(|(i, address)| Share { address, difficulty: 100, timestamp: i as u64 })
+        .collect();
+
+    let fee_rate = 3;
+    let CtvContract { taproot_info, address: ctv_address, mut spend_tx, ctv_script, .. } = build_ctv_contract(
+        &secp,
+        xonly,
+        5_000_000_000,
+        fee_rate,
+        true,
+        &Payouts::Scheme(Box::new(Pplns { window: 300 }), shares),
+        None,
+        &rpc,
+    )
+    .unwrap();
+    assert_eq!(spend_tx.output[0].script_pubkey, legacy.script_pubkey());
+
+    mine_ctv_contract(&rpc, &taproot_info, &ctv_address, &mut spend_tx, &ctv_script, &miner).unwrap();
+    let paid: u64 = spend_tx.output.iter().map(|o| o.value.to_sat()).sum();
+    assert_eq!(5_000_000_000 - paid, get_virtual_bytes(&spend_tx) as u64 * fee_rate);
+}
+
+/// Pays one miner up to `cap` and keeps the rest for the pool.
+struct Capped {
+    address: String,
+    cap: u64,
+}
+
+impl PayoutScheme for Capped {
+    fn name(&self) -> &'static str {
+        "capped"
+    }
+
+    fn manifest(&self, _: &[Share], reward: u64) -> Result<PayoutManifest, Error> {
+        let value = reward.min(self.cap);
+        let entries = vec![ManifestEntry { address: self.address.clone(), weight: 1, value }];
+        Ok(PayoutManifest { entries, retained: reward - value })
+    }
+}
+
+#[test]
+fn scheme_fees_are_sized_from_the_final_split() {
+    let rpc = MockRpc::new();
+    let (secp, xonly) = keypair();
+    let miner = rpc.new_address().unwrap();
+    let fee_rate = 10;
+
+    // Before the fee comes off the pool keeps the excess over the cap, after
+    // it there is none, so a fee sized from the first split pays for a pool
+    // output the spend never has
+    let fee = calculate_fee_with_anchor(&secp, xonly, fee_rate, &[miner.script_pubkey()], true, None).unwrap();
+    let cap = 5_000_000_000 - ANCHOR_VALUE - fee;
+    let scheme = Capped { address: miner.to_string(), cap };
+    let CtvContract { taproot_info, address: ctv_address, mut spend_tx, ctv_script, manifest } = build_ctv_contract(
+        &secp,
+        xonly,
+        5_000_000_000,
+        fee_rate,
+        true,
+        &Payouts::Scheme(Box::new(scheme), vec![]),
+        None,
+        &rpc,
+    )
+    .unwrap();
+    assert_eq!(manifest.unwrap().retained, 0);
+    assert_eq!(spend_tx.output.len(), 2);
+    assert_eq!(spend_tx.output[0].value.to_sat(), cap);
+
+    mine_ctv_contract(&rpc, &taproot_info, &ctv_address, &mut spend_tx, &ctv_script, &miner).unwrap();
+    let paid: u64 = spend_tx.output.iter().map(|o| o.value.to_sat()).sum();
+    assert_eq!(5_000_000_000 - paid, get_virtual_bytes(&spend_tx) as u64 * fee_rate);
+}
+
+#[test]
+fn tampered_outputs_fail_the_template_hash() {
+    let rpc = MockRpc::new();
+    let (secp, xonly) = keypair();
+    let spend_address = rpc.new_address().unwrap();
+
+    let CtvContract { taproot_info, address: ctv_address, mut spend_tx, ctv_script, .. } = build_ctv_contract(
+        &secp,
+        xonly,
+        5_000_000_000,
+        1,
+        false,
+        &Payouts::NewAddresses(2),
+        None,
+        &rpc,
+    )
+    .unwrap();
+
+    mine_ctv_contract(&rpc, &taproot_info, &ctv_address, &mut spend_tx, &ctv_script, &spend_address).unwrap();
+    spend_tx.output[0].value -= Amount::from_sat(1);
+    spend_tx.output[1].value += Amount::from_sat(1);
+
+    let err = rpc.send_raw_transaction(&spend_tx).unwrap_err();
+    assert!(matches!(&err, Error::Node(msg) if msg.contains("CTV template hash mismatch")), "{err}");
+}
+
+#[test]
+fn immature_coinbase_cannot_be_spent() {
+    let rpc = MockRpc::new();
+    let (secp, xonly) = keypair();
+    let spend_address = rpc.new_address().unwrap();
+
+    let CtvContract { taproot_info, address: ctv_address, mut spend_tx, ctv_script, .. } = build_ctv_contract(
+        &secp,
+        xonly,
+        5_000_000_000,
+        1,
+        true,
+        &Payouts::NewAddresses(3),
+        None,
+        &rpc,
+    )
+    .unwrap();
+
+    let block = rpc.generate_to_address(1, &ctv_address).unwrap()[0];
+    let coinbase_txid = rpc.get_block(&block).unwrap().txdata[0].txid();
+    rpc.generate_to_address(98, &spend_address).unwrap();
+
+    finalize_ctv_spend(&mut spend_tx, coinbase_txid, &taproot_info, &ctv_script).unwrap();
+    let err = rpc.send_raw_transaction(&spend_tx).unwrap_err();
+    assert!(matches!(&err, Error::Node(msg) if msg.contains("premature-spend-of-coinbase")), "{err}");
+
+    rpc.generate_to_address(1, &spend_address).unwrap();
+    rpc.send_raw_transaction(&spend_tx).unwrap();
+}
+
+#[test]
+fn recovery_template_spends_the_coinbase_through_the_timeout_leaf() {
+    let rpc = MockRpc::new();
+    let (secp, xonly) = keypair();
+    let spend_address = rpc.new_address().unwrap();
+
+    let fallback = TimeoutFallback {
+        blocks: 144,
+        recovery: vec![TxOut {
+            value: Amount::from_sat(4_999_999_000),
+            script_pubkey: rpc.new_address().unwrap().script_pubkey(),
+        }],
+    };
+    let CtvContract { taproot_info, address: ctv_address, mut spend_tx, ctv_script, .. } = build_ctv_contract(
+        &secp,
+        xonly,
+        5_000_000_000,
+        1,
+        true,
+        &Payouts::NewAddresses(2),
+        Some(&fallback),
+        &rpc,
+    )
+    .unwrap();
+    let coinbase_txid = mine_ctv_contract(
+        &rpc,
+        &taproot_info,
+        &ctv_address,
+        &mut spend_tx,
+        &ctv_script,
+        &spend_address,
+    )
+    .unwrap();
+
+    // The leaf commits the sequence it checks, so the hash is not the plain one
+    let (sequence, hash) = timeout_from_script(&fallback.script()).unwrap();
+    assert_eq!(sequence, Sequence::from_height(144));
+    assert_eq!(hash, calc_ctv_hash_with_sequence(&fallback.recovery, sequence));
+    assert_ne!(hash, calc_ctv_hash(&fallback.recovery));
+    assert_eq!(template_hash(&fallback.recovery_tx(), 0), hash);
+
+    let mut recovery_tx = fallback.recovery_tx();
+    recovery_tx.input[0].previous_output = OutPoint {
+        txid: coinbase_txid,
+        vout: 0,
+    };
+    recovery_tx.input[0].witness = tapscript_witness(&[], &fallback.script(), &taproot_info).unwrap();
+
+    let mut tampered = recovery_tx.clone();
+    tampered.input[0].sequence = Sequence::ENABLE_RBF_NO_LOCKTIME;
+    let err = rpc.send_raw_transaction(&tampered).unwrap_err();
+    assert!(
+        matches!(&err, Error::Node(msg) if msg.contains("CTV template hash mismatch")),
+        "{err}"
+    );
+
+    let txid = rpc.send_raw_transaction(&recovery_tx).unwrap();
+    rpc.generate_to_address(1, &spend_address).unwrap();
+    assert!(rpc.is_confirmed(&txid));
+}
+
+#[test]
+fn layered_tree_unrolls_level_by_level() {
+    let rpc = MockRpc::new();
+    let (secp, xonly) = keypair();
+    let miner = rpc.new_address().unwrap();
+
+    let leaf_outputs = build_leaf_outputs(&rpc, &[400_000_000; 7]).unwrap();
+    let mut root = build_ctv_tree(&leaf_outputs, 2, 500).unwrap();
+    mine_ctv_tree(&rpc, &secp, xonly, &mut root, &miner).unwrap();
+
+    let txids = broadcast_by_level(&rpc, &root, &miner).unwrap();
+    assert!(txids.iter().all(|txid| rpc.is_confirmed(txid)));
+    assert!(rpc.mempool().is_empty());
+
+    let exits = leaf_exits(&root);
+    assert_eq!(exits.len(), leaf_outputs.len());
+    for exit in exits {
+        assert_eq!(rpc.get_tx_out(&exit.outpoint).map(|o| o.value.to_sat()), Some(exit.value));
+    }
+}
+
+#[test]
+fn default_layered_leaves_spend_the_coinbase() {
+    let rpc = MockRpc::new();
+    let (secp, xonly) = keypair();
+    let miner = rpc.new_address().unwrap();
+
+    let coinbase_value = mine_coinbase_value(&rpc, &miner).unwrap();
+    let leaf_outputs = build_leaf_outputs(&rpc, &layered_leaf_values(coinbase_value, 500).unwrap()).unwrap();
+    let mut root = build_ctv_tree(&leaf_outputs, 2, 500).unwrap();
+    // The last leaf takes the odd sats, so the root spends the whole coinbase
+    assert_eq!(root.input_value, coinbase_value);
+    mine_ctv_tree(&rpc, &secp, xonly, &mut root, &miner).unwrap();
+
+    let txids = broadcast_by_level(&rpc, &root, &miner).unwrap();
+    assert_eq!(txids.len(), 3);
+    assert!(txids.iter().all(|txid| rpc.is_confirmed(txid)));
+}
+
+#[test]
+fn default_layered_leaves_need_the_node_fees() {
+    assert_eq!(layered_leaf_values(2_003, 500).unwrap(), vec![125, 125, 125, 128]);
+    assert_eq!(layered_leaf_values(1_500, 500).unwrap(), vec![0; 4]);
+    assert!(matches!(
+        layered_leaf_values(1_499, 500),
+        Err(Error::InsufficientValue { needed: 1_500, available: 1_499 })
+    ));
+    assert!(layered_leaf_values(u64::MAX - 1, u64::MAX / 2).is_err());
+}
+
+/// Runs the flat flow with keys and addresses from `seed`, returning the spend.
+fn seeded_flat_spend(seed: &str) -> bitcoin::Transaction {
+    let rpc = MockRpc::new();
+    let seed = Seed::from_hex(seed).unwrap();
+    let node = SeededRpc { rpc: &rpc, seed: Some(&seed) };
+    let secp = Secp256k1::new();
+    let (xonly, _) = XOnlyPublicKey::from_keypair(&seed.keypair().unwrap());
+    let spend_address = node.new_address().unwrap();
+
+    let CtvContract { taproot_info, address: ctv_address, mut spend_tx, ctv_script, .. } = build_ctv_contract(
+        &secp,
+        xonly,
+        5_000_000_000,
+        1,
+        true,
+        &Payouts::NewAddresses(3),
+        None,
+        &node,
+    )
+    .unwrap();
+
+    mine_ctv_contract(&node, &taproot_info, &ctv_address, &mut spend_tx, &ctv_script, &spend_address).unwrap();
+    node.send_raw_transaction(&spend_tx).unwrap();
+    spend_tx
+}
+
+#[test]
+fn same_seed_gives_same_spend() {
+    let seed = "000102030405060708090a0b0c0d0e0f";
+    assert_eq!(seeded_flat_spend(seed), seeded_flat_spend(seed));
+    assert_ne!(seeded_flat_spend(seed).output, seeded_flat_spend("ff".repeat(16).as_str()).output);
+}
+
+#[test]
+fn contracts_are_encoded_for_the_node_network() {
+    let rpc = MockRpc::with_network(Network::Signet);
+    let seed = Seed::from_hex("000102030405060708090a0b0c0d0e0f").unwrap();
+    let node = SeededRpc { rpc: &rpc, seed: Some(&seed) };
+    let (secp, xonly) = keypair();
+    let spend_address = node.new_address().unwrap();
+
+    let CtvContract { address: ctv_address, spend_tx, .. } = build_ctv_contract(
+        &secp,
+        xonly,
+        5_000_000_000,
+        1,
+        true,
+        &Payouts::NewAddresses(3),
+        None,
+        &node,
+    )
+    .unwrap();
+
+    assert!(ctv_address.to_string().starts_with("tb1p"), "{ctv_address}");
+    assert_eq!(*spend_address.network(), Network::Signet);
+    assert_eq!(spend_tx.output.len(), 4);
+}
+
+#[test]
+fn only_replaceable_conflicts_are_replaced() {
+    let rpc = MockRpc::new();
+    let miner = rpc.new_address().unwrap();
+    // Witness script spends are not executed by the mock
+    let anyone = Address::p2wsh(&ScriptBuf::from_bytes(vec![0x51]), Network::Regtest);
+    let coinbases: Vec<_> = rpc
+        .generate_to_address(2, &anyone)
+        .unwrap()
+        .iter()
+        .map(|block| rpc.get_block(block).unwrap().txdata[0].clone())
+        .collect();
+    rpc.generate_to_address(100, &miner).unwrap();
+
+    let spend = |coinbase: &Transaction, sequence: Sequence, fee: u64| Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint { txid: coinbase.txid(), vout: 0 },
+            script_sig: ScriptBuf::new(),
+            sequence,
+            witness: Witness::from_slice(&[vec![0x51]]),
+        }],
+        output: vec![TxOut { value: coinbase.output[0].value - Amount::from_sat(fee), script_pubkey: miner.script_pubkey() }],
+    };
+
+    rpc.send_raw_transaction(&spend(&coinbases[0], Sequence::MAX, 1_000)).unwrap();
+    let err = rpc.send_raw_transaction(&spend(&coinbases[0], Sequence::MAX, 2_000)).unwrap_err();
+    assert!(matches!(&err, Error::Node(msg) if msg.contains("txn-mempool-conflict")), "{err}");
+
+    let signaling: Txid = rpc.send_raw_transaction(&spend(&coinbases[1], Sequence::ENABLE_RBF_NO_LOCKTIME, 1_000)).unwrap();
+    let replacement = rpc.send_raw_transaction(&spend(&coinbases[1], Sequence::MAX, 2_000)).unwrap();
+    assert!(!rpc.mempool().contains(&signaling));
+    assert!(rpc.mempool().contains(&replacement));
+}
@@ -0,0 +1,346 @@
+//! The MuSig2 implementation against the BIP-327 KeyAgg, NonceAgg, Sign,
+//! tweak and PartialSigAgg test vectors, and MuSig2 trees whose leaves share
+//! an owner.
+
+use bitcoin::{
+    key::{Keypair, Secp256k1},
+    secp256k1::{Message, PublicKey, Scalar},
+    Amount, OutPoint, ScriptBuf, TxOut, Txid,
+    hashes::Hash,
+};
+use scripts::{
+    collapse::{build_collapse_tx, sign_collapse_tx},
+    error::Error,
+    musig::{aggregate_nonces, aggregate_partial_sigs, partial_sign, sign_with_all, AggNonce, KeyAggContext, PubNonce, SecNonce},
+    musig_tree::{build_musig_tree, LeafPayout},
+    taproot::key_spend_sighash,
+};
+
+fn bytes(hex: &str) -> Vec<u8> {
+    hex::decode(hex).unwrap()
+}
+
+fn pubkey(hex: &str) -> PublicKey {
+    PublicKey::from_slice(&bytes(hex)).unwrap()
+}
+
+fn scalar(hex: &str) -> Scalar {
+    Scalar::from_be_bytes(bytes(hex).try_into().unwrap()).unwrap()
+}
+
+fn msg(hex: &str) -> [u8; 32] {
+    bytes(hex).try_into().unwrap()
+}
+
+fn keys(all: &[&str], indices: &[usize]) -> Vec<PublicKey> {
+    indices.iter().map(|i| pubkey(all[*i])).collect()
+}
+
+const KEY_AGG_PUBKEYS: [&str; 7] = [
+    "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9",
+    "03dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+    "023590a94e768f8e1815c2f24b4d80a8e3149316c3518ce7b7ad338368d038ca66",
+    "020000000000000000000000000000000000000000000000000000000000000005",
+    "02fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc30",
+    "04f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9",
+    "03935f972da013f80ae011890fa89b67a27b7be6ccb24d3274d18b2d4067f261a9",
+];
+
+#[test]
+fn key_agg_vectors() {
+    let secp = Secp256k1::new();
+    let cases: [(&[usize], &str); 4] = [
+        (&[0, 1, 2], "90539eede565f5d054f32cc0c220126889ed1e5d193baf15aef344fe59d4610c"),
+        (&[2, 1, 0], "6204de8b083426dc6eaf9502d27024d53fc826bf7d2012148a0575435df54b2b"),
+        (&[0, 0, 0], "b436e3bad62b8cd409969a224731c193d051162d8c5ae8b109306127da3aa935"),
+        (&[0, 0, 1, 1], "69bc22bfa5d106306e48a20679de1d7389386124d07571d0d872686028c26a3e"),
+    ];
+    for (indices, expected) in cases {
+        let ctx = KeyAggContext::new(&secp, &keys(&KEY_AGG_PUBKEYS, indices)).unwrap();
+        assert_eq!(hex::encode(ctx.aggregate_xonly().serialize()), expected, "keys {indices:?}");
+    }
+
+    // Invalid public keys never make it into a key set
+    for invalid in [3, 4, 5] {
+        assert!(PublicKey::from_slice(&bytes(KEY_AGG_PUBKEYS[invalid])).is_err());
+    }
+
+    // A tweak equal to the group order is out of range
+    let order: [u8; 32] = bytes("fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141").try_into().unwrap();
+    assert!(Scalar::from_be_bytes(order).is_err());
+
+    // A tweak that cancels the aggregate key out leaves the point at infinity
+    let ctx = KeyAggContext::new(&secp, &keys(&KEY_AGG_PUBKEYS, &[6])).unwrap();
+    let tweak = scalar("252e4bd67410a76cdf933d30eaa1608214037f1b105a013eccd3c5c184a6110b");
+    assert!(ctx.with_tweak(&secp, tweak, false).is_err());
+}
+
+#[test]
+fn nonce_agg_vectors() {
+    let pnonces = [
+        "020151c80f435648df67a22b749cd798ce54e0321d034b92b709b567d60a42e66603ba47fbc1834437b3212e89a84d8425e7bf12e0245d98262268ebdcb385d50641",
+        "03ff406ffd8adb9cd29877e4985014f66a59f6cd01c0e88caa8e5f3166b1f676a60248c264cdd57d3c24d79990b0f865674eb62a0f9018277a95011b41bfc193b833",
+        "020151c80f435648df67a22b749cd798ce54e0321d034b92b709b567d60a42e6660279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        "03ff406ffd8adb9cd29877e4985014f66a59f6cd01c0e88caa8e5f3166b1f676a60379be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        "04ff406ffd8adb9cd29877e4985014f66a59f6cd01c0e88caa8e5f3166b1f676a60248c264cdd57d3c24d79990b0f865674eb62a0f9018277a95011b41bfc193b833",
+        "03ff406ffd8adb9cd29877e4985014f66a59f6cd01c0e88caa8e5f3166b1f676a60248c264cdd57d3c24d79990b0f865674eb62a0f9018277a95011b41bfc193b831",
+        "03ff406ffd8adb9cd29877e4985014f66a59f6cd01c0e88caa8e5f3166b1f676a602fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc30",
+    ];
+    let cases = [
+        ([0, 1], "035fe1873b4f2967f52fea4a06ad5a8eccbe9d0fd73068012c894e2e87ccb5804b024725377345bde0e9c33af3c43c0a29a9249f2f2956fa8cfeb55c8573d0262dc8"),
+        // The second halves cancel out to the point at infinity
+        ([2, 3], "035fe1873b4f2967f52fea4a06ad5a8eccbe9d0fd73068012c894e2e87ccb5804b000000000000000000000000000000000000000000000000000000000000000000"),
+    ];
+    for (indices, expected) in cases {
+        let nonces: Vec<PubNonce> = indices.iter().map(|i| PubNonce::from_slice(&bytes(pnonces[*i])).unwrap()).collect();
+        let agg_nonce = aggregate_nonces(&nonces).unwrap();
+        assert_eq!(hex::encode(agg_nonce.serialize()), expected);
+        assert_eq!(AggNonce::from_slice(&bytes(expected)).unwrap(), agg_nonce);
+    }
+
+    // A wrong prefix, an x off the curve and an x beyond the field size
+    for invalid in [4, 5, 6] {
+        assert!(PubNonce::from_slice(&bytes(pnonces[invalid])).is_err());
+    }
+}
+
+const SIGN_SECRET_KEY: &str = "7fb9e0e687ada1eebf7ecfe2f21e73ebdb51a7d450948dfe8d76d7f2d1007671";
+const SIGN_SECNONCE: &str = "508b81a611f100a6b2b6b29656590898af488bcf2e1f55cf22e5cfb84421fe61fa27fd49b1d50085b481285e1ca205d55c82cc1b31ff5cd54a489829355901f7";
+const SIGN_MSG: &str = "f95466d086770e689964664219266fe5ed215c92ae20bab5c9d79addddf3c0cf";
+
+#[test]
+fn sign_vectors() {
+    let secp = Secp256k1::new();
+    let keypair = Keypair::from_seckey_slice(&secp, &bytes(SIGN_SECRET_KEY)).unwrap();
+    let pubkeys = [
+        "03935f972da013f80ae011890fa89b67a27b7be6ccb24d3274d18b2d4067f261a9",
+        "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9",
+        "02dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba661",
+        "020000000000000000000000000000000000000000000000000000000000000007",
+    ];
+    let aggnonces = [
+        "028465fcf0bbdbcf443aabcce533d42b4b5a10966ac09a49655e8c42daab8fcd61037496a3cc86926d452cafcfd55d25972ca1675d549310de296bff42f72eeea8c9",
+        "000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
+        "048465fcf0bbdbcf443aabcce533d42b4b5a10966ac09a49655e8c42daab8fcd61037496a3cc86926d452cafcfd55d25972ca1675d549310de296bff42f72eeea8c9",
+        "028465fcf0bbdbcf443aabcce533d42b4b5a10966ac09a49655e8c42daab8fcd61020000000000000000000000000000000000000000000000000000000000000009",
+        "028465fcf0bbdbcf443aabcce533d42b4b5a10966ac09a49655e8c42daab8fcd6102fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc30",
+    ];
+    assert_eq!(keypair.public_key(), pubkey(pubkeys[0]));
+
+    let cases: [(&[usize], usize, &str); 4] = [
+        (&[0, 1, 2], 0, "012abbcb52b3016ac03ad82395a1a415c48b93def78718e62a7a90052fe224fb"),
+        (&[1, 0, 2], 0, "9ff2f7aaa856150cc8819254218d3adeeb0535269051897724f9db3789513a52"),
+        (&[1, 2, 0], 0, "fa23c359f6fac4e7796bb93bc9f0532a95468c539ba20ff86d7c76ed92227900"),
+        // Both halves of the aggregate nonce at infinity, so R is G
+        (&[0, 1], 1, "ae386064b26105404798f75de2eb9af5eda5387b064b83d049cb7c5e08879531"),
+    ];
+    for (indices, aggnonce, expected) in cases {
+        let ctx = KeyAggContext::new(&secp, &keys(&pubkeys, indices)).unwrap();
+        let agg_nonce = AggNonce::from_slice(&bytes(aggnonces[aggnonce])).unwrap();
+        let sec_nonce = SecNonce::from_slice(&bytes(SIGN_SECNONCE)).unwrap();
+        let sig = partial_sign(&secp, &ctx, sec_nonce, &keypair, &agg_nonce, &msg(SIGN_MSG)).unwrap();
+        assert_eq!(hex::encode(sig.to_be_bytes()), expected, "keys {indices:?}");
+    }
+
+    // The signer's key is not in the set
+    let ctx = KeyAggContext::new(&secp, &keys(&pubkeys, &[1, 2])).unwrap();
+    let agg_nonce = AggNonce::from_slice(&bytes(aggnonces[0])).unwrap();
+    let sec_nonce = SecNonce::from_slice(&bytes(SIGN_SECNONCE)).unwrap();
+    assert!(partial_sign(&secp, &ctx, sec_nonce, &keypair, &agg_nonce, &msg(SIGN_MSG)).is_err());
+
+    // An invalid key, invalid aggregate nonces and an all-zero secret nonce
+    assert!(PublicKey::from_slice(&bytes(pubkeys[3])).is_err());
+    for invalid in [2, 3, 4] {
+        assert!(AggNonce::from_slice(&bytes(aggnonces[invalid])).is_err());
+    }
+    assert!(SecNonce::from_slice(&[0; 64]).is_err());
+}
+
+#[test]
+fn tweak_vectors() {
+    let secp = Secp256k1::new();
+    let keypair = Keypair::from_seckey_slice(&secp, &bytes(SIGN_SECRET_KEY)).unwrap();
+    let pubkeys = [
+        "03935f972da013f80ae011890fa89b67a27b7be6ccb24d3274d18b2d4067f261a9",
+        "02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9",
+        "02dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659",
+    ];
+    let aggnonce = "028465fcf0bbdbcf443aabcce533d42b4b5a10966ac09a49655e8c42daab8fcd61037496a3cc86926d452cafcfd55d25972ca1675d549310de296bff42f72eeea8c9";
+    let tweaks = [
+        "e8f791ff9225a2af0102afff4a9a723d9612a682a25ebe79802b263cdfcd83bb",
+        "ae2ea797cc0fe72ac5b97b97f3c6957d7e4199a167a58eb08bcaffda70ac0455",
+        "f52ecbc565b3d8bea2dfd5b75a4f457e54369809322e4120831626f290fa87e0",
+        "1969ad73cc177fa0b4fced6df1f7bf9907e665fde9ba196a74fed0a3cf5aef9d",
+    ];
+    let cases: [(&[(usize, bool)], &str); 5] = [
+        (&[(0, true)], "e28a5c66e61e178c2ba19db77b6cf9f7e2f0f56c17918cd13135e60cc848fe91"),
+        (&[(0, false)], "38b0767798252f21bf5702c48028b095428320f73a4b14db1e25de58543d2d2d"),
+        (&[(0, false), (1, true)], "408a0a21c4a0f5dacaf9646ad6eb6fecd7f7a11f03ed1f48dfff2185bc2c2408"),
+        (&[(0, false), (1, false), (2, true), (3, true)], "45abd206e61e3df2ec9e264a6fec8292141a633c28586388235541f9ade75435"),
+        (&[(0, true), (1, false), (2, true), (3, false)], "b255fdcac27b40c7ce7848e2d3b7bf5ea0ed756da81565ac804ccca3e1d5d239"),
+    ];
+    for (applied, expected) in cases {
+        let mut ctx = KeyAggContext::new(&secp, &keys(&pubkeys, &[1, 2, 0])).unwrap();
+        for (tweak, xonly) in applied {
+            ctx = ctx.with_tweak(&secp, scalar(tweaks[*tweak]), *xonly).unwrap();
+        }
+        let agg_nonce = AggNonce::from_slice(&bytes(aggnonce)).unwrap();
+        let sec_nonce = SecNonce::from_slice(&bytes(SIGN_SECNONCE)).unwrap();
+        let sig = partial_sign(&secp, &ctx, sec_nonce, &keypair, &agg_nonce, &msg(SIGN_MSG)).unwrap();
+        assert_eq!(hex::encode(sig.to_be_bytes()), expected, "tweaks {applied:?}");
+    }
+}
+
+/// Key indices, applied tweaks, aggregate nonce, partial signature indices and
+/// the expected signature of a PartialSigAgg vector.
+type SigAggCase = (&'static [usize], &'static [(usize, bool)], &'static str, [usize; 2], &'static str);
+
+#[test]
+fn partial_sig_agg_vectors() {
+    let secp = Secp256k1::new();
+    let pubkeys = [
+        "03935f972da013f80ae011890fa89b67a27b7be6ccb24d3274d18b2d4067f261a9",
+        "02d2dc6f5df7c56acf38c7fa0ae7a759ae30e19b37359dfde015872324c7ef6e05",
+        "03c7fb101d97ff930acd0c6760852ef64e69083de0b06ac6335724754bb4b0522c",
+        "02352433b21e7e05d3b452b81cae566e06d2e003ece16d1074aaba4289e0e3d581",
+    ];
+    let tweaks = [
+        "b511da492182a91b0ffb9a98020d55f260ae86d7ecbd0399c7383d59a5f2af7c",
+        "a815fe049ee3c5aab66310477fbc8bcccac2f3395f59f921c364acd78a2f48dc",
+        "75448a87274b056468b977be06eb1e9f657577b7320b0a3376ea51fd420d18a8",
+    ];
+    let psigs = [
+        "b15d2cd3c3d22b04dae438ce653f6b4ecf042f42cfded7c41b64aaf9b4af53fb",
+        "6193d6ac61b354e9105bbdc8937a3454a6d705b6d57322a5a472a02ce99fcb64",
+        "9a87d3b79ec67228cb97878b76049b15dbd05b8158d17b5b9114d3c226887505",
+        "66f82ea90923689b855d36c6b7e032fb9970301481b99e01cdb4d6ac7c347a15",
+        "4f5aee41510848a6447dcd1bbc78457ef69024944c87f40250d3ef2c25d33efe",
+        "ddef427bbb847cc027beff4edb01038148917832253ebc355fc33f4a8e2fcce4",
+        "97b890a26c981da8102d3bc294159d171d72810fdf7c6a691def02f0f7af3fdc",
+        "53fa9e08ba5243cbcb0d797c5ee83bc6728e539eb76c2d0bf0f971ee4e909971",
+        "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141",
+    ];
+    let message = msg("599c67ea410d005b9da90817cf03ed3b1c868e4da4edf00a5880b0082c237869");
+    let cases: [SigAggCase; 4] = [
+        (
+            &[0, 1],
+            &[],
+            "0341432722c5cd0268d829c702cf0d1cbce57033eed201fd335191385227c3210c03d377f2d258b64aadc0e16f26462323d701d286046a2ea93365656afd9875982b",
+            [0, 1],
+            "041da22223ce65c92c9a0d6c2cac828aaf1eee56304fec371ddf91ebb2b9ef0912f1038025857fedeb3ff696f8b99fa4bb2c5812f6095a2e0004ec99ce18de1e",
+        ),
+        (
+            &[0, 2],
+            &[],
+            "0224afd36c902084058b51b5d36676bba4dc97c775873768e58822f87fe437d792028cb15929099eee2f5dae404cd39357591ba32e9af4e162b8d3e7cb5efe31cb20",
+            [2, 3],
+            "1069b67ec3d2f3c7c08291accb17a9c9b8f2819a52eb5df8726e17e7d6b52e9f01800260a7e9dac450f4be522de4ce12ba91aeaf2b4279219ef74be1d286add9",
+        ),
+        (
+            &[0, 2],
+            &[(0, false)],
+            "0208c5c438c710f4f96a61e9ff3c37758814b8c3ae12bfea0ed2c87ff6954ff186020b1816ea104b4fca2d304d733e0e19cead51303ff6420bfd222335caa402916d",
+            [4, 5],
+            "5c558e1dcade86da0b2f02626a512e30a22cf5255caea7ee32c38e9a71a0e9148ba6c0e6ec7683b64220f0298696f1b878cd47b107b81f7188812d593971e0cc",
+        ),
+        (
+            &[0, 3],
+            &[(0, true), (1, false), (2, true)],
+            "02b5ad07afcd99b6d92cb433fbd2a28fdeb98eae2eb09b6014ef0f8197cd58403302e8616910f9293cf692c49f351db86b25e352901f0e237bafda11f1c1cef29ffd",
+            [6, 7],
+            "839b08820b681dba8daf4cc7b104e8f2638f9388f8d7a555dc17b6e6971d7426ce07bf6ab01f1db50e4e33719295f4094572b79868e440fb3defd3fac1db589e",
+        ),
+    ];
+    for (indices, applied, aggnonce, signed, expected) in cases {
+        let mut ctx = KeyAggContext::new(&secp, &keys(&pubkeys, indices)).unwrap();
+        for (tweak, xonly) in applied {
+            ctx = ctx.with_tweak(&secp, scalar(tweaks[*tweak]), *xonly).unwrap();
+        }
+        let agg_nonce = AggNonce::from_slice(&bytes(aggnonce)).unwrap();
+        let partial_sigs: Vec<Scalar> = signed.iter().map(|i| scalar(psigs[*i])).collect();
+        let sig = aggregate_partial_sigs(&secp, &ctx, &agg_nonce, &message, &partial_sigs).unwrap();
+        assert_eq!(hex::encode(sig.as_ref()), expected, "keys {indices:?}");
+        secp.verify_schnorr(&sig, &Message::from_digest(message), &ctx.aggregate_xonly()).unwrap();
+    }
+
+    // A partial signature equal to the group order is out of range
+    assert!(Scalar::from_be_bytes(bytes(psigs[8]).try_into().unwrap()).is_err());
+}
+
+fn owner_payout(secp: &Secp256k1<bitcoin::secp256k1::All>, owner: &Keypair, value: u64) -> LeafPayout {
+    LeafPayout {
+        owner: owner.public_key(),
+        output: TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey: ScriptBuf::new_p2tr(secp, owner.x_only_public_key().0, None),
+        },
+    }
+}
+
+#[test]
+fn owners_of_several_leaves_sign_once() {
+    let secp = Secp256k1::new();
+    let owners: Vec<Keypair> = (1..=3u8).map(|i| Keypair::from_seckey_slice(&secp, &[i; 32]).unwrap()).collect();
+    // The first owner holds two leaves on each side of the root
+    let payouts = vec![
+        owner_payout(&secp, &owners[0], 100_000),
+        owner_payout(&secp, &owners[1], 200_000),
+        owner_payout(&secp, &owners[0], 300_000),
+        owner_payout(&secp, &owners[2], 400_000),
+    ];
+    let tree = build_musig_tree(&secp, &payouts, 2, 500, 1_002_000).unwrap();
+    let root = tree.lock(&[]).unwrap();
+    let keys: Vec<PublicKey> = owners.iter().map(Keypair::public_key).collect();
+    assert_eq!(root.owners, keys);
+
+    let ctx = root.signing_context(&secp).unwrap();
+    assert_eq!(ctx.aggregate_xonly(), root.spend_info.output_key().to_inner());
+    let signers: Vec<&Keypair> = owners.iter().collect();
+    let digest = [0x42; 32];
+    let sig = sign_with_all(&secp, &ctx, &signers, &digest).unwrap();
+    secp.verify_schnorr(&sig, &Message::from_digest(digest), &ctx.aggregate_xonly()).unwrap();
+
+    // Collapsing the left node pays each owner under it once
+    let prevout = OutPoint { txid: Txid::all_zeros(), vout: 0 };
+    let left = tree.lock(&[0]).unwrap();
+    let mut tx = build_collapse_tx(&tree, &[0], prevout, &payouts, &[], 1).unwrap();
+    assert_eq!(tx.output.len(), 2);
+    let spent = tree.root.tx.output[0].clone();
+    sign_collapse_tx(&secp, left, &mut tx, spent.clone(), &owners).unwrap();
+    let sighash = key_spend_sighash(&tx, 0, &[spent]).unwrap();
+    let sig = bitcoin::secp256k1::schnorr::Signature::from_slice(&tx.input[0].witness[0]).unwrap();
+    secp.verify_schnorr(&sig, &Message::from_digest(sighash), &left.spend_info.output_key().to_inner()).unwrap();
+}
+
+#[test]
+fn overflowing_node_values_are_rejected() {
+    let secp = Secp256k1::new();
+    let owner = Keypair::from_seckey_slice(&secp, &[1; 32]).unwrap();
+    let payouts = vec![owner_payout(&secp, &owner, u64::MAX), owner_payout(&secp, &owner, 1)];
+    let err = build_musig_tree(&secp, &payouts, 2, 0, u64::MAX).unwrap_err();
+    assert!(matches!(err, Error::Policy(_)), "{err}");
+
+    // The outputs fit but the node fee on top of them does not
+    let payouts = vec![owner_payout(&secp, &owner, u64::MAX - 1), owner_payout(&secp, &owner, 1)];
+    let err = build_musig_tree(&secp, &payouts, 2, 1, u64::MAX).unwrap_err();
+    assert!(matches!(err, Error::Policy(_)), "{err}");
+}
+
+#[test]
+fn collapses_merge_every_leaf_an_owner_holds() {
+    let secp = Secp256k1::new();
+    let owners: Vec<Keypair> = (1..=2u8).map(|i| Keypair::from_seckey_slice(&secp, &[i; 32]).unwrap()).collect();
+    let payouts = vec![
+        owner_payout(&secp, &owners[0], 100_000),
+        owner_payout(&secp, &owners[1], 200_000),
+        owner_payout(&secp, &owners[0], 300_000),
+    ];
+    let tree = build_musig_tree(&secp, &payouts, 3, 500, 600_500).unwrap();
+    assert_eq!(tree.lock(&[]).unwrap().owners.len(), 2);
+
+    let prevout = OutPoint { txid: Txid::all_zeros(), vout: 0 };
+    let tx = build_collapse_tx(&tree, &[], prevout, &payouts, &[], 0).unwrap();
+    let values: Vec<u64> = tx.output.iter().map(|o| o.value.to_sat()).collect();
+    assert_eq!(values, vec![400_250, 200_250]);
+    assert_eq!(tx.output[0].script_pubkey, payouts[0].output.script_pubkey);
+}
@@ -0,0 +1,186 @@
+//! Value conservation in the tree and contract builders over random payouts:
+//! every node pays exactly its outputs plus fee, no output is dust, and bad
+//! inputs come back as errors rather than panics.
+
+use bitcoin::{
+    key::{Keypair, Secp256k1},
+    Amount, ScriptBuf, TxOut, XOnlyPublicKey,
+};
+use proptest::prelude::*;
+use scripts::{
+    builder::{build_dust_aware_tree, build_shaped_tree, DustHandling, DustPolicy, TreeShape},
+    contract::{build_ctv_contract, calculate_fee_with_anchor, Payouts},
+    ctv::{build_ctv_script, is_anchor},
+    error::Error,
+    mock::MockRpc,
+    tree::TreeNode,
+};
+
+/// Distinct P2TR-shaped scripts so every payout can be told apart.
+fn payouts(values: &[u64]) -> Vec<TxOut> {
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let mut script = vec![0x51, 0x20];
+            script.extend((i as u64).to_le_bytes());
+            script.resize(34, 0);
+            TxOut { value: Amount::from_sat(*value), script_pubkey: ScriptBuf::from_bytes(script) }
+        })
+        .collect()
+}
+
+fn is_dust(output: &TxOut) -> bool {
+    output.value < output.script_pubkey.dust_value()
+}
+
+/// Checks every node below `node` and returns its leaf outputs.
+fn check_tree(node: &TreeNode, node_fee: u64) -> Vec<TxOut> {
+    assert_eq!(node.input_value, node.output_value() + node_fee);
+    assert!(node.tx.output.iter().all(|o| !is_dust(o)));
+
+    let mut leaves: Vec<TxOut> = node.leaf_outputs().map(|(_, o)| o.clone()).collect();
+    for (vout, child) in &node.children {
+        let output = &node.tx.output[*vout as usize];
+        assert_eq!(output.value.to_sat(), child.input_value);
+        assert_eq!(output.script_pubkey, build_ctv_script(&child.tx.output));
+        leaves.extend(check_tree(child, node_fee));
+    }
+    leaves
+}
+
+fn sorted(mut outputs: Vec<TxOut>) -> Vec<TxOut> {
+    outputs.sort_by(|a, b| a.script_pubkey.cmp(&b.script_pubkey));
+    outputs
+}
+
+fn shape() -> impl Strategy<Value = TreeShape> {
+    prop_oneof![
+        Just(TreeShape::Balanced),
+        Just(TreeShape::ValueWeighted { large_shallow: true }),
+        Just(TreeShape::ValueWeighted { large_shallow: false }),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn trees_conserve_value(
+        values in prop::collection::vec(0u64..100_000_000, 1..40),
+        radix in 2usize..6,
+        node_fee in 0u64..2_000,
+        shape in shape(),
+    ) {
+        let payouts = payouts(&values);
+        match build_shaped_tree(&payouts, radix, node_fee, shape) {
+            Ok(root) => {
+                let leaves = check_tree(&root, node_fee);
+                prop_assert_eq!(sorted(leaves), sorted(payouts.clone()));
+                let nodes = root.transactions().len() as u64;
+                prop_assert_eq!(root.input_value, values.iter().sum::<u64>() + nodes * node_fee);
+            }
+            // Inner outputs are at least 1000 sat when every payout is
+            Err(err) => {
+                prop_assert!(values.iter().any(|v| *v < 1_000), "{}", err);
+                prop_assert!(matches!(err, Error::Policy(_)), "{}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn dust_aware_trees_account_for_every_payout(
+        values in prop::collection::vec(0u64..10_000_000, 1..30),
+        radix in 2usize..5,
+        node_fee in 0u64..1_000,
+        fee_rate in 0u64..200,
+        margin in 0u64..100_000,
+        promote in any::<bool>(),
+        shape in shape(),
+    ) {
+        let payouts = payouts(&values);
+        let pool_script = ScriptBuf::from_bytes([[0x51, 0x20].as_slice(), &[0xff; 32]].concat());
+        let handling = if promote {
+            DustHandling::Promote
+        } else {
+            DustHandling::CarryOver { pool_script: pool_script.clone() }
+        };
+        let policy = DustPolicy { fee_rate, margin, handling };
+
+        let tree = match build_dust_aware_tree(&payouts, radix, node_fee, shape, &policy) {
+            Ok(tree) => tree,
+            Err(err) => {
+                prop_assert!(matches!(err, Error::Policy(_) | Error::InvalidTemplate(_)), "{}", err);
+                return Ok(());
+            }
+        };
+        let leaves = check_tree(&tree.root, node_fee);
+        let (pool, paid): (Vec<TxOut>, Vec<TxOut>) =
+            leaves.into_iter().partition(|o| o.script_pubkey == pool_script);
+
+        let mut accounted = paid;
+        accounted.extend(tree.carried.iter().cloned());
+        prop_assert_eq!(sorted(accounted), sorted(payouts.clone()));
+        let carried: u64 = tree.carried.iter().map(|o| o.value.to_sat()).sum();
+        let pooled = pool.iter().map(|o| o.value.to_sat()).sum::<u64>();
+        if promote {
+            // Only payouts no node would relay are carried, and nothing holds them
+            prop_assert!(tree.carried.iter().all(|o| o.value < o.script_pubkey.dust_value()));
+            prop_assert_eq!(pooled, 0);
+        } else if Amount::from_sat(carried) < pool_script.dust_value() {
+            prop_assert_eq!(pooled, 0);
+        } else {
+            prop_assert_eq!(pooled, carried);
+        }
+    }
+}
+
+proptest! {
+    // Each case builds a few taproot trees, so fewer of them
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn flat_contracts_conserve_value(
+        input_value in 0u64..5_000_000_000,
+        count in 0usize..40,
+        fee_rate in 0u64..50,
+        include_anchor in any::<bool>(),
+    ) {
+        let rpc = MockRpc::new();
+        let secp = Secp256k1::new();
+        let keypair = Keypair::from_seckey_slice(&secp, &[7; 32]).unwrap();
+        let (xonly, _) = XOnlyPublicKey::from_keypair(&keypair);
+
+        let result = build_ctv_contract(
+            &secp,
+            xonly,
+            input_value,
+            fee_rate,
+            include_anchor,
+            &Payouts::NewAddresses(count),
+            None,
+            &rpc,
+        );
+        let spend_tx = match result {
+            Ok(contract) => contract.spend_tx,
+            Err(Error::InvalidTemplate(_)) => {
+                prop_assert_eq!(count, 0);
+                return Ok(());
+            }
+            Err(err) => {
+                prop_assert!(matches!(err, Error::Policy(_) | Error::InsufficientValue { .. }), "{}", err);
+                return Ok(());
+            }
+        };
+
+        let scripts: Vec<ScriptBuf> = spend_tx
+            .output
+            .iter()
+            .filter(|o| !is_anchor(&o.script_pubkey))
+            .map(|o| o.script_pubkey.clone())
+            .collect();
+        let fee = calculate_fee_with_anchor(&secp, xonly, fee_rate, &scripts, include_anchor, None).unwrap();
+        let output_value: u64 = spend_tx.output.iter().map(|o| o.value.to_sat()).sum();
+        prop_assert_eq!(output_value + fee, input_value);
+        prop_assert!(spend_tx.output.iter().all(|o| !is_dust(o)));
+        prop_assert_eq!(spend_tx.output.iter().filter(|o| is_anchor(&o.script_pubkey)).count(), usize::from(include_anchor));
+    }
+}
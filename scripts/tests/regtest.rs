@@ -0,0 +1,168 @@
+//! The mining scripts' flows against a real bitcoind, on regtest and on a
+//! custom signet. Needs a CTV-enabled bitcoind on `PATH`; run with
+//! `cargo test -p scripts --features bitcoind-tests`.
+
+#![cfg(feature = "bitcoind-tests")]
+
+mod common;
+
+use bitcoin::{
+    key::{Keypair, Secp256k1},
+    Amount, Network, Txid, XOnlyPublicKey,
+};
+use common::bitcoind::Bitcoind;
+use scripts::{
+    builder::build_ctv_tree,
+    chain::ChainSource,
+    contract::{broadcast_by_level, build_ctv_contract, build_leaf_outputs, CtvContract, Payouts},
+    flow::{layered_leaf_values, mine_coinbase_value, mine_ctv_contract, mine_ctv_tree},
+    rpc::NodeRpc,
+    signet::{SignetKey, SignetMiner},
+    tree::TreeNode,
+    watchtower::{tick, Event, FeeKey, Policy, WatchtowerState},
+};
+
+#[test]
+fn mine_and_send() {
+    let node = Bitcoind::start().unwrap();
+    let miner = node.wallet.new_address().unwrap();
+    node.wallet.generate_to_address(101, &miner).unwrap();
+
+    let recipient = node.wallet.new_address().unwrap();
+    let txid = bitcoincore_rpc::RpcApi::send_to_address(
+        &node.wallet,
+        &recipient,
+        Amount::from_btc(1.0).unwrap(),
+        None, None, None, None, None, None,
+    )
+    .unwrap();
+    node.wallet.generate_to_address(1, &miner).unwrap();
+
+    assert!(node.is_confirmed(&txid).unwrap());
+}
+
+#[test]
+fn mine_ctv_coinbase() {
+    let node = Bitcoind::start().unwrap();
+    let secp = Secp256k1::new();
+    let keypair = Keypair::new(&secp, &mut rand::thread_rng());
+    let (xonly, _) = XOnlyPublicKey::from_keypair(&keypair);
+    let spend_address = node.wallet.new_address().unwrap();
+
+    let coinbase_value = mine_coinbase_value(&node.wallet, &spend_address).unwrap();
+    let CtvContract { taproot_info, address: ctv_address, mut spend_tx, ctv_script, .. } = build_ctv_contract(
+        &secp,
+        xonly,
+        coinbase_value,
+        1,
+        true,
+        &Payouts::NewAddresses(5),
+        None,
+        &node.wallet,
+    )
+    .unwrap();
+
+    let coinbase_txid =
+        mine_ctv_contract(&node.wallet, &taproot_info, &ctv_address, &mut spend_tx, &ctv_script, &spend_address).unwrap();
+    let txid = node.wallet.send_raw_transaction(&spend_tx).unwrap();
+    node.wallet.generate_to_address(1, &spend_address).unwrap();
+
+    assert!(node.is_confirmed(&coinbase_txid).unwrap());
+    assert!(node.is_confirmed(&txid).unwrap());
+}
+
+#[test]
+fn mine_layered_ctv_coinbase() {
+    let node = Bitcoind::start().unwrap();
+    let secp = Secp256k1::new();
+    let keypair = Keypair::new(&secp, &mut rand::thread_rng());
+    let (xonly, _) = XOnlyPublicKey::from_keypair(&keypair);
+    let miner = node.wallet.new_address().unwrap();
+
+    let coinbase_value = mine_coinbase_value(&node.wallet, &miner).unwrap();
+    let leaf_outputs = build_leaf_outputs(&node.wallet, &layered_leaf_values(coinbase_value, 500).unwrap()).unwrap();
+    let mut root = build_ctv_tree(&leaf_outputs, 2, 500).unwrap();
+    root.input_value = coinbase_value;
+    mine_ctv_tree(&node.wallet, &secp, xonly, &mut root, &miner).unwrap();
+
+    let txids = broadcast_by_level(&node.wallet, &root, &miner).unwrap();
+    assert_eq!(txids.len(), 3);
+    for txid in txids {
+        assert!(node.is_confirmed(&txid).unwrap());
+    }
+}
+
+#[test]
+fn signet_blocks_are_accepted() {
+    let key = SignetKey::generate();
+    let node = Bitcoind::start_signet(&key.challenge()).unwrap();
+    let miner = SignetMiner { rpc: &node.wallet, key };
+    let address = miner.new_address().unwrap();
+
+    let hashes = miner.generate_to_address(2, &address).unwrap();
+
+    assert_eq!(bitcoincore_rpc::RpcApi::get_block_count(&node.client).unwrap(), 2);
+    assert_eq!(bitcoincore_rpc::RpcApi::get_best_block_hash(&node.client).unwrap(), hashes[1]);
+}
+
+#[test]
+fn watchtower_bumps_and_replaces_a_stuck_node() {
+    // Blocks leave out anything under 15 sat/vB, so the node and its first
+    // bump at 10 sat/vB stay in the mempool
+    let node = Bitcoind::start_with(&["-blockmintxfee=0.00015".to_string()]).unwrap();
+    let secp = Secp256k1::new();
+    let keypair = Keypair::new(&secp, &mut rand::thread_rng());
+    let (xonly, _) = XOnlyPublicKey::from_keypair(&keypair);
+    let miner = node.wallet.new_address().unwrap();
+    let policy = Policy::default();
+    let mine = |blocks| NodeRpc::generate_to_address(&node.wallet, blocks, &miner).unwrap();
+
+    let key = FeeKey::generate();
+    let mut state = WatchtowerState::default();
+    mine(101);
+    // Pays 25 sat/vB, over the block minimum
+    let funding: Txid = bitcoincore_rpc::RpcApi::call(
+        &node.wallet,
+        "sendtoaddress",
+        &[serde_json::json!(key.address(Network::Regtest)), 1.0.into(), ().into(), ().into(), false.into(), true.into(), ().into(), "unset".into(), ().into(), 25.into()],
+    )
+    .unwrap();
+    mine(1);
+    state.fund(&node.wallet.fetch_transaction(&funding).unwrap().unwrap(), &key).unwrap();
+
+    let coinbase_value = mine_coinbase_value(&node.wallet, &miner).unwrap();
+    let CtvContract { taproot_info, address: ctv_address, mut spend_tx, ctv_script, .. } = build_ctv_contract(
+        &secp,
+        xonly,
+        coinbase_value,
+        1,
+        true,
+        &Payouts::NewAddresses(5),
+        None,
+        &node.wallet,
+    )
+    .unwrap();
+    mine_ctv_contract(&node.wallet, &taproot_info, &ctv_address, &mut spend_tx, &ctv_script, &miner).unwrap();
+    let tree = TreeNode::new(spend_tx, coinbase_value);
+    let txid = tree.txid();
+    let mut pass = || tick(&node.wallet, &mut state, std::slice::from_ref(&tree), &key, &policy).unwrap();
+
+    assert_eq!(pass(), vec![Event::Broadcast { txid }]);
+    mine(policy.stuck_after);
+    let first = match pass().as_slice() {
+        [Event::Bumped { child, feerate: 10, .. }] => *child,
+        events => panic!("expected a bump at 10 sat/vB, got {events:?}"),
+    };
+
+    mine(policy.stuck_after);
+    assert!(!node.is_confirmed(&txid).unwrap(), "10 sat/vB is under the block minimum");
+    let second = match pass().as_slice() {
+        [Event::Bumped { child, feerate: 20, .. }] => *child,
+        events => panic!("expected a replacement at 20 sat/vB, got {events:?}"),
+    };
+    assert!(!bitcoincore_rpc::RpcApi::get_raw_mempool(&node.client).unwrap().contains(&first), "the replacement evicts the first bump");
+
+    mine(1);
+    assert!(node.is_confirmed(&txid).unwrap());
+    assert!(node.is_confirmed(&second).unwrap());
+}
@@ -0,0 +1,106 @@
+//! The bitcoind client against a stub JSON-RPC server: chain names newer than
+//! the `bitcoin` crate, and asking for the chain only once.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use bitcoin::Network;
+use bitcoincore_rpc::{Auth, Client};
+use scripts::rpc::{take_network_arg, Chain, NodeClient, NodeRpc};
+use serde_json::{json, Value};
+
+const ADDRESS: &str = "tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c";
+
+/// A node on `chain` answering `getblockchaininfo` and `getnewaddress`, and
+/// recording the method of every call.
+fn serve(chain: &'static str) -> (NodeClient, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let calls = Arc::new(Mutex::new(vec![]));
+    let recorded = Arc::clone(&calls);
+
+    thread::spawn(move || {
+        // The client keeps its connection alive, so answer every request on
+        // it until it hangs up rather than closing under its next request
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            'requests: loop {
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                        break 'requests;
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                let request: Value = serde_json::from_slice(&body).unwrap();
+                let method = request["method"].as_str().unwrap().to_string();
+                let result = match method.as_str() {
+                    "getblockchaininfo" => json!({ "chain": chain, "blocks": 0 }),
+                    "getnewaddress" => json!(ADDRESS),
+                    _ => Value::Null,
+                };
+                recorded.lock().unwrap().push(method);
+                let response = json!({ "result": result, "error": null, "id": request["id"] }).to_string();
+                write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{response}", response.len()).unwrap();
+            }
+        }
+    });
+
+    (NodeClient::new(Client::new(&url, Auth::None).unwrap()), calls)
+}
+
+#[test]
+fn testnet4_nodes_hand_out_testnet_addresses() {
+    let (node, _) = serve("testnet4");
+    assert_eq!(node.network().unwrap(), Network::Testnet);
+    assert_eq!(node.new_address().unwrap().to_string(), ADDRESS);
+}
+
+#[test]
+fn the_chain_is_asked_for_once() {
+    let (node, calls) = serve("signet");
+    for _ in 0..3 {
+        node.new_address().unwrap();
+    }
+    assert_eq!(node.network().unwrap(), Network::Signet);
+    let calls = calls.lock().unwrap();
+    assert_eq!(calls.iter().filter(|method| *method == "getblockchaininfo").count(), 1);
+    assert_eq!(calls.iter().filter(|method| *method == "getnewaddress").count(), 3);
+}
+
+#[test]
+fn every_chain_bitcoind_reports_parses() {
+    for (name, chain) in [
+        ("main", Chain::Bitcoin),
+        ("test", Chain::Testnet),
+        ("testnet4", Chain::Testnet4),
+        ("signet", Chain::Signet),
+        ("regtest", Chain::Regtest),
+    ] {
+        assert_eq!(name.parse::<Chain>().unwrap(), chain);
+    }
+    assert!("testnet5".parse::<Chain>().is_err());
+}
+
+#[test]
+fn the_network_flag_is_taken_out_of_the_arguments() {
+    let mut args: Vec<String> = ["parse_witness", "--network", "signet", "abcd", "0"].map(String::from).into();
+    assert_eq!(take_network_arg(&mut args).unwrap(), Some(Chain::Signet));
+    assert_eq!(args, ["parse_witness", "abcd", "0"]);
+    assert_eq!(take_network_arg(&mut args).unwrap(), None);
+
+    assert!(take_network_arg(&mut vec!["x".into(), "--network".into()]).is_err());
+    assert!(take_network_arg(&mut vec!["--network".into(), "testnet5".into()]).is_err());
+}
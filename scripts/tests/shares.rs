@@ -0,0 +1,154 @@
+//! The share ledger and the payout schemes: PPLNS and TIDES window edges,
+//! FPPS accrual since the last payout, and where the rounding dust goes.
+
+use bitcoin::{Address, Network};
+use scripts::{
+    error::Error,
+    shares::{
+        append_share, load_shares, parse_scheme, pplns_manifest, Fpps, ManifestEntry, PayoutManifest, PayoutScheme, Pplns, Share,
+        Tides,
+    },
+};
+
+fn share(address: &str, difficulty: u64, timestamp: u64) -> Share {
+    Share { address: address.to_string(), difficulty, timestamp }
+}
+
+/// (address, weight, value) of every manifest entry.
+fn entries(manifest: &PayoutManifest) -> Vec<(&str, u64, u64)> {
+    manifest.entries.iter().map(|e| (e.address.as_str(), e.weight, e.value)).collect()
+}
+
+#[test]
+fn ledger_round_trips_in_append_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data").join("shares.jsonl");
+    assert!(load_shares(&path).unwrap().is_empty());
+
+    let shares = vec![share("a", 10, 1), share("b", 20, 2), share("a", 30, 3)];
+    for share in &shares {
+        append_share(&path, share).unwrap();
+    }
+    assert_eq!(load_shares(&path).unwrap(), shares);
+}
+
+#[test]
+fn pplns_splits_by_difficulty_in_the_window() {
+    let shares = vec![share("a", 100, 1), share("b", 300, 2), share("a", 100, 3)];
+    let manifest = pplns_manifest(&shares, 500, 1_000_000).unwrap();
+    // Newest first: a's latest share comes before b's
+    assert_eq!(entries(&manifest), vec![("a", 200, 400_000), ("b", 300, 600_000)]);
+    assert_eq!(manifest.total(), 1_000_000);
+    assert_eq!(manifest.retained, 0);
+}
+
+#[test]
+fn pplns_counts_only_the_part_of_the_oldest_share_that_fits() {
+    let shares = vec![share("old", 1_000, 1), share("a", 100, 2), share("b", 100, 3)];
+    // 50 of the oldest share's 1000 difficulty fit in the window
+    let manifest = pplns_manifest(&shares, 250, 250_000).unwrap();
+    assert_eq!(entries(&manifest), vec![("b", 100, 100_000), ("a", 100, 100_000), ("old", 50, 50_000)]);
+
+    // A window ending exactly on a share boundary leaves older shares out
+    let manifest = pplns_manifest(&shares, 200, 250_000).unwrap();
+    assert_eq!(entries(&manifest), vec![("b", 100, 125_000), ("a", 100, 125_000)]);
+}
+
+#[test]
+fn pplns_windows_wider_than_the_ledger_pay_every_share() {
+    let shares = vec![share("a", 10, 1), share("b", 30, 2)];
+    let manifest = pplns_manifest(&shares, 1_000_000, 400).unwrap();
+    assert_eq!(entries(&manifest), vec![("b", 30, 300), ("a", 10, 100)]);
+}
+
+#[test]
+fn pplns_rounding_dust_goes_to_the_heaviest_miner() {
+    let shares = vec![share("a", 1, 1), share("b", 1, 2), share("c", 2, 3)];
+    let manifest = pplns_manifest(&shares, 4, 1_001).unwrap();
+    assert_eq!(entries(&manifest), vec![("c", 2, 501), ("b", 1, 250), ("a", 1, 250)]);
+    assert_eq!(manifest.total(), 1_001);
+}
+
+#[test]
+fn pplns_needs_shares_in_the_window() {
+    assert!(matches!(pplns_manifest(&[], 100, 1_000), Err(Error::Payout(_))));
+    assert!(matches!(pplns_manifest(&[share("a", 10, 1)], 0, 1_000), Err(Error::Payout(_))));
+    assert!(matches!(pplns_manifest(&[share("a", 0, 1)], 100, 1_000), Err(Error::Payout(_))));
+}
+
+#[test]
+fn manifests_become_outputs_on_their_network() {
+    let address = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+    let shares = vec![share(address, 10, 1)];
+    let manifest = pplns_manifest(&shares, 10, 5_000).unwrap();
+
+    let outputs = manifest.to_outputs(Network::Regtest).unwrap();
+    let expected = address.parse::<Address<_>>().unwrap().assume_checked().script_pubkey();
+    assert_eq!(outputs.len(), 1);
+    assert_eq!(outputs[0].value.to_sat(), 5_000);
+    assert_eq!(outputs[0].script_pubkey, expected);
+    assert!(manifest.to_outputs(Network::Bitcoin).is_err());
+}
+
+#[test]
+fn tides_windows_are_a_multiple_of_network_difficulty() {
+    let shares = vec![share("old", 100, 1), share("a", 300, 2), share("b", 500, 3)];
+    let tides = Tides { network_difficulty: 100, multiplier: 8 };
+    let manifest = tides.manifest(&shares, 800_000).unwrap();
+    assert_eq!(entries(&manifest), vec![("b", 500, 500_000), ("a", 300, 300_000)]);
+    assert_eq!(manifest, Pplns { window: 800 }.manifest(&shares, 800_000).unwrap());
+
+    // Harder network, longer window
+    let tides = Tides { network_difficulty: 200, multiplier: 8 };
+    assert_eq!(tides.manifest(&shares, 900_000).unwrap().entries.len(), 3);
+
+    let overflowing = Tides { network_difficulty: u64::MAX, multiplier: 8 };
+    assert_eq!(overflowing.manifest(&shares, 900).unwrap().total(), 900);
+}
+
+#[test]
+fn fpps_pays_shares_since_the_last_payout_their_expected_value() {
+    let shares = vec![share("paid", 500, 10), share("a", 100, 11), share("b", 300, 12)];
+    let fpps = Fpps { network_difficulty: 1_000, since: 10 };
+    let manifest = fpps.manifest(&shares, 1_000_000).unwrap();
+    // 400 of 1000 network difficulty earns 40% of the reward
+    assert_eq!(entries(&manifest), vec![("a", 100, 100_000), ("b", 300, 300_000)]);
+    assert_eq!(manifest.retained, 600_000);
+    assert_eq!(manifest.total() + manifest.retained, 1_000_000);
+}
+
+#[test]
+fn fpps_scales_down_when_owed_more_than_the_block_pays() {
+    let shares = vec![share("a", 1_000, 1), share("b", 3_000, 2)];
+    let fpps = Fpps { network_difficulty: 1_000, since: 0 };
+    let manifest = fpps.manifest(&shares, 1_000_001).unwrap();
+    assert_eq!(entries(&manifest), vec![("a", 1_000, 250_000), ("b", 3_000, 750_001)]);
+    assert_eq!(manifest.retained, 0);
+}
+
+#[test]
+fn fpps_needs_shares_and_a_network_difficulty() {
+    let shares = vec![share("a", 100, 5)];
+    // Every share already paid, and an empty ledger
+    assert!(matches!(Fpps { network_difficulty: 1_000, since: 5 }.manifest(&shares, 1_000), Err(Error::Payout(_))));
+    assert!(matches!(Fpps { network_difficulty: 1_000, since: 0 }.manifest(&[], 1_000), Err(Error::Payout(_))));
+    assert!(matches!(Fpps { network_difficulty: 0, since: 0 }.manifest(&shares, 1_000), Err(Error::Payout(_))));
+}
+
+#[test]
+fn schemes_parse_from_their_specs() {
+    let shares = vec![share("a", 100, 1), share("b", 100, 2)];
+    assert_eq!(parse_scheme("pplns:100").unwrap().name(), "PPLNS");
+    assert_eq!(
+        parse_scheme("tides:10").unwrap().manifest(&shares, 1_000).unwrap(),
+        Tides { network_difficulty: 10, multiplier: Tides::DEFAULT_MULTIPLIER }.manifest(&shares, 1_000).unwrap()
+    );
+    assert_eq!(
+        parse_scheme("fpps:1000:1").unwrap().manifest(&shares, 1_000).unwrap().entries,
+        vec![ManifestEntry { address: "b".into(), weight: 100, value: 100 }]
+    );
+
+    assert!(matches!(parse_scheme("pplns"), Err(Error::Parse(_))));
+    assert!(matches!(parse_scheme("pps:100"), Err(Error::Parse(_))));
+    assert!(parse_scheme("tides:ten").is_err());
+}
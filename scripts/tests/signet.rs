@@ -0,0 +1,227 @@
+//! Signet blocks built and signed from a template: the solution in the
+//! witness commitment must verify against the challenge the way bitcoind
+//! checks it (BIP-325), and must stop verifying when the block changes.
+
+use bitcoin::{
+    consensus::encode::{deserialize, deserialize_partial, serialize_hex},
+    ecdsa,
+    hashes::Hash,
+    key::Secp256k1,
+    secp256k1::{Message, PublicKey},
+    sighash::{EcdsaSighashType, SighashCache},
+    transaction::Version,
+    absolute::LockTime,
+    script::{Builder, Instruction},
+    secp256k1::SecretKey,
+    Amount, Block, BlockHash, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, WPubkeyHash, Witness,
+};
+use scripts::signet::{block_solution, build_block, grind, sign_block, BlockTemplate, SignetKey, TemplateTransaction};
+
+/// Marks the solution push in the witness commitment (BIP-325).
+const SIGNET_HEADER: [u8; 4] = [0xec, 0xc7, 0xda, 0xa2];
+
+/// A segwit spend, so the witness commitment covers something.
+fn segwit_tx() -> Transaction {
+    Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint { txid: Hash::from_byte_array([0x11; 32]), vout: 0 },
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::from_slice(&[[0x22; 64]]),
+        }],
+        output: vec![TxOut { value: Amount::from_sat(40_000), script_pubkey: ScriptBuf::from_bytes(vec![0x51, 0x20, 0x33]) }],
+    }
+}
+
+fn template(key: &SignetKey) -> BlockTemplate {
+    BlockTemplate {
+        version: 0x2000_0000,
+        previous_block_hash: BlockHash::from_byte_array([0x44; 32]),
+        transactions: vec![TemplateTransaction { data: serialize_hex(&segwit_tx()) }],
+        coinbase_value: 5_000_010_000,
+        // Regtest's limit, so grinding takes a couple of hashes
+        bits: "207fffff".into(),
+        height: 300,
+        current_time: 1_700_000_000,
+        signet_challenge: key.challenge(),
+    }
+}
+
+fn payout() -> ScriptBuf {
+    ScriptBuf::from_bytes([[0x51, 0x20].as_slice(), &[0x55; 32]].concat())
+}
+
+/// Checks the block's solution against a P2WPKH `challenge` the way BIP-325
+/// describes, rebuilding `to_spend` and `to_sign` here rather than trusting
+/// the ones the signer used.
+fn solution_verifies(block: &Block, challenge: &ScriptBuf) -> bool {
+    // Cut the solution out of the witness commitment, leaving the bare header push
+    let mut unsolved = block.clone();
+    let coinbase = &mut unsolved.txdata[0];
+    let index = coinbase
+        .output
+        .iter()
+        .rposition(|output| output.script_pubkey.as_bytes().starts_with(&[0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed]))
+        .unwrap();
+    let mut solution = None;
+    let mut script = Builder::new();
+    for instruction in coinbase.output[index].script_pubkey.instructions() {
+        script = match instruction.unwrap() {
+            Instruction::PushBytes(push) if solution.is_none() && push.len() > 4 && push.as_bytes()[..4] == SIGNET_HEADER => {
+                solution = Some(push.as_bytes()[4..].to_vec());
+                script.push_slice(SIGNET_HEADER)
+            }
+            Instruction::PushBytes(push) => script.push_slice(push),
+            Instruction::Op(op) => script.push_opcode(op),
+        };
+    }
+    let Some(solution) = solution else {
+        return false;
+    };
+    coinbase.output[index].script_pubkey = script.into_script();
+
+    // to_spend: OP_0 and a push of version, previous hash, unsolved merkle root and time
+    let mut block_data = vec![];
+    block_data.extend(block.header.version.to_consensus().to_le_bytes());
+    block_data.extend(block.header.prev_blockhash.to_byte_array());
+    block_data.extend(unsolved.compute_merkle_root().unwrap().to_byte_array());
+    block_data.extend(block.header.time.to_le_bytes());
+    let to_spend = Transaction {
+        version: Version(0),
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint { txid: Txid::all_zeros(), vout: u32::MAX },
+            script_sig: ScriptBuf::from_bytes([[0x00, 72].as_slice(), &block_data].concat()),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut { value: Amount::ZERO, script_pubkey: challenge.clone() }],
+    };
+
+    // to_sign spends it with the solution's scriptSig and witness
+    let (script_sig, read): (ScriptBuf, usize) = deserialize_partial(&solution).unwrap();
+    let witness: Witness = deserialize(&solution[read..]).unwrap();
+    assert!(script_sig.is_empty());
+    let to_sign = Transaction {
+        version: Version(0),
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint { txid: to_spend.txid(), vout: 0 },
+            script_sig,
+            sequence: Sequence::ZERO,
+            witness: witness.clone(),
+        }],
+        output: vec![TxOut { value: Amount::ZERO, script_pubkey: ScriptBuf::from_bytes(vec![0x6a]) }],
+    };
+
+    let (Some(sig), Some(pubkey), None) = (witness.nth(0), witness.nth(1), witness.nth(2)) else {
+        return false;
+    };
+    if ScriptBuf::new_p2wpkh(&WPubkeyHash::hash(pubkey)) != *challenge {
+        return false;
+    }
+    let sighash = SighashCache::new(&to_sign)
+        .p2wpkh_signature_hash(0, challenge, Amount::ZERO, EcdsaSighashType::All)
+        .unwrap();
+    let sig = ecdsa::Signature::from_slice(sig).unwrap();
+    Secp256k1::verification_only()
+        .verify_ecdsa(&Message::from_digest(sighash.to_byte_array()), &sig.sig, &PublicKey::from_slice(pubkey).unwrap())
+        .is_ok()
+}
+
+#[test]
+fn signed_blocks_carry_a_valid_solution() {
+    let key = SignetKey::generate();
+    let mut block = build_block(&template(&key), &payout()).unwrap();
+    assert!(block_solution(&block).unwrap().is_none());
+
+    sign_block(&mut block, &key).unwrap();
+    grind(&mut block.header);
+
+    assert!(solution_verifies(&block, &key.challenge()));
+    assert!(block.check_merkle_root());
+    assert!(block.check_witness_commitment());
+    assert!(block.header.validate_pow(block.header.target()).is_ok());
+    assert_eq!(block.bip34_block_height().unwrap(), 300);
+    assert_eq!(block.txdata[0].output[0].value.to_sat(), 5_000_010_000);
+}
+
+#[test]
+fn changing_the_block_breaks_the_solution() {
+    let key = SignetKey::generate();
+    let mut block = build_block(&template(&key), &payout()).unwrap();
+    sign_block(&mut block, &key).unwrap();
+
+    let mut repaid = block.clone();
+    repaid.txdata[0].output[0].script_pubkey = ScriptBuf::from_bytes(vec![0x51]);
+    assert!(!solution_verifies(&repaid, &key.challenge()));
+
+    let mut retimed = block.clone();
+    retimed.header.time += 1;
+    assert!(!solution_verifies(&retimed, &key.challenge()));
+
+    assert!(!solution_verifies(&block, &SignetKey::generate().challenge()));
+}
+
+#[test]
+fn resigning_replaces_the_solution() {
+    let key = SignetKey::generate();
+    let mut block = build_block(&template(&key), &payout()).unwrap();
+    sign_block(&mut block, &key).unwrap();
+    block.header.time += 600;
+    sign_block(&mut block, &key).unwrap();
+
+    assert!(solution_verifies(&block, &key.challenge()));
+    let commitment = block.txdata[0].output.last().unwrap();
+    let pushes = commitment.script_pubkey.instructions().count();
+    assert_eq!(pushes, 3, "OP_RETURN, the witness commitment and one signet push");
+}
+
+#[test]
+fn templates_parse_from_getblocktemplate() {
+    let key = SignetKey::generate();
+    let json = serde_json::json!({
+        "version": 536870912,
+        "rules": ["csv", "!segwit", "!signet", "taproot", "checktemplateverify"],
+        "previousblockhash": "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6",
+        "transactions": [],
+        "coinbasevalue": 5000000000u64,
+        "longpollid": "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef61",
+        "target": "00000377ae000000000000000000000000000000000000000000000000000000",
+        "mintime": 1598918401,
+        "bits": "1e0377ae",
+        "height": 1,
+        "curtime": 1700000000,
+        "signet_challenge": key.challenge().to_hex_string(),
+        "default_witness_commitment": "6a24aa21a9ede2f61c3f71d1defd3fa999dfa36953755c690689799962b48bebd836974e8cf9",
+    });
+    let template: BlockTemplate = serde_json::from_value(json).unwrap();
+    assert_eq!(template.signet_challenge, key.challenge());
+
+    let block = build_block(&template, &payout()).unwrap();
+    let commitment = block.txdata[0].output.last().unwrap();
+    assert_eq!(commitment.script_pubkey.to_hex_string(), "6a24aa21a9ede2f61c3f71d1defd3fa999dfa36953755c690689799962b48bebd836974e8cf9");
+}
+
+/// A fixed key's signature over the fixed template. ECDSA nonces come from
+/// RFC 6979, so the solution never changes unless what is signed does.
+#[test]
+fn fixed_key_blocks_match_their_vector() {
+    let key = SignetKey::from(SecretKey::from_slice(&[0x42; 32]).unwrap());
+    assert_eq!(key.challenge().to_hex_string(), "001414db4138d56a2ecfb10881a9be394d9f321985b2");
+    let mut block = build_block(&template(&key), &payout()).unwrap();
+    sign_block(&mut block, &key).unwrap();
+
+    let commitment = &block.txdata[0].output.last().unwrap().script_pubkey;
+    assert_eq!(
+        commitment.to_hex_string(),
+        "6a24aa21a9eddf861f2422e3ac3776153bb1e263cbd271946df073bed7c11e3108a48e0b96a2\
+         4c70ecc7daa20002473044022072b8651492e7211198448dbbe183a6228aaf2c098f2adf731c\
+         0c2b9fe04d9068022046fa8b1f30595359421fe3ffdc692f6049c4ae88ab010f1d2f1641b708\
+         386f5101210324653eac434488002cc06bbfb7f10fe18991e35f9fe4302dbea6d2353dc0ab1c"
+    );
+    assert_eq!(block.header.merkle_root.to_string(), "5e039511897b3e5d7bc01c991615afdfc6f3dcdd9cdae9c284ec510ba5050f3b");
+    assert!(solution_verifies(&block, &key.challenge()));
+}
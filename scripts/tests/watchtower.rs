@@ -0,0 +1,300 @@
+//! The watchtower unrolling trees on the in-memory chain: maturity, level by
+//! level broadcasts, anchor bumps and their replacements, and state that
+//! survives a restart, even one that lost the last bump.
+
+use bitcoin::{
+    key::{Keypair, Secp256k1},
+    taproot::{LeafVersion, TaprootBuilder},
+    Address, Network, OutPoint, Txid, XOnlyPublicKey,
+};
+use scripts::{
+    builder::build_ctv_tree,
+    chain::ChainSource,
+    contract::{build_ctv_contract, build_leaf_outputs, finalize_ctv_spend, CtvContract, Payouts},
+    ctv::build_ctv_script,
+    mock::MockRpc,
+    rpc::NodeRpc,
+    tree::TreeNode,
+    watchtower::{cpfp_child, tick, Event, FeeKey, FeeUtxo, Policy, WatchtowerState},
+};
+
+struct Setup {
+    rpc: MockRpc,
+    miner: Address,
+    key: FeeKey,
+    state: WatchtowerState,
+    tree: TreeNode,
+    coinbase: Txid,
+}
+
+impl Setup {
+    /// A flat CTV spend with an anchor whose coinbase was just mined, and a
+    /// funded fee key.
+    fn flat() -> Setup {
+        let rpc = MockRpc::new();
+        let secp = Secp256k1::new();
+        let (xonly, _) = XOnlyPublicKey::from_keypair(&Keypair::from_seckey_slice(&secp, &[7; 32]).unwrap());
+        let miner = rpc.new_address().unwrap();
+        let key = FeeKey::generate();
+
+        let block = rpc.generate_to_address(1, &key.address(Network::Regtest)).unwrap()[0];
+        let funding = rpc.get_block(&block).unwrap().txdata[0].clone();
+        let coinbase_value = funding.output[0].value.to_sat();
+        let mut state = WatchtowerState::default();
+        state.fund(&funding, &key).unwrap();
+
+        let CtvContract { taproot_info, address: ctv_address, mut spend_tx, ctv_script, .. } = build_ctv_contract(
+            &secp,
+            xonly,
+            coinbase_value,
+            1,
+            true,
+            &Payouts::NewAddresses(5),
+            None,
+            &rpc,
+        )
+        .unwrap();
+        let block = rpc.generate_to_address(1, &ctv_address).unwrap()[0];
+        let coinbase = rpc.get_block(&block).unwrap().txdata[0].txid();
+        finalize_ctv_spend(&mut spend_tx, coinbase, &taproot_info, &ctv_script).unwrap();
+        let tree = TreeNode::new(spend_tx, coinbase_value);
+
+        Setup { rpc, miner, key, state, tree, coinbase }
+    }
+
+    fn tick(&mut self, policy: &Policy) -> Vec<Event> {
+        tick(&self.rpc, &mut self.state, std::slice::from_ref(&self.tree), &self.key, policy).unwrap()
+    }
+
+    fn mine(&self, blocks: u64) {
+        self.rpc.generate_to_address(blocks, &self.miner).unwrap();
+    }
+}
+
+#[test]
+fn waits_for_maturity_then_unrolls() {
+    let mut setup = Setup::flat();
+    let policy = Policy::default();
+    let coinbase = setup.coinbase;
+    let txid = setup.tree.txid();
+
+    assert_eq!(setup.tick(&policy), vec![Event::Maturing { coinbase, blocks_left: 99 }]);
+    setup.mine(98);
+    assert_eq!(setup.tick(&policy), vec![Event::Maturing { coinbase, blocks_left: 1 }]);
+
+    setup.mine(1);
+    assert_eq!(setup.tick(&policy), vec![Event::Broadcast { txid }]);
+    assert_eq!(setup.rpc.mempool(), vec![txid]);
+    assert_eq!(setup.tick(&policy), vec![], "in the mempool and not stuck yet");
+
+    setup.mine(1);
+    let height = setup.rpc.tip_height().unwrap();
+    assert_eq!(setup.tick(&policy), vec![Event::Confirmed { txid, height }, Event::Unrolled { coinbase }]);
+    assert_eq!(setup.tick(&policy), vec![]);
+}
+
+#[test]
+fn layered_trees_unroll_a_level_per_block() {
+    let rpc = MockRpc::new();
+    let secp = Secp256k1::new();
+    let (xonly, _) = XOnlyPublicKey::from_keypair(&Keypair::from_seckey_slice(&secp, &[7; 32]).unwrap());
+    let miner = rpc.new_address().unwrap();
+
+    let leaf_outputs = build_leaf_outputs(&rpc, &[400_000_000; 7]).unwrap();
+    let mut root = build_ctv_tree(&leaf_outputs, 2, 500).unwrap();
+    let root_script = build_ctv_script(&root.tx.output);
+    let taproot = TaprootBuilder::new().add_leaf(0, root_script.clone()).unwrap().finalize(&secp, xonly).unwrap();
+    let block = rpc.generate_to_address(1, &Address::p2tr_tweaked(taproot.output_key(), Network::Regtest)).unwrap()[0];
+    let coinbase = rpc.get_block(&block).unwrap().txdata[0].txid();
+    rpc.generate_to_address(100, &miner).unwrap();
+
+    let control = taproot.control_block(&(root_script.clone(), LeafVersion::TapScript)).unwrap();
+    root.tx.input[0].witness.push(root_script.to_bytes());
+    root.tx.input[0].witness.push(control.serialize());
+    root.bind(OutPoint { txid: coinbase, vout: 0 });
+
+    let mut state = WatchtowerState::default();
+    let key = FeeKey::generate();
+    let policy = Policy::default();
+    let trees = [root.clone()];
+
+    let mut broadcasts = vec![];
+    for _ in 0..10 {
+        let events = tick(&rpc, &mut state, &trees, &key, &policy).unwrap();
+        if events.contains(&Event::Unrolled { coinbase }) {
+            break;
+        }
+        let level: Vec<Txid> = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Broadcast { txid } => Some(*txid),
+                _ => None,
+            })
+            .collect();
+        assert!(events.iter().all(|event| !matches!(event, Event::Rejected { .. })), "{events:?}");
+        broadcasts.push(level);
+        rpc.generate_to_address(1, &miner).unwrap();
+    }
+
+    // Seven leaves in pairs: the root, its two children, then four nodes
+    let sizes: Vec<usize> = broadcasts.iter().map(Vec::len).collect();
+    assert_eq!(sizes, vec![1, 2, 4]);
+    assert!(broadcasts.concat().iter().all(|txid| rpc.is_confirmed(txid)));
+    assert!(state.trees[&coinbase].unrolled);
+}
+
+#[test]
+fn stuck_nodes_are_bumped_through_their_anchor() {
+    let mut setup = Setup::flat();
+    let policy = Policy::default();
+    let txid = setup.tree.txid();
+    setup.mine(99);
+    setup.rpc.set_min_block_feerate(5);
+
+    assert_eq!(setup.tick(&policy), vec![Event::Broadcast { txid }]);
+    setup.mine(policy.stuck_after - 1);
+    assert_eq!(setup.tick(&policy), vec![], "one block short of stuck");
+    assert!(!setup.rpc.is_confirmed(&txid));
+
+    setup.mine(1);
+    let events = setup.tick(&policy);
+    let Some(Event::Bumped { child, feerate, .. }) = events.first().cloned() else {
+        panic!("expected a bump, got {events:?}");
+    };
+    assert_eq!(feerate, policy.bump_feerate);
+    let fee_input = setup.state.trees[&setup.coinbase].nodes[&txid].bump.as_ref().unwrap().fee_input;
+    let bump = cpfp_child(&setup.tree.tx, setup.tree.fee(), fee_input, feerate, &setup.key).unwrap();
+    assert_eq!(bump.txid(), child);
+
+    setup.mine(1);
+    assert!(setup.rpc.is_confirmed(&txid));
+    assert!(setup.rpc.is_confirmed(&child));
+    let fee_utxo = setup.state.fee_utxo.unwrap();
+    assert_eq!(fee_utxo.outpoint, OutPoint { txid: child, vout: 0 });
+    assert_eq!(setup.rpc.get_tx_out(&fee_utxo.outpoint).map(|o| o.value.to_sat()), Some(fee_utxo.value));
+}
+
+#[test]
+fn bumps_that_stay_stuck_are_replaced_at_double_the_feerate() {
+    let mut setup = Setup::flat();
+    let policy = Policy::default();
+    let txid = setup.tree.txid();
+    setup.mine(99);
+    setup.rpc.set_min_block_feerate(15);
+
+    setup.tick(&policy);
+    setup.mine(policy.stuck_after);
+    let first = match setup.tick(&policy).as_slice() {
+        [Event::Bumped { child, feerate: 10, .. }] => *child,
+        events => panic!("expected a bump at 10 sat/vB, got {events:?}"),
+    };
+
+    setup.mine(policy.stuck_after);
+    let second = match setup.tick(&policy).as_slice() {
+        [Event::Bumped { child, feerate: 20, .. }] => *child,
+        events => panic!("expected a bump at 20 sat/vB, got {events:?}"),
+    };
+    assert!(!setup.rpc.mempool().contains(&first), "the replacement evicts the first bump");
+
+    setup.mine(1);
+    assert!(setup.rpc.is_confirmed(&txid));
+    assert!(setup.rpc.is_confirmed(&second));
+}
+
+#[test]
+fn unfunded_watchtowers_say_so() {
+    let mut setup = Setup::flat();
+    setup.state.fee_utxo = None;
+    let policy = Policy::default();
+    setup.mine(99);
+    setup.rpc.set_min_block_feerate(5);
+
+    setup.tick(&policy);
+    setup.mine(policy.stuck_after);
+    let txid = setup.tree.txid();
+    assert_eq!(
+        setup.tick(&policy),
+        vec![Event::CannotBump { txid, reason: "no fee output, fund the watchtower".into() }]
+    );
+}
+
+#[test]
+fn bumps_that_cannot_pay_their_fee_fail() {
+    let setup = Setup::flat();
+    let fee_input = FeeUtxo { outpoint: setup.state.fee_utxo.unwrap().outpoint, value: 1_000 };
+    assert!(cpfp_child(&setup.tree.tx, setup.tree.fee(), fee_input, 100, &setup.key).is_err());
+}
+
+#[test]
+fn state_survives_a_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("watchtower.json");
+    let key_path = dir.path().join("watchtower_key.json");
+
+    let mut setup = Setup::flat();
+    let policy = Policy::default();
+    let txid = setup.tree.txid();
+    setup.mine(99);
+    setup.rpc.set_min_block_feerate(5);
+    setup.tick(&policy);
+    setup.state.save(&path).unwrap();
+    setup.key.save(&key_path).unwrap();
+
+    // A new process: nothing is broadcast twice and the bump comes on schedule
+    setup.state = WatchtowerState::load(&path).unwrap();
+    setup.key = FeeKey::load(&key_path).unwrap();
+    assert_eq!(setup.state.trees[&setup.coinbase].nodes[&txid].broadcast_at, Some(setup.rpc.tip_height().unwrap()));
+    setup.mine(policy.stuck_after);
+    assert!(matches!(setup.tick(&policy).as_slice(), [Event::Bumped { .. }]));
+
+    setup.state.save(&path).unwrap();
+    let reloaded = WatchtowerState::load(&path).unwrap();
+    assert_eq!(reloaded.fee_utxo, setup.state.fee_utxo);
+    assert!(WatchtowerState::load(&dir.path().join("missing.json")).unwrap().trees.is_empty());
+}
+
+#[test]
+fn bumps_broadcast_after_the_last_save_are_found_again() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("watchtower.json");
+
+    let mut setup = Setup::flat();
+    let policy = Policy::default();
+    let txid = setup.tree.txid();
+    let funding = setup.state.fee_utxo.unwrap();
+    setup.mine(99);
+    setup.rpc.set_min_block_feerate(15);
+    setup.tick(&policy);
+    setup.mine(policy.stuck_after);
+    setup.state.save(&path).unwrap();
+
+    // Killed after broadcasting the bump, before saving it
+    let first = match setup.tick(&policy).as_slice() {
+        [Event::Bumped { child, .. }] => *child,
+        events => panic!("expected a bump, got {events:?}"),
+    };
+    setup.state = WatchtowerState::load(&path).unwrap();
+    let trees = std::slice::from_ref(&setup.tree);
+    assert_eq!(setup.state.reconcile(&setup.rpc, trees, &setup.key).unwrap(), vec![Event::Recovered { txid, child: first }]);
+    assert_eq!(setup.state.fee_utxo.unwrap().outpoint, OutPoint { txid: first, vout: 0 });
+    let bump = setup.state.trees[&setup.coinbase].nodes[&txid].bump.as_ref().unwrap();
+    assert_eq!((bump.child, bump.fee_input, bump.feerate), (first, funding, policy.bump_feerate));
+    assert!(setup.state.reconcile(&setup.rpc, trees, &setup.key).unwrap().is_empty(), "nothing left to find");
+    setup.state.save(&path).unwrap();
+
+    // And again after its replacement
+    setup.mine(policy.stuck_after);
+    let second = match setup.tick(&policy).as_slice() {
+        [Event::Bumped { child, feerate: 20, .. }] => *child,
+        events => panic!("expected a replacement at 20 sat/vB, got {events:?}"),
+    };
+    setup.state = WatchtowerState::load(&path).unwrap();
+    let trees = std::slice::from_ref(&setup.tree);
+    assert_eq!(setup.state.reconcile(&setup.rpc, trees, &setup.key).unwrap(), vec![Event::Recovered { txid, child: second }]);
+    assert_eq!(setup.state.fee_utxo.unwrap().outpoint, OutPoint { txid: second, vout: 0 });
+    assert_eq!(setup.state.trees[&setup.coinbase].nodes[&txid].bump.as_ref().unwrap().feerate, 20);
+
+    setup.mine(1);
+    assert!(setup.rpc.is_confirmed(&txid));
+    assert!(setup.rpc.is_confirmed(&second));
+}